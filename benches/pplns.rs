@@ -0,0 +1,61 @@
+#[macro_use]
+extern crate criterion;
+
+#[allow(dead_code)]
+#[path = "../src/payout_model.rs"]
+mod payout_model;
+
+use std::collections::{HashMap, VecDeque};
+
+use criterion::{BenchmarkId, Criterion};
+use payout_model::{PayoutModel, Share, PPLNS};
+
+const PROVERS: usize = 1000;
+
+fn share(i: usize) -> Share {
+    Share::init(1 + (i % 7) as u64, format!("aleo1prover{}", i % PROVERS))
+}
+
+/// Round shares the way they were read before the running totals, by going over the whole queue.
+fn rescan(queue: &VecDeque<Share>) -> HashMap<String, u64> {
+    let mut shares = HashMap::new();
+    for share in queue {
+        *shares.entry(share.owner().to_string()).or_default() += share.value();
+    }
+    shares
+}
+
+fn pplns_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pplns");
+    for window in [10_000usize, 100_000, 1_000_000] {
+        let n = window as u64 * 4;
+        let mut pplns = PPLNS::new(1);
+        pplns.set_n(n);
+        let mut queue = VecDeque::new();
+        for i in 0..window {
+            pplns.add_share(share(i));
+            queue.push_back(share(i));
+        }
+
+        group.bench_with_input(BenchmarkId::new("rescan_round_shares", window), &queue, |b, queue| {
+            b.iter(|| rescan(queue))
+        });
+        group.bench_function(BenchmarkId::new("round_shares", window), |b| b.iter(|| pplns.round_shares()));
+
+        let mut i = window;
+        group.bench_function(BenchmarkId::new("add_share_and_round_shares", window), |b| {
+            b.iter(|| {
+                pplns.add_share(share(i));
+                i += 1;
+                pplns.round_shares()
+            })
+        });
+        group.bench_function(BenchmarkId::new("solution_shares", window), |b| {
+            b.iter(|| pplns.solution_shares())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(pplns, pplns_benchmark);
+criterion_main!(pplns);
@@ -0,0 +1,263 @@
+-- Initial schema, as deployed before versioned migrations
+
+--
+-- Name: balance; Type: TABLE
+--
+
+CREATE TABLE balance (
+    id integer NOT NULL,
+    address text NOT NULL,
+    unpaid bigint DEFAULT 0 NOT NULL,
+    paid bigint DEFAULT 0 NOT NULL,
+    pending bigint DEFAULT 0 NOT NULL
+);
+
+
+--
+-- Name: balance_id_seq; Type: SEQUENCE
+--
+
+CREATE SEQUENCE balance_id_seq
+    AS integer
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: balance_id_seq; Type: SEQUENCE OWNED BY
+--
+
+ALTER SEQUENCE balance_id_seq OWNED BY balance.id;
+
+
+--
+-- Name: solution; Type: TABLE
+--
+
+CREATE TABLE solution (
+    id integer NOT NULL,
+    height bigint,
+    reward bigint,
+    "timestamp" bigint DEFAULT EXTRACT(epoch FROM now()) NOT NULL,
+    paid boolean DEFAULT false NOT NULL,
+    valid boolean DEFAULT false NOT NULL,
+    solution_id text NOT NULL,
+    checked integer DEFAULT 0 NOT NULL
+);
+
+
+--
+-- Name: block_id_seq; Type: SEQUENCE
+--
+
+CREATE SEQUENCE block_id_seq
+    AS integer
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: block_id_seq; Type: SEQUENCE OWNED BY
+--
+
+ALTER SEQUENCE block_id_seq OWNED BY solution.id;
+
+
+--
+-- Name: payout; Type: TABLE
+--
+
+CREATE TABLE payout (
+    id integer NOT NULL,
+    solution_id integer NOT NULL,
+    address text NOT NULL,
+    amount bigint NOT NULL,
+    "timestamp" integer DEFAULT EXTRACT(epoch FROM now())
+);
+
+
+--
+-- Name: payout_id_seq; Type: SEQUENCE
+--
+
+CREATE SEQUENCE payout_id_seq
+    AS integer
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: payout_id_seq; Type: SEQUENCE OWNED BY
+--
+
+ALTER SEQUENCE payout_id_seq OWNED BY payout.id;
+
+
+--
+-- Name: share; Type: TABLE
+--
+
+CREATE TABLE share (
+    id integer NOT NULL,
+    solution_id integer NOT NULL,
+    address text NOT NULL,
+    share bigint NOT NULL
+);
+
+
+--
+-- Name: share_id_seq; Type: SEQUENCE
+--
+
+CREATE SEQUENCE share_id_seq
+    AS integer
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: share_id_seq; Type: SEQUENCE OWNED BY
+--
+
+ALTER SEQUENCE share_id_seq OWNED BY share.id;
+
+
+--
+-- Name: stats; Type: TABLE
+--
+
+CREATE TABLE stats (
+    key text NOT NULL,
+    value bigint
+);
+
+
+--
+-- Name: balance id; Type: DEFAULT
+--
+
+ALTER TABLE ONLY balance ALTER COLUMN id SET DEFAULT nextval('balance_id_seq'::regclass);
+
+
+--
+-- Name: payout id; Type: DEFAULT
+--
+
+ALTER TABLE ONLY payout ALTER COLUMN id SET DEFAULT nextval('payout_id_seq'::regclass);
+
+
+--
+-- Name: share id; Type: DEFAULT
+--
+
+ALTER TABLE ONLY share ALTER COLUMN id SET DEFAULT nextval('share_id_seq'::regclass);
+
+
+--
+-- Name: solution id; Type: DEFAULT
+--
+
+ALTER TABLE ONLY solution ALTER COLUMN id SET DEFAULT nextval('block_id_seq'::regclass);
+
+
+--
+-- Name: balance balance_pk; Type: CONSTRAINT
+--
+
+ALTER TABLE ONLY balance
+    ADD CONSTRAINT balance_pk PRIMARY KEY (id);
+
+
+--
+-- Name: payout payout_pk; Type: CONSTRAINT
+--
+
+ALTER TABLE ONLY payout
+    ADD CONSTRAINT payout_pk PRIMARY KEY (id);
+
+
+--
+-- Name: share share_pk; Type: CONSTRAINT
+--
+
+ALTER TABLE ONLY share
+    ADD CONSTRAINT share_pk PRIMARY KEY (id);
+
+
+--
+-- Name: solution solution_pk; Type: CONSTRAINT
+--
+
+ALTER TABLE ONLY solution
+    ADD CONSTRAINT solution_pk PRIMARY KEY (id);
+
+
+--
+-- Name: stats stats_pk; Type: CONSTRAINT
+--
+
+ALTER TABLE ONLY stats
+    ADD CONSTRAINT stats_pk PRIMARY KEY (key);
+
+
+--
+-- Name: balance_address_uindex; Type: INDEX
+--
+
+CREATE UNIQUE INDEX balance_address_uindex ON balance USING btree (address);
+
+
+--
+-- Name: payout_address_index; Type: INDEX
+--
+
+CREATE INDEX payout_address_index ON payout USING btree (address);
+
+
+--
+-- Name: solution_height_index; Type: INDEX
+--
+
+CREATE INDEX solution_height_index ON solution USING btree (height);
+
+
+--
+-- Name: solution_paid_index; Type: INDEX
+--
+
+CREATE INDEX solution_paid_index ON solution USING btree (paid);
+
+
+--
+-- Name: solution_valid_index; Type: INDEX
+--
+
+CREATE INDEX solution_valid_index ON solution USING btree (valid);
+
+
+--
+-- Name: payout payout_solution_id_fk; Type: FK CONSTRAINT
+--
+
+ALTER TABLE ONLY payout
+    ADD CONSTRAINT payout_solution_id_fk FOREIGN KEY (solution_id) REFERENCES solution(id);
+
+
+--
+-- Name: share share_solution_id_fk; Type: FK CONSTRAINT
+--
+
+ALTER TABLE ONLY share
+    ADD CONSTRAINT share_solution_id_fk FOREIGN KEY (solution_id) REFERENCES solution(id);
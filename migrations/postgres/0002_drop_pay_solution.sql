@@ -0,0 +1,4 @@
+-- Rewards are distributed by the pool server since the PL/Python procedure was ported to Rust
+
+DROP PROCEDURE IF EXISTS pay_solution(integer);
+DROP PROCEDURE IF EXISTS pay_solution(integer, bigint);
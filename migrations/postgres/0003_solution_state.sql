@@ -0,0 +1,15 @@
+-- Explicit solution states replace the valid flag and the checked counter
+
+ALTER TABLE solution ADD COLUMN state text DEFAULT 'pending' NOT NULL;
+
+UPDATE solution SET state = CASE
+    WHEN paid THEN 'mature'
+    WHEN valid THEN 'confirmed'
+    WHEN checked >= 3 THEN 'expired'
+    ELSE 'pending'
+END;
+
+DROP INDEX IF EXISTS solution_valid_index;
+ALTER TABLE solution DROP COLUMN valid, DROP COLUMN checked;
+
+CREATE INDEX solution_state_index ON solution USING btree (state);
@@ -0,0 +1,8 @@
+-- On-chain transfers of the balances are recorded as payouts without a solution
+
+ALTER TABLE payout ALTER COLUMN solution_id DROP NOT NULL;
+ALTER TABLE payout ADD COLUMN transaction_id text;
+ALTER TABLE payout ADD COLUMN kind text DEFAULT 'reward' NOT NULL;
+ALTER TABLE payout ADD COLUMN status text DEFAULT 'done' NOT NULL;
+
+CREATE INDEX payout_status_index ON payout USING btree (status);
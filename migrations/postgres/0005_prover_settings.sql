@@ -0,0 +1,7 @@
+-- Payout settings provers sign for their mining address, and where each transfer went
+
+ALTER TABLE balance ADD COLUMN minimum_payout bigint;
+ALTER TABLE balance ADD COLUMN payout_address text;
+ALTER TABLE balance ADD COLUMN settings_timestamp bigint;
+
+ALTER TABLE payout ADD COLUMN recipient text;
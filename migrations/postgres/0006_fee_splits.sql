@@ -0,0 +1,6 @@
+-- Donations and referrers chosen by provers, and where donation and referral payouts come from
+
+ALTER TABLE balance ADD COLUMN donation_bps integer DEFAULT 0 NOT NULL;
+ALTER TABLE balance ADD COLUMN referrer text;
+
+ALTER TABLE payout ADD COLUMN origin text;
@@ -0,0 +1,46 @@
+-- Same tables as the PostgreSQL schema
+
+CREATE TABLE balance (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    address TEXT NOT NULL UNIQUE,
+    unpaid INTEGER DEFAULT 0 NOT NULL,
+    paid INTEGER DEFAULT 0 NOT NULL,
+    pending INTEGER DEFAULT 0 NOT NULL
+);
+
+CREATE TABLE solution (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    height INTEGER,
+    reward INTEGER,
+    "timestamp" INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL,
+    paid INTEGER DEFAULT 0 NOT NULL,
+    valid INTEGER DEFAULT 0 NOT NULL,
+    solution_id TEXT NOT NULL,
+    checked INTEGER DEFAULT 0 NOT NULL
+);
+
+CREATE INDEX solution_height_index ON solution (height);
+CREATE INDEX solution_paid_index ON solution (paid);
+CREATE INDEX solution_valid_index ON solution (valid);
+
+CREATE TABLE payout (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    solution_id INTEGER NOT NULL REFERENCES solution (id),
+    address TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    "timestamp" INTEGER DEFAULT (strftime('%s', 'now'))
+);
+
+CREATE INDEX payout_address_index ON payout (address);
+
+CREATE TABLE share (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    solution_id INTEGER NOT NULL REFERENCES solution (id),
+    address TEXT NOT NULL,
+    share INTEGER NOT NULL
+);
+
+CREATE TABLE stats (
+    key TEXT PRIMARY KEY,
+    value INTEGER
+);
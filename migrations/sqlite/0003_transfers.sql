@@ -0,0 +1,21 @@
+-- On-chain transfers of the balances are recorded as payouts without a solution
+
+CREATE TABLE payout_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    solution_id INTEGER REFERENCES solution (id),
+    address TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    "timestamp" INTEGER DEFAULT (strftime('%s', 'now')),
+    transaction_id TEXT,
+    kind TEXT DEFAULT 'reward' NOT NULL,
+    status TEXT DEFAULT 'done' NOT NULL
+);
+
+INSERT INTO payout_new (id, solution_id, address, amount, "timestamp")
+    SELECT id, solution_id, address, amount, "timestamp" FROM payout;
+
+DROP TABLE payout;
+ALTER TABLE payout_new RENAME TO payout;
+
+CREATE INDEX payout_address_index ON payout (address);
+CREATE INDEX payout_status_index ON payout (status);
@@ -0,0 +1,7 @@
+-- Payout settings provers sign for their mining address, and where each transfer went
+
+ALTER TABLE balance ADD COLUMN minimum_payout INTEGER;
+ALTER TABLE balance ADD COLUMN payout_address TEXT;
+ALTER TABLE balance ADD COLUMN settings_timestamp INTEGER;
+
+ALTER TABLE payout ADD COLUMN recipient TEXT;
@@ -0,0 +1,5 @@
+-- Solutions with a base store the change of their share window from the base solution
+
+ALTER TABLE solution ADD COLUMN base_solution_id INTEGER REFERENCES solution (id);
+
+CREATE INDEX share_solution_id_index ON share (solution_id);
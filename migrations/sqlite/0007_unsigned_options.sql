@@ -0,0 +1,3 @@
+-- Donations and referrers used to come from the unchecked authorize password, only signed settings set them now
+
+UPDATE balance SET donation_bps = 0, referrer = NULL WHERE settings_timestamp IS NULL;
@@ -1,21 +1,16 @@
-
 use std::{
-    collections::{HashMap, VecDeque},
-    fs::create_dir_all,
+    collections::HashMap,
     path::PathBuf,
-    sync::{atomic::AtomicBool, Arc},
-    time::{Duration, Instant},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
-use anyhow::{anyhow, Error, Result};
-use cache::Cache;
-use dirs::home_dir;
-use parking_lot::RwLock;
-use savefile::{load_file, save_file};
-use savefile_derive::Savefile;
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, Result};
 use serde_json::{json, Value};
-use snarkvm::ledger::puzzle::SolutionID;
 use tokio::{
     sync::{
         mpsc::{channel, Sender},
@@ -24,198 +19,225 @@ use tokio::{
     task,
     time::sleep,
 };
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-#[cfg(feature = "db")]
-use crate::db::DB;
+#[cfg(feature = "storage")]
+use crate::state_storage::{Storage, StorageData, StorageType};
 use crate::{
-    accounting::AccountingMessage::{NewShare, NewSolution},
-    AccountingMessage::{Exit, SetN},
-    N,
+    accounting::AccountingMessage::{
+        BlockHeight,
+        NewShare,
+        NewSolution,
+        SessionDonation,
+        SolutionConfirmed,
+        SolutionExpired,
+        SolutionOrphaned,
+    },
+    explorer::{SolutionSource, SolutionStatus},
+    payout_model::{self, FeeSettings, PayoutSettings, Share},
+    share_log::LoggedModel,
+    store::{PoolStore, ProverOptions, SolutionRecord, SolutionShares, SolutionState},
+    AccountingMessage::{Exit, SetProofTarget},
 };
 
-trait PayoutModel {
-    fn add_share(&mut self, share: Share);
-}
-
-#[derive(Clone, Savefile)]
-struct Share {
-    value: u64,
-    owner: String,
-}
-
-impl Share {
-    pub fn init(value: u64, owner: String) -> Self {
-        Share { value, owner }
-    }
-}
-
-#[allow(clippy::upper_case_acronyms)]
-#[derive(Clone, Savefile)]
-struct PPLNS {
-    queue: VecDeque<Share>,
-    current_n: Arc<RwLock<u64>>,
-    n: Arc<RwLock<u64>>,
-}
-
-impl PPLNS {
-    pub fn load() -> Self {
-        let home = home_dir();
-        if home.is_none() {
-            panic!("No home directory found");
-        }
-        create_dir_all(home.as_ref().unwrap().join(".aleo_pool_mainnet")).unwrap();
-        let db_path = home.unwrap().join(".aleo_pool_mainnet/state");
-        if !db_path.exists() {
-            return PPLNS {
-                queue: VecDeque::new(),
-                current_n: Default::default(),
-                n: Default::default(),
-            };
-        }
-        load_file::<PPLNS, PathBuf>(db_path, 0).unwrap()
-    }
-
-    pub fn save(&self) -> std::result::Result<(), Error> {
-        let home = home_dir();
-        if home.is_none() {
-            panic!("No home directory found");
-        }
-        let db_path = home.unwrap().join(".aleo_pool_mainnet/state");
-        save_file(db_path, 0, self).map_err(|e| anyhow!("Failed to save PPLNS state: {}", e))
-    }
-
-    pub fn set_n(&mut self, n: u64) {
-        let start = Instant::now();
-        let mut current_n = self.current_n.write();
-        let mut self_n = self.n.write();
-        if n < *self_n {
-            while *current_n > n {
-                let share = self.queue.pop_front().unwrap();
-                *current_n -= share.value;
-            }
-        }
-        *self_n = n;
-        debug!("set_n took {} us", start.elapsed().as_micros());
-    }
-}
-
-impl PayoutModel for PPLNS {
-    fn add_share(&mut self, share: Share) {
-        let start = Instant::now();
-        self.queue.push_back(share.clone());
-        let mut current_n = self.current_n.write();
-        let self_n = self.n.read();
-        *current_n += share.value;
-        while *current_n > *self_n {
-            let share = self.queue.pop_front().unwrap();
-            *current_n -= share.value;
-        }
-        debug!("add_share took {} us", start.elapsed().as_micros());
-        debug!("n: {} / {}", *current_n, self_n);
-    }
-}
-
-#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
-struct Null {}
-
 pub enum AccountingMessage {
-    NewShare(String, u64),
-    SetN(u64),
-    NewSolution(SolutionID<N>),
+    /// (address, value, difficulty)
+    NewShare(String, u64, u64),
+    SetProofTarget(u64),
+    NewSolution(String),
+    /// (solution_id, height, reward)
+    SolutionConfirmed(String, u32, u64),
+    /// The block including the solution was replaced.
+    SolutionOrphaned(String),
+    /// The solution was not included before the end of its epoch.
+    SolutionExpired(String),
+    /// Latest block height of the network.
+    BlockHeight(u32),
+    /// Donation in basis points an address chose when authorizing, 0 once its last prover is gone.
+    SessionDonation(String, u64),
     Exit,
 }
 
-#[cfg(feature = "db")]
 static PAY_INTERVAL: Duration = Duration::from_secs(60);
+/// Age after which a solution never seen in a block is given up on, well past the end of its epoch
+static SOLUTION_EXPIRY: Duration = Duration::from_secs(60 * 60 * 2);
+/// Most solutions stored as changes in a row before a whole window is stored again, which bounds the
+/// rows read to rebuild a window
+static KEYFRAME_INTERVAL: u32 = 16;
+#[cfg(feature = "storage")]
+static SHARE_HISTORY_RETENTION: Duration = Duration::from_secs(60 * 60 * 24 * 7);
 
 #[allow(clippy::type_complexity)]
 pub struct Accounting {
-    pplns: Arc<TokioRwLock<PPLNS>>,
-    #[cfg(feature = "db")]
-    explorer_url: String,
-    #[cfg(feature = "db")]
-    database: Arc<DB>,
+    model: Arc<TokioRwLock<LoggedModel>>,
+    // Donations of connected addresses from the unsigned authorize password, never stored
+    session_donations: Arc<TokioRwLock<HashMap<String, u64>>>,
+    solution_source: Option<Arc<dyn SolutionSource>>,
+    store: Arc<dyn PoolStore>,
+    fees: FeeSettings,
+    maturity_blocks: u32,
+    // Latest block height known from the node or the solution source, 0 until the first one arrives
+    latest_height: Arc<AtomicU32>,
     sender: Sender<AccountingMessage>,
-    round_cache: TokioRwLock<Cache<Null, (u32, HashMap<String, u64>)>>,
     exit_lock: Arc<AtomicBool>,
 }
 
 impl Accounting {
-    pub fn init(explorer_url: Option<String>) -> Arc<Accounting> {
-        #[cfg(feature = "db")]
-        let database = Arc::new(DB::init());
-
-        #[cfg(feature = "db")]
-        if explorer_url.is_none() {
-            panic!("Explorer URL is required with db feature enabled");
+    pub fn init(
+        solution_source: Option<Arc<dyn SolutionSource>>,
+        data_dir: PathBuf,
+        payout_settings: PayoutSettings,
+        store: Arc<dyn PoolStore>,
+        #[cfg(feature = "storage")] storage: Arc<Storage>,
+    ) -> Arc<Accounting> {
+        let fees = &payout_settings.fees;
+        match &fees.address {
+            Some(fee_address) => info!("Pool fee is {} bps, credited to {}", fees.bps, fee_address),
+            None => info!("Pool fee is {} bps", fees.bps),
         }
-
-        let pplns = Arc::new(TokioRwLock::new(PPLNS::load()));
+        for (address, bps) in &fees.splits {
+            info!("{} bps of the pool fee is credited to {}", bps, address);
+        }
+        if let Some(donation_address) = &fees.donation_address {
+            info!("Donations are credited to {}", donation_address);
+        }
+        if fees.referral_bps > 0 {
+            info!("Referrers earn {} bps of the referred earnings", fees.referral_bps);
+        }
+        let model = payout_model::new(&payout_settings)
+            .and_then(|model| {
+                LoggedModel::recover(
+                    model,
+                    &data_dir,
+                    #[cfg(feature = "storage")]
+                    &storage,
+                )
+            })
+            .expect("Unable to recover payout model state");
+        // (unix timestamp, address) -> share value
+        #[cfg(feature = "storage")]
+        let share_history: StorageData<(u64, String), u64> = storage.init_data(StorageType::ShareHistory);
+        let model = Arc::new(TokioRwLock::new(model));
 
         let (sender, mut receiver) = channel(1024);
 
         let accounting = Accounting {
-            pplns,
-            #[cfg(feature = "db")]
-            explorer_url: explorer_url.unwrap(),
-            #[cfg(feature = "db")]
-            database,
+            model,
+            session_donations: Default::default(),
+            solution_source,
+            store,
+            fees: payout_settings.fees.clone(),
+            maturity_blocks: payout_settings.maturity_blocks,
+            latest_height: Default::default(),
             sender,
-            round_cache: TokioRwLock::new(Cache::new(Duration::from_secs(10))),
             exit_lock: Arc::new(AtomicBool::new(false)),
         };
 
-        let pplns = accounting.pplns.clone();
-        #[cfg(feature = "db")]
-        let database = accounting.database.clone();
+        let model = accounting.model.clone();
+        let store = accounting.store.clone();
+        let latest_height = accounting.latest_height.clone();
+        let session_donations = accounting.session_donations.clone();
+        #[cfg(feature = "storage")]
+        let history = share_history.clone();
         let exit_lock = accounting.exit_lock.clone();
         task::spawn(async move {
+            let mut saved_window: Option<SavedWindow> = None;
             while let Some(request) = receiver.recv().await {
                 match request {
-                    NewShare(address, value) => {
-                        pplns.write().await.add_share(Share::init(value, address.clone()));
+                    NewShare(address, value, difficulty) => {
+                        let share = Share::init(value, address.clone());
+                        let mut model = model.write().await;
+                        model.credit_share(&share, difficulty);
+                        model.add_share(share);
+                        #[cfg(feature = "storage")]
+                        Accounting::record_share_history(&history, address.clone(), value);
                         debug!("Recorded share from {} with value {}", address, value);
                     }
-                    SetN(n) => {
-                        pplns.write().await.set_n(n);
-                        debug!("Set N to {}", n);
+                    SetProofTarget(proof_target) => {
+                        model.write().await.set_proof_target(proof_target);
                     }
                     NewSolution(solution_id) => {
-                        let pplns = pplns.read().await.clone();
-                        let (_, address_shares) = Accounting::pplns_to_provers_shares(&pplns);
-
-                        #[cfg(feature = "db")]
-                        if let Err(e) = database.save_solution(solution_id, address_shares).await {
-                            error!("Failed to save block reward : {}", e);
-                        } else {
-                            info!("Recorded solution {}", solution_id);
+                        let address_shares = model.write().await.solution_shares(&solution_id);
+                        let shares = SavedWindow::encode(saved_window.as_ref(), &address_shares);
+
+                        match store.save_solution(&solution_id, &shares).await {
+                            Ok(id) => {
+                                info!("Recorded solution {}", solution_id);
+                                let depth = match (&shares.base, &saved_window) {
+                                    (Some(_), Some(previous)) => previous.depth + 1,
+                                    _ => 0,
+                                };
+                                saved_window = Some(SavedWindow {
+                                    id,
+                                    shares: address_shares,
+                                    depth,
+                                });
+                            }
+                            Err(e) => error!("Failed to save block reward : {}", e),
                         }
                     }
+                    SolutionConfirmed(solution_id, height, reward) => {
+                        info!(
+                            "Solution {} confirmed at height {} with reward {}",
+                            solution_id, height, reward
+                        );
+                        if let Err(e) = store.confirm_solution(&solution_id, height, reward).await {
+                            error!("Failed to update solution {}: {}", solution_id, e);
+                        }
+                    }
+                    SolutionOrphaned(solution_id) => {
+                        match store.set_solution_state(&solution_id, SolutionState::Orphaned).await {
+                            Ok(true) => warn!("Solution {} was orphaned, its reward is lost", solution_id),
+                            Ok(false) => {}
+                            Err(e) => error!("Failed to update solution {}: {}", solution_id, e),
+                        }
+                    }
+                    SolutionExpired(solution_id) => {
+                        match store.set_solution_state(&solution_id, SolutionState::Expired).await {
+                            Ok(true) => warn!("Solution {} expired without being included", solution_id),
+                            Ok(false) => {}
+                            Err(e) => error!("Failed to update solution {}: {}", solution_id, e),
+                        }
+                    }
+                    BlockHeight(height) => {
+                        latest_height.fetch_max(height, Ordering::SeqCst);
+                    }
+                    SessionDonation(address, 0) => {
+                        session_donations.write().await.remove(&address);
+                    }
+                    SessionDonation(address, donation_bps) => {
+                        session_donations.write().await.insert(address, donation_bps);
+                    }
                     Exit => {
                         receiver.close();
-                        let _ = pplns.read().await.save();
-                        exit_lock.store(true, std::sync::atomic::Ordering::SeqCst);
+                        Accounting::flush_credits(&model, store.as_ref()).await;
+                        if let Err(e) = model.write().await.snapshot() {
+                            error!("Unable to snapshot payout model state: {}", e);
+                        }
+                        exit_lock.store(true, Ordering::SeqCst);
                     }
                 }
             }
         });
 
-        // backup pplns
-        let pplns = accounting.pplns.clone();
+        // backup payout model state and write per-share earnings
+        let model = accounting.model.clone();
+        let store = accounting.store.clone();
         task::spawn(async move {
             loop {
                 sleep(Duration::from_secs(60)).await;
-                if let Err(e) = pplns.read().await.save() {
-                    error!("Unable to backup pplns: {}", e);
+                Accounting::flush_credits(&model, store.as_ref()).await;
+                if let Err(e) = model.write().await.snapshot() {
+                    error!("Unable to snapshot payout model state: {}", e);
                 }
+                #[cfg(feature = "storage")]
+                Accounting::prune_share_history(&share_history);
             }
         });
 
         let res = Arc::new(accounting);
 
         // payout routine
-        #[cfg(feature = "db")]
         task::spawn(Accounting::payout_loop(res.clone()));
 
         res
@@ -226,110 +248,652 @@ impl Accounting {
     }
 
     pub async fn wait_for_exit(&self) {
-        while !self.exit_lock.load(std::sync::atomic::Ordering::SeqCst) {
+        while !self.exit_lock.load(Ordering::SeqCst) {
             sleep(Duration::from_millis(100)).await;
         }
     }
 
-    fn pplns_to_provers_shares(pplns: &PPLNS) -> (u32, HashMap<String, u64>) {
-        let mut address_shares = HashMap::new();
-
-        let time = Instant::now();
-        pplns.queue.iter().for_each(|share| {
-            if let Some(shares) = address_shares.get_mut(&share.owner) {
-                *shares += share.value;
-            } else {
-                address_shares.insert(share.clone().owner, share.value);
-            }
-        });
-        debug!("PPLNS to Provers shares took {} us", time.elapsed().as_micros());
-
-        (address_shares.len() as u32, address_shares)
+    /// Credits the per-share earnings and snapshots the payout model now, rather than at the next
+    /// minute.
+    pub async fn save(&self) -> Result<()> {
+        Accounting::flush_credits(&self.model, self.store.as_ref()).await;
+        self.model.write().await.snapshot()
     }
 
     pub async fn current_round(&self) -> Value {
-        let pplns = self.pplns.clone().read().await.clone();
-        let cache = self.round_cache.read().await.get(Null {});
-        let (provers, shares) = match cache {
-            Some(cache) => cache,
-            None => {
-                let result = Accounting::pplns_to_provers_shares(&pplns);
-                self.round_cache.write().await.set(Null {}, result.clone());
-                result
-            }
+        let ((n, current_n), shares) = {
+            let model = self.model.read().await;
+            (model.round(), model.round_shares())
         };
         json!({
-            "n": pplns.n,
-            "current_n": pplns.current_n,
-            "provers": provers,
-            "shares": shares,
+            "n": n,
+            "current_n": current_n,
+            "provers": shares.len(),
+            "shares": shares.as_ref(),
         })
     }
 
-    #[cfg(feature = "db")]
-    async fn check_solution(&self, solution_id: &String) -> Result<bool> {
-        let client = reqwest::ClientBuilder::new()
-            .user_agent(format!("HarukaAleoPool/{}", env!("CARGO_PKG_VERSION")))
-            .build()?;
+    #[cfg(feature = "storage")]
+    fn record_share_history(history: &StorageData<(u64, String), u64>, address: String, value: u64) {
+        let key = (now_unix_secs(), address);
+        let result = history
+            .get(&key)
+            .and_then(|previous| history.put(&key, &(previous.unwrap_or_default() + value)));
+        if let Err(e) = result {
+            error!("Unable to record share history: {}", e);
+        }
+    }
 
-        let resp = client
-            .get(format!("{}/v2/solution/{}", self.explorer_url, solution_id))
-            .send()
-            .await?;
+    #[cfg(feature = "storage")]
+    fn prune_share_history(history: &StorageData<(u64, String), u64>) {
+        let oldest = now_unix_secs().saturating_sub(SHARE_HISTORY_RETENTION.as_secs());
+        let expired = history
+            .iter()
+            .take_while(|((timestamp, _), _)| *timestamp < oldest)
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        for key in expired {
+            if let Err(e) = history.delete(&key) {
+                error!("Unable to prune share history: {}", e);
+                return;
+            }
+        }
+    }
 
-        let is_valid = resp.status() == 200;
-        if resp.status() != 404 && !is_valid {
-            return Err(anyhow!("Failed to check solution: {}", resp.text().await?));
+    /// Writes the logged per-share earnings to the balances. The model stays locked meanwhile, so the
+    /// same earnings are never written twice.
+    async fn flush_credits(model: &TokioRwLock<LoggedModel>, store: &dyn PoolStore) {
+        let mut model = model.write().await;
+        let pending = model.credits().clone();
+        if pending.is_empty() {
+            return;
         }
-        if is_valid {
-            let result = resp.json::<Value>().await?;
-            self.database
-                .set_solution_valid(
-                    solution_id,
-                    true,
-                    Some(result["height"].as_u64().ok_or_else(|| anyhow!("height"))? as u32),
-                    Some(result["reward"].as_u64().ok_or_else(|| anyhow!("reward"))?),
-                )
-                .await?;
-        } else {
-            self.database.set_solution_valid(solution_id, false, None, None).await?;
+        match store.credit_balances(&pending).await {
+            Ok(_) => model.credits_flushed(pending),
+            Err(e) => error!("Unable to credit per-share earnings: {}", e),
+        }
+    }
+
+    async fn pay_solution(&self, id: i32, reward: u64) -> Result<()> {
+        if reward == 0 {
+            return self.store.close_solution(id).await;
+        }
+        let shares = self.store.get_solution_shares(id).await?;
+        let addresses = shares.keys().cloned().collect::<Vec<_>>();
+        let mut options = self.store.get_prover_options(&addresses).await?;
+        // A session donation can raise the signed one, never lower it
+        for (address, donation_bps) in self.session_donations.read().await.iter() {
+            if shares.contains_key(address) {
+                let options = options.entry(address.clone()).or_default();
+                options.donation_bps = options.donation_bps.max(*donation_bps);
+            }
         }
-        Ok(is_valid)
+        let distribution = distribute_reward(reward, &shares, &options, &self.fees)?;
+        self.store.pay_solution(id, &distribution).await
+    }
+
+    /// Moves a solution along its states, crediting the reward once it is mature.
+    async fn settle_solution(&self, mut solution: SolutionRecord) -> Result<()> {
+        // Without a source, inclusion comes from the blocks followed by the node link
+        if let Some(source) = &self.solution_source {
+            match source.lookup(&solution.solution_id, solution.height).await? {
+                SolutionStatus::Included(height, reward) => {
+                    if solution.height != Some(height) || solution.reward != Some(reward) {
+                        self.store.confirm_solution(&solution.solution_id, height, reward).await?;
+                        info!(
+                            "Solution {} confirmed at height {} with reward {}",
+                            solution.solution_id, height, reward
+                        );
+                    }
+                    solution.state = SolutionState::Confirmed;
+                    solution.height = Some(height);
+                    solution.reward = Some(reward);
+                }
+                SolutionStatus::NotIncluded if solution.state == SolutionState::Confirmed => {
+                    // The source may not have indexed the block yet, it is only believed once it is
+                    // past the depth the solution would have matured at
+                    let height = solution.height.unwrap_or_default();
+                    let source_height = source.latest_height().await?;
+                    if !source_height.is_some_and(|source_height| source_height > height + self.maturity_blocks) {
+                        debug!(
+                            "Solution {} at height {} is in no block on {} yet (its latest height {:?})",
+                            solution.solution_id,
+                            height,
+                            source.name(),
+                            source_height
+                        );
+                        return Ok(());
+                    }
+                    if self
+                        .store
+                        .set_solution_state(&solution.solution_id, SolutionState::Orphaned)
+                        .await?
+                    {
+                        warn!(
+                            "Solution {} is no longer on the network, reward {} is lost",
+                            solution.solution_id,
+                            solution.reward.unwrap_or_default()
+                        );
+                    }
+                    return Ok(());
+                }
+                SolutionStatus::NotIncluded | SolutionStatus::Unknown => {}
+            }
+        }
+
+        match (solution.state, solution.height, solution.reward) {
+            (SolutionState::Pending, ..) => {
+                if now_unix_secs().saturating_sub(solution.timestamp) > SOLUTION_EXPIRY.as_secs()
+                    && self
+                        .store
+                        .set_solution_state(&solution.solution_id, SolutionState::Expired)
+                        .await?
+                {
+                    warn!("Solution {} expired without being included", solution.solution_id);
+                }
+            }
+            (SolutionState::Confirmed, Some(height), Some(reward)) => {
+                let latest_height = self.latest_height.load(Ordering::SeqCst);
+                if latest_height == 0 {
+                    warn!(
+                        "No block height is known yet, solution {} is settled once the node or the solution \
+                         source reports one",
+                        solution.solution_id
+                    );
+                    return Ok(());
+                }
+                if latest_height < height + self.maturity_blocks {
+                    debug!(
+                        "Solution {} at height {} is not mature yet (latest height {})",
+                        solution.solution_id, height, latest_height
+                    );
+                    return Ok(());
+                }
+                // The model only learns of the reward once it is paid, so a failed payment is retried
+                // against the same state
+                let credited = self.model.read().await.solution_reward(reward);
+                self.pay_solution(solution.id, credited).await?;
+                self.model.write().await.solution_matured(reward);
+                info!("Solution {} matured, credited {}", solution.solution_id, credited);
+            }
+            (state, ..) => {
+                warn!(
+                    "Solution {} is {} without a height or reward",
+                    solution.solution_id,
+                    state.as_str()
+                );
+            }
+        }
+        Ok(())
     }
 
-    #[cfg(feature = "db")]
     async fn payout_loop(self: Arc<Accounting>) {
-        'forever: loop {
+        loop {
             info!("Running payout loop");
-            let blocks = self.database.get_should_pay_solutions().await;
-            if blocks.is_err() {
-                error!("Unable to get should pay blocks: {}", blocks.unwrap_err());
-                sleep(PAY_INTERVAL).await;
-                continue;
-            }
-            for (id, solution_id) in blocks.unwrap() {
-                let valid = self.check_solution(&solution_id).await;
-                if valid.is_err() {
-                    error!("Unable to check solution: {}", valid.unwrap_err());
+            let solutions = match self.store.get_unsettled_solutions().await {
+                Ok(solutions) => solutions,
+                Err(e) => {
+                    error!("Unable to get unsettled solutions: {}", e);
                     sleep(PAY_INTERVAL).await;
-                    continue 'forever;
+                    continue;
                 }
-                let valid = valid.unwrap();
-                if valid {
-                    match self.database.pay_solution(id).await {
-                        Ok(_) => {
-                            info!("Paid solution {}", solution_id);
-                        }
-                        Err(e) => {
-                            error!("Unable to pay solution {}: {}", id, e);
-                            sleep(PAY_INTERVAL).await;
-                            continue 'forever;
-                        }
+            };
+            // The node link may not have sent a height yet, or never does with the mock upstream
+            if let Some(source) = &self.solution_source {
+                match source.latest_height().await {
+                    Ok(Some(height)) => {
+                        self.latest_height.fetch_max(height, Ordering::SeqCst);
                     }
+                    Ok(None) => {}
+                    Err(e) => warn!("Unable to get the latest height from {}: {}", source.name(), e),
+                }
+            }
+            // A failed lookup or payout is retried on the next pass, the other solutions go on
+            for solution in solutions {
+                let id = solution.id;
+                if let Err(e) = self.settle_solution(solution).await {
+                    error!("Unable to settle solution {}: {}", id, e);
                 }
             }
 
             sleep(PAY_INTERVAL).await;
         }
     }
-}
\ No newline at end of file
+}
+
+/// Window of the latest solution saved, the next one is stored relative to it.
+struct SavedWindow {
+    id: i32,
+    shares: HashMap<String, u64>,
+    /// Solutions stored as changes since the last keyframe
+    depth: u32,
+}
+
+impl SavedWindow {
+    /// Stores a window as its change from the previous one, or whole if that is not smaller or the
+    /// previous one ends a long enough run of changes.
+    fn encode(previous: Option<&SavedWindow>, shares: &HashMap<String, u64>) -> SolutionShares {
+        let keyframe = || SolutionShares {
+            base: None,
+            shares: shares
+                .iter()
+                .map(|(address, share)| (address.clone(), *share as i64))
+                .collect(),
+        };
+        let previous = match previous {
+            Some(previous) if previous.depth + 1 < KEYFRAME_INTERVAL => previous,
+            _ => return keyframe(),
+        };
+        let mut changes = HashMap::new();
+        for (address, share) in shares {
+            let change = *share as i64 - previous.shares.get(address).copied().unwrap_or_default() as i64;
+            if change != 0 {
+                changes.insert(address.clone(), change);
+            }
+        }
+        for (address, share) in &previous.shares {
+            if !shares.contains_key(address) && *share > 0 {
+                changes.insert(address.clone(), -(*share as i64));
+            }
+        }
+        if changes.len() >= shares.len() {
+            return keyframe();
+        }
+        SolutionShares {
+            base: Some(previous.id),
+            shares: changes,
+        }
+    }
+}
+
+/// What a payout row credits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PayoutKind {
+    Reward,
+    Fee,
+    Donation,
+    Referral,
+}
+
+impl PayoutKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PayoutKind::Reward => "reward",
+            PayoutKind::Fee => "fee",
+            PayoutKind::Donation => "donation",
+            PayoutKind::Referral => "referral",
+        }
+    }
+
+    /// Whether the payout comes out of the pool fee, which the `total_fee` stat already counts.
+    pub fn from_fee(&self) -> bool {
+        matches!(self, PayoutKind::Fee | PayoutKind::Referral)
+    }
+}
+
+impl FromStr for PayoutKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "reward" => Ok(PayoutKind::Reward),
+            "fee" => Ok(PayoutKind::Fee),
+            "donation" => Ok(PayoutKind::Donation),
+            "referral" => Ok(PayoutKind::Referral),
+            _ => Err(anyhow!("Unknown payout kind {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Payout {
+    pub address: String,
+    pub amount: u64,
+    pub kind: PayoutKind,
+    /// Prover a donation or referral reward comes from
+    pub origin: Option<String>,
+}
+
+/// Split of a solution reward between the provers and the pool.
+pub struct Distribution {
+    /// Whole pool fee, including the parts credited to the fee splits and referrers
+    pub fee: u64,
+    pub payouts: Vec<Payout>,
+}
+
+fn bps_of(amount: u64, bps: u64) -> u64 {
+    (amount as u128 * bps as u128 / 10000) as u64
+}
+
+/// Splits a reward in proportion to the shares, after taking the pool fee. The dust left by the
+/// integer division goes to the addresses with the largest remainders, one microcredit each, so the
+/// payouts and the fee always add up to the reward.
+///
+/// Donations come out of the prover earnings. Referral rewards come out of the pool fee, in address
+/// order until it runs out, and the fee splits then share what is left of it. The rest goes to the
+/// fee address, or stays in the pool wallet without one.
+pub(crate) fn distribute_reward(
+    reward: u64,
+    shares: &HashMap<String, u64>,
+    options: &HashMap<String, ProverOptions>,
+    fees: &FeeSettings,
+) -> Result<Distribution> {
+    let total_shares: u128 = shares.values().map(|share| *share as u128).sum();
+    if total_shares == 0 {
+        return Err(anyhow!("No share data for solution"));
+    }
+    let fee = bps_of(reward, fees.bps);
+    let distributable = (reward - fee) as u128;
+
+    // (address, amount, remainder)
+    let mut earnings = shares
+        .iter()
+        .map(|(address, share)| {
+            let amount = distributable * *share as u128;
+            (address.clone(), (amount / total_shares) as u64, amount % total_shares)
+        })
+        .collect::<Vec<_>>();
+    let paid: u128 = earnings.iter().map(|(_, amount, _)| *amount as u128).sum();
+    let dust = (distributable - paid) as usize;
+    earnings.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+    earnings.iter_mut().take(dust).for_each(|earning| earning.1 += 1);
+    earnings.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut payouts = vec![];
+    let mut fee_left = fee;
+    for (address, earned, _) in earnings {
+        let mut amount = earned;
+        let options = options.get(&address);
+        if let (Some(donation_address), Some(options)) = (&fees.donation_address, options) {
+            let donation = bps_of(earned, options.donation_bps);
+            amount -= donation;
+            payouts.push(Payout {
+                address: donation_address.clone(),
+                amount: donation,
+                kind: PayoutKind::Donation,
+                origin: Some(address.clone()),
+            });
+        }
+        if let Some(referrer) = options.and_then(|options| options.referrer.as_ref()) {
+            let referral = bps_of(earned, fees.referral_bps).min(fee_left);
+            fee_left -= referral;
+            payouts.push(Payout {
+                address: referrer.clone(),
+                amount: referral,
+                kind: PayoutKind::Referral,
+                origin: Some(address.clone()),
+            });
+        }
+        payouts.push(Payout {
+            address,
+            amount,
+            kind: PayoutKind::Reward,
+            origin: None,
+        });
+    }
+
+    let mut split = 0;
+    for (address, bps) in &fees.splits {
+        let amount = bps_of(fee_left, *bps);
+        split += amount;
+        payouts.push(Payout {
+            address: address.clone(),
+            amount,
+            kind: PayoutKind::Fee,
+            origin: None,
+        });
+    }
+    if let Some(fee_address) = &fees.address {
+        payouts.push(Payout {
+            address: fee_address.clone(),
+            amount: fee_left - split,
+            kind: PayoutKind::Fee,
+            origin: None,
+        });
+    }
+    payouts.retain(|payout| payout.amount > 0);
+
+    Ok(Distribution { fee, payouts })
+}
+
+fn now_unix_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn sum(distribution: &Distribution, kinds: &[PayoutKind]) -> u64 {
+        distribution
+            .payouts
+            .iter()
+            .filter(|payout| kinds.contains(&payout.kind))
+            .map(|payout| payout.amount)
+            .sum()
+    }
+
+    /// Random shares, prover options and fee settings, with splits adding up to at most the whole fee.
+    fn random_case(rng: &mut StdRng) -> (u64, HashMap<String, u64>, HashMap<String, ProverOptions>, FeeSettings) {
+        let reward = rng.gen_range(1..100_000_000_000);
+        let provers = rng.gen_range(1..50);
+        let mut shares = HashMap::new();
+        let mut options = HashMap::new();
+        for i in 0..provers {
+            let address = format!("prover{}", i);
+            shares.insert(address.clone(), rng.gen_range(1..1_000_000));
+            if rng.gen_bool(0.5) {
+                let referrer = rng.gen_bool(0.5).then(|| format!("referrer{}", rng.gen_range(0..5)));
+                options.insert(
+                    address,
+                    ProverOptions {
+                        donation_bps: rng.gen_range(0..=10000),
+                        referrer,
+                    },
+                );
+            }
+        }
+        let mut splits = vec![];
+        let mut bps_left = 10000;
+        for i in 0..rng.gen_range(0..4) {
+            let bps = rng.gen_range(0..=bps_left);
+            bps_left -= bps;
+            splits.push((format!("split{}", i), bps));
+        }
+        let fees = FeeSettings {
+            bps: rng.gen_range(0..=10000),
+            address: rng.gen_bool(0.5).then(|| "fee".to_string()),
+            splits,
+            donation_address: rng.gen_bool(0.5).then(|| "donation".to_string()),
+            referral_bps: rng.gen_range(0..=10000),
+        };
+        (reward, shares, options, fees)
+    }
+
+    /// Saves a run of sliding windows the way the accounting loop does and reads every one back.
+    async fn window_round_trip(store: Arc<dyn PoolStore>) {
+        let mut window = (0..10)
+            .map(|i| (format!("prover{}", i), 100 + i as u64))
+            .collect::<HashMap<_, _>>();
+        let mut next_prover = 10;
+        let mut saved: Option<SavedWindow> = None;
+        let mut windows = vec![];
+        let mut changes = 0;
+        let mut keyframes = 0;
+        let mut left = 0;
+        for step in 0..(KEYFRAME_INTERVAL * 3) as usize {
+            // New shares come in, and from time to time a prover joins or leaves the window
+            *window.entry(format!("prover{}", next_prover - 1 - step % 5)).or_default() += 7;
+            if step % 3 == 0 {
+                let oldest = window
+                    .keys()
+                    .min_by_key(|address| address["prover".len()..].parse::<u32>().unwrap())
+                    .cloned()
+                    .unwrap();
+                window.remove(&oldest);
+            }
+            if step % 4 == 0 {
+                window.insert(format!("prover{}", next_prover), 50);
+                next_prover += 1;
+            }
+
+            let shares = SavedWindow::encode(saved.as_ref(), &window);
+            match &shares.base {
+                Some(_) => {
+                    changes += 1;
+                    left += shares
+                        .shares
+                        .iter()
+                        .filter(|(address, change)| **change < 0 && !window.contains_key(*address))
+                        .count();
+                }
+                None => keyframes += 1,
+            }
+            let id = store.save_solution(&format!("solution{}", step), &shares).await.unwrap();
+            let depth = match (&shares.base, &saved) {
+                (Some(_), Some(previous)) => previous.depth + 1,
+                _ => 0,
+            };
+            saved = Some(SavedWindow {
+                id,
+                shares: window.clone(),
+                depth,
+            });
+            windows.push((id, window.clone()));
+        }
+        assert!(changes > 0 && keyframes >= 3, "{} changes, {} keyframes", changes, keyframes);
+        assert!(left > 0, "no prover left the window");
+
+        for (id, window) in windows {
+            assert_eq!(store.get_solution_shares(id).await.unwrap(), window, "solution {}", id);
+        }
+    }
+
+    #[tokio::test]
+    async fn overlapping_windows_round_trip_in_memory() {
+        window_round_trip(Arc::new(crate::store::MemoryStore::default())).await;
+    }
+
+    #[tokio::test]
+    async fn referrals_are_counted_once_in_stats() {
+        let store = crate::store::MemoryStore::default();
+        let shares = HashMap::from([("prover0".to_string(), 1), ("prover1".to_string(), 3)]);
+        let options = HashMap::from([(
+            "prover1".to_string(),
+            ProverOptions {
+                donation_bps: 0,
+                referrer: Some("referrer".to_string()),
+            },
+        )]);
+        let fees = FeeSettings {
+            bps: 1000,
+            address: Some("fee".to_string()),
+            splits: vec![],
+            donation_address: None,
+            referral_bps: 1000,
+        };
+        let distribution = distribute_reward(1_000_000, &shares, &options, &fees).unwrap();
+        assert!(sum(&distribution, &[PayoutKind::Referral]) > 0);
+
+        let id = store
+            .save_solution("solution", &SavedWindow::encode(None, &shares))
+            .await
+            .unwrap();
+        store.confirm_solution("solution", 1, 1_000_000).await.unwrap();
+        store.pay_solution(id, &distribution).await.unwrap();
+        let stats = store.get_stats().await.unwrap();
+        assert_eq!(stats["total_fee"], distribution.fee);
+        assert_eq!(stats["total_paid"] + stats["total_fee"], 1_000_000);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn overlapping_windows_round_trip_in_sqlite() {
+        let path = std::env::temp_dir().join(format!("aleo-pool-windows-{}.sqlite", std::process::id()));
+        let store = Arc::new(crate::store::SqliteStore::open(&path));
+        store.migrate().await.unwrap();
+        window_round_trip(store).await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn payouts_and_fee_add_up_to_reward() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let (reward, shares, options, fees) = random_case(&mut rng);
+            let distribution = distribute_reward(reward, &shares, &options, &fees).unwrap();
+            let earnings = sum(&distribution, &[PayoutKind::Reward, PayoutKind::Donation]);
+            assert_eq!(earnings + distribution.fee, reward);
+            if fees.address.is_some() {
+                let fee = sum(&distribution, &[PayoutKind::Fee, PayoutKind::Referral]);
+                assert_eq!(fee, distribution.fee);
+            }
+        }
+    }
+
+    #[test]
+    fn dust_goes_to_largest_remainders() {
+        let fees = FeeSettings::default();
+        let rewards = |shares: &[(&str, u64)], reward: u64| {
+            let shares = shares
+                .iter()
+                .map(|(address, share)| (address.to_string(), *share))
+                .collect::<HashMap<_, _>>();
+            let distribution = distribute_reward(reward, &shares, &HashMap::new(), &fees).unwrap();
+            distribution
+                .payouts
+                .into_iter()
+                .map(|payout| (payout.address, payout.amount))
+                .collect::<HashMap<_, _>>()
+        };
+
+        // 3.33, 6.67: the larger remainder gets the microcredit
+        let paid = rewards(&[("a", 1), ("b", 2)], 10);
+        assert_eq!((paid["a"], paid["b"]), (3, 7));
+
+        // 5, 2.5, 2.5: a tie between b and c goes to the lowest address
+        let paid = rewards(&[("c", 1), ("b", 1), ("a", 2)], 10);
+        assert_eq!((paid["a"], paid["b"], paid["c"]), (5, 3, 2));
+
+        // 3.33 each: one microcredit of dust, to the lowest address
+        let paid = rewards(&[("c", 1), ("a", 1), ("b", 1)], 10);
+        assert_eq!((paid["a"], paid["b"], paid["c"]), (4, 3, 3));
+    }
+
+    #[test]
+    fn fee_splits_and_referrals_stay_within_fee() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..1000 {
+            let (reward, shares, options, mut fees) = random_case(&mut rng);
+            // Without a fee address, the fee payouts are only the splits
+            fees.address = None;
+            let distribution = distribute_reward(reward, &shares, &options, &fees).unwrap();
+            let referrals = sum(&distribution, &[PayoutKind::Referral]);
+            let splits = sum(&distribution, &[PayoutKind::Fee]);
+            assert!(referrals + splits <= distribution.fee);
+        }
+
+        // Referrals worth far more than the fee are cut down to it
+        let shares = HashMap::from([("a".to_string(), 1), ("b".to_string(), 1)]);
+        let referred = ProverOptions {
+            donation_bps: 0,
+            referrer: Some("r".to_string()),
+        };
+        let options = HashMap::from([("a".to_string(), referred.clone()), ("b".to_string(), referred)]);
+        let fees = FeeSettings {
+            bps: 100,
+            splits: vec![("s".to_string(), 5000)],
+            referral_bps: 5000,
+            ..Default::default()
+        };
+        let distribution = distribute_reward(1_000_000, &shares, &options, &fees).unwrap();
+        assert_eq!(distribution.fee, 10_000);
+        assert_eq!(sum(&distribution, &[PayoutKind::Referral]), 10_000);
+        assert_eq!(sum(&distribution, &[PayoutKind::Fee]), 0);
+    }
+}
@@ -0,0 +1,401 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{error, info, warn};
+use warp::{
+    addr::remote,
+    body,
+    delete,
+    filters::BoxedFilter,
+    get,
+    header,
+    http::StatusCode,
+    path,
+    post,
+    reply,
+    reply::json,
+    Filter,
+    Rejection,
+    Reply,
+};
+
+use crate::{
+    network::PoolNetwork,
+    server::{TargetSettings, BAN_DURATION, MAX_BAN_DURATION},
+    store::PoolStore,
+    Accounting,
+    Server,
+};
+
+/// File in the data directory admin actions are appended to, one JSON object per line.
+static AUDIT_LOG_FILE: &str = "admin_audit.log";
+/// Refused requests written to the audit log per minute, the others are only counted
+const MAX_REFUSED_PER_MINUTE: u32 = 10;
+
+/// What an admin request asks for, as recorded in the audit log.
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Action {
+    CurrentRound,
+    ListProvers,
+    KickProver { peer_addr: SocketAddr },
+    ListBans,
+    Ban { ip: IpAddr, seconds: u64 },
+    Unban { ip: IpAddr },
+    GetTargetSettings,
+    SetTargetSettings { settings: TargetSettings },
+    RefreshPuzzle,
+    SavePplns,
+    PayoutStatus,
+    PausePayouts,
+    ResumePayouts,
+    ListPendingTransfers,
+    RefundTransfer { id: i32 },
+}
+
+/// Response of an action, or its status and error message.
+type ActionResult = std::result::Result<Value, (StatusCode, String)>;
+
+#[derive(Deserialize)]
+struct BanRequest {
+    ip: IpAddr,
+    /// An hour by default
+    seconds: Option<u64>,
+}
+
+/// Where an admin request comes from. Behind a reverse proxy, `remote` is the proxy and the client
+/// is in `forwarded_for`, which is only as trustworthy as the proxy.
+struct Caller {
+    remote: Option<SocketAddr>,
+    forwarded_for: Option<String>,
+    token: Option<String>,
+}
+
+/// Refused requests of the current minute.
+#[derive(Default)]
+struct Refused {
+    minute: u64,
+    recorded: u32,
+    suppressed: u64,
+}
+
+/// Admin actions and who made them, appended to a file that is never rotated by the pool. Refused
+/// requests are capped per minute, so anyone reaching the port can not fill the disk.
+struct AuditLog {
+    file: Mutex<File>,
+    refused: Mutex<Refused>,
+}
+
+impl AuditLog {
+    fn open(path: &Path) -> Result<AuditLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            file: Mutex::new(file),
+            refused: Default::default(),
+        })
+    }
+
+    fn record(&self, caller: &Caller, action: &Action, result: std::result::Result<(), &str>) {
+        let mut entry = serde_json::to_value(action).unwrap_or_else(|_| json!({}));
+        entry["timestamp"] = json!(now_unix_secs());
+        entry["remote"] = json!(caller.remote);
+        entry["forwarded_for"] = json!(caller.forwarded_for);
+        entry["result"] = match result {
+            Ok(()) => json!("ok"),
+            Err(e) => json!(e),
+        };
+        self.write(&entry);
+    }
+
+    /// Records a request refused before authentication, returns whether it was written rather than
+    /// only counted. The count of the ones left out is written with the first refused request of a
+    /// later minute.
+    fn record_refused(&self, caller: &Caller, action: &Action, message: &str) -> bool {
+        let now = now_unix_secs();
+        let mut refused = self.refused.lock();
+        if refused.minute != now / 60 {
+            if refused.suppressed > 0 {
+                self.write(&json!({
+                    "action": "refused_requests_suppressed",
+                    "count": refused.suppressed,
+                    "timestamp": now,
+                }));
+            }
+            *refused = Refused {
+                minute: now / 60,
+                ..Default::default()
+            };
+        }
+        if refused.recorded >= MAX_REFUSED_PER_MINUTE {
+            refused.suppressed += 1;
+            return false;
+        }
+        refused.recorded += 1;
+        drop(refused);
+        self.record(caller, action, Err(message));
+        true
+    }
+
+    fn write(&self, entry: &Value) {
+        if let Err(e) = writeln!(self.file.lock(), "{}", entry) {
+            error!("Unable to write to the admin audit log: {}", e);
+        }
+    }
+}
+
+/// Authenticated control of the pool. Requests carry `Authorization: Bearer <token>` with the
+/// token from `ADMIN_TOKEN`; without it set, every admin route is refused.
+pub struct Admin<N: PoolNetwork> {
+    token: Option<String>,
+    audit_log: AuditLog,
+    server: Arc<Server<N>>,
+    accounting: Arc<Accounting>,
+    store: Arc<dyn PoolStore>,
+    payouts_paused: Arc<AtomicBool>,
+}
+
+impl<N: PoolNetwork> Admin<N> {
+    pub fn init(
+        server: Arc<Server<N>>,
+        accounting: Arc<Accounting>,
+        store: Arc<dyn PoolStore>,
+        payouts_paused: Arc<AtomicBool>,
+        data_dir: &Path,
+    ) -> Result<Arc<Admin<N>>> {
+        let token = std::env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
+        if token.is_none() {
+            warn!("ADMIN_TOKEN is not set, the admin API is disabled");
+        }
+        Ok(Arc::new(Admin {
+            token,
+            audit_log: AuditLog::open(&data_dir.join(AUDIT_LOG_FILE))?,
+            server,
+            accounting,
+            store,
+            payouts_paused,
+        }))
+    }
+
+    fn authenticate(&self, caller: &Caller) -> std::result::Result<(), (StatusCode, &'static str)> {
+        let Some(token) = &self.token else {
+            return Err((StatusCode::FORBIDDEN, "admin API disabled"));
+        };
+        match &caller.token {
+            Some(given) if token_matches(token, given) => Ok(()),
+            _ => Err((StatusCode::UNAUTHORIZED, "unauthorized")),
+        }
+    }
+
+    async fn run(&self, action: &Action) -> ActionResult {
+        match action {
+            Action::CurrentRound => Ok(self.accounting.current_round().await),
+            Action::ListProvers => Ok(json!({ "provers": self.server.prover_list().await })),
+            Action::KickProver { peer_addr } => match self.server.kick(*peer_addr).await {
+                true => Ok(json!({ "kicked": [peer_addr] })),
+                false => Err((StatusCode::NOT_FOUND, "prover not connected".to_string())),
+            },
+            Action::ListBans => {
+                let bans = self
+                    .server
+                    .bans()
+                    .await
+                    .into_iter()
+                    .map(|(ip, until)| json!({ "ip": ip, "until": until }))
+                    .collect::<Vec<_>>();
+                Ok(json!({ "bans": bans }))
+            }
+            Action::Ban { ip, seconds } => {
+                if *seconds > MAX_BAN_DURATION.as_secs() {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        format!("bans last at most {} seconds", MAX_BAN_DURATION.as_secs()),
+                    ));
+                }
+                let kicked = self.server.ban(*ip, Duration::from_secs(*seconds)).await;
+                Ok(json!({ "ip": ip, "until": now_unix_secs().saturating_add(*seconds), "kicked": kicked }))
+            }
+            Action::Unban { ip } => match self.server.unban(*ip).await {
+                true => Ok(json!({ "ip": ip })),
+                false => Err((StatusCode::NOT_FOUND, "not banned".to_string())),
+            },
+            Action::GetTargetSettings => Ok(json!(self.server.target_settings().await)),
+            Action::SetTargetSettings { settings } => self
+                .server
+                .set_target_settings(*settings)
+                .await
+                .map(|_| json!(settings))
+                .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string())),
+            Action::RefreshPuzzle => self
+                .server
+                .refresh_puzzle()
+                .await
+                .map(|_| json!({}))
+                .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, e.to_string())),
+            Action::SavePplns => self.accounting.save().await.map(|_| json!({})).map_err(|e| {
+                error!("Unable to save the payout model: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+            }),
+            Action::PayoutStatus => Ok(json!({ "paused": self.payouts_paused.load(Ordering::SeqCst) })),
+            Action::PausePayouts | Action::ResumePayouts => {
+                let paused = matches!(action, Action::PausePayouts);
+                self.payouts_paused.store(paused, Ordering::SeqCst);
+                info!("Payouts {}", if paused { "paused" } else { "resumed" });
+                Ok(json!({ "paused": paused }))
+            }
+            Action::ListPendingTransfers => match self.store.get_pending_transfers().await {
+                Ok(transfers) => Ok(json!({ "transfers": transfers })),
+                Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+            },
+            Action::RefundTransfer { id } => {
+                let transfers = self
+                    .store
+                    .get_pending_transfers()
+                    .await
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                let Some(transfer) = transfers.into_iter().find(|transfer| transfer.id == *id) else {
+                    return Err((StatusCode::NOT_FOUND, "transfer not pending".to_string()));
+                };
+                self.store
+                    .finish_transfer(*id, false)
+                    .await
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                warn!(
+                    "Transfer {} of {} to {} refunded, the amount is unpaid again",
+                    transfer.transaction_id, transfer.amount, transfer.address
+                );
+                Ok(json!(transfer))
+            }
+        }
+    }
+}
+
+/// Compares in time independent of where the strings differ, so the token can not be guessed a
+/// byte at a time.
+fn token_matches(token: &str, given: &str) -> bool {
+    token.len() == given.len() && token.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn caller() -> impl Filter<Extract = (Caller,), Error = Rejection> + Clone {
+    remote()
+        .and(header::optional::<String>("authorization"))
+        .and(header::optional::<String>("x-forwarded-for"))
+        .map(|remote, authorization: Option<String>, forwarded_for| {
+            let token = authorization
+                .as_deref()
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(|token| token.trim().to_string());
+            Caller {
+                remote,
+                forwarded_for,
+                token,
+            }
+        })
+}
+
+/// Every admin route. The caller is authenticated before the action runs, and the action is
+/// written to the audit log whether it was allowed or not.
+pub fn routes<N: PoolNetwork>(admin: Arc<Admin<N>>) -> BoxedFilter<(reply::Response,)> {
+    let current_round = get().and(path!("admin" / "current_round")).map(|| Action::CurrentRound);
+    let list_provers = get().and(path!("admin" / "provers")).map(|| Action::ListProvers);
+    let kick_prover = post()
+        .and(path!("admin" / "provers" / SocketAddr / "kick"))
+        .map(|peer_addr| Action::KickProver { peer_addr });
+    let list_bans = get().and(path!("admin" / "bans")).map(|| Action::ListBans);
+    let ban = post()
+        .and(path!("admin" / "bans"))
+        .and(body::content_length_limit(4096))
+        .and(body::json())
+        .map(|request: BanRequest| Action::Ban {
+            ip: request.ip,
+            seconds: request.seconds.unwrap_or(BAN_DURATION.as_secs()),
+        });
+    let unban = delete().and(path!("admin" / "bans" / IpAddr)).map(|ip| Action::Unban { ip });
+    let get_target_settings = get().and(path!("admin" / "target")).map(|| Action::GetTargetSettings);
+    let set_target_settings = post()
+        .and(path!("admin" / "target"))
+        .and(body::content_length_limit(4096))
+        .and(body::json())
+        .map(|settings: TargetSettings| Action::SetTargetSettings { settings });
+    let refresh_puzzle = post().and(path!("admin" / "puzzle" / "refresh")).map(|| Action::RefreshPuzzle);
+    let save_pplns = post().and(path!("admin" / "pplns" / "save")).map(|| Action::SavePplns);
+    let payout_status = get().and(path!("admin" / "payouts")).map(|| Action::PayoutStatus);
+    let pause_payouts = post().and(path!("admin" / "payouts" / "pause")).map(|| Action::PausePayouts);
+    let resume_payouts = post().and(path!("admin" / "payouts" / "resume")).map(|| Action::ResumePayouts);
+    let list_pending_transfers = get()
+        .and(path!("admin" / "transfers"))
+        .map(|| Action::ListPendingTransfers);
+    let refund_transfer = post()
+        .and(path!("admin" / "transfers" / i32 / "refund"))
+        .map(|id| Action::RefundTransfer { id });
+
+    current_round
+        .or(list_provers)
+        .unify()
+        .or(kick_prover)
+        .unify()
+        .or(list_bans)
+        .unify()
+        .or(ban)
+        .unify()
+        .or(unban)
+        .unify()
+        .or(get_target_settings)
+        .unify()
+        .or(set_target_settings)
+        .unify()
+        .or(refresh_puzzle)
+        .unify()
+        .or(save_pplns)
+        .unify()
+        .or(payout_status)
+        .unify()
+        .or(pause_payouts)
+        .unify()
+        .or(resume_payouts)
+        .unify()
+        .or(list_pending_transfers)
+        .unify()
+        .or(refund_transfer)
+        .unify()
+        .and(caller())
+        .and(warp::any().map(move || admin.clone()))
+        .then(handle::<N>)
+        .boxed()
+}
+
+async fn handle<N: PoolNetwork>(action: Action, caller: Caller, admin: Arc<Admin<N>>) -> reply::Response {
+    if let Err((status, message)) = admin.authenticate(&caller) {
+        if admin.audit_log.record_refused(&caller, &action, message) {
+            warn!("Refused admin request {:?} from {:?}: {}", action, caller.remote, message);
+        }
+        return reply::with_status(json(&json!({ "error": message })), status).into_response();
+    }
+    let result = admin.run(&action).await;
+    admin
+        .audit_log
+        .record(&caller, &action, result.as_ref().map(|_| ()).map_err(|(_, e)| e.as_str()));
+    match result {
+        Ok(value) => {
+            info!("Admin action {:?} from {:?}", action, caller.remote);
+            json(&value).into_response()
+        }
+        Err((status, message)) => reply::with_status(json(&json!({ "error": message })), status).into_response(),
+    }
+}
+
+fn now_unix_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
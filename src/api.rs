@@ -1,54 +1,149 @@
+use std::{convert::Infallible, str::FromStr, sync::Arc, time::Duration};
 
-use std::{convert::Infallible, net::SocketAddr, sync::Arc};
-
-use serde_json::json;
-use snarkvm::console::account::Address;
+use futures::{future::ready, stream, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use snarkvm::console::account::{Address, Signature};
 use tokio::task;
-use tracing::info;
+use tracing::{error, info};
 use warp::{
-    addr::remote,
+    body,
     get,
     head,
+    http::StatusCode,
     path,
+    post,
+    query,
     reply,
     reply::{json, Json},
     serve,
+    sse,
     Filter,
     Reply,
 };
 
-use crate::{Accounting, Server, N};
+use crate::{
+    admin::{self, Admin},
+    events::{Events, PoolEvent},
+    network::PoolNetwork,
+    speed_history::{SpeedHistory, POOL_SERIES},
+    store::{PoolStore, ProverSettings, SolutionRecord},
+    Accounting,
+    Server,
+};
+
+/// How far the timestamp of signed settings may be from the pool clock.
+static SETTINGS_MAX_SKEW: Duration = Duration::from_secs(10 * 60);
+
+/// Rows returned by the paginated endpoints unless asked for fewer.
+const PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 500;
 
-pub fn start(port: u16, accounting: Arc<Accounting>, server: Arc<Server>) {
+/// Longest period served by the history endpoints, in hours.
+const MAX_HISTORY_HOURS: u64 = 24 * 30;
+
+pub fn start<N: PoolNetwork>(
+    port: u16,
+    accounting: Arc<Accounting>,
+    server: Arc<Server<N>>,
+    store: Arc<dyn PoolStore>,
+    speed_history: Arc<SpeedHistory>,
+    events: Events,
+    admin: Arc<Admin<N>>,
+) {
     task::spawn(async move {
         let current_round = path("current_round")
             .and(use_accounting(accounting.clone()))
             .then(current_round)
             .boxed();
 
-        let pool_stats = path("stats").and(use_server(server.clone())).then(pool_stats).boxed();
+        let pool_stats = path("stats").and(use_server(server.clone())).then(pool_stats::<N>).boxed();
+
+        let pool_history = path!("stats" / "history")
+            .and(query::<HistoryQuery>())
+            .and(use_speed_history(speed_history.clone()))
+            .then(pool_history)
+            .boxed();
+
+        let address_history = path!("stats" / String / "history")
+            .and(query::<HistoryQuery>())
+            .and(use_speed_history(speed_history.clone()))
+            .then(address_history::<N>)
+            .boxed();
 
         let address_stats = path!("stats" / String)
             .and(use_server(server.clone()))
-            .then(address_stats)
+            .then(address_stats::<N>)
             .boxed();
 
-        let admin_current_round = path!("admin" / "current_round")
-            .and(remote())
-            .and(use_accounting(accounting.clone()))
-            .then(admin_current_round)
+        let prover_settings = path!("settings" / String)
+            .and(use_store(store.clone()))
+            .then(prover_settings)
+            .boxed();
+
+        let address_balance = path!("balance" / String)
+            .and(use_store(store.clone()))
+            .then(address_balance::<N>)
+            .boxed();
+
+        let address_payouts = path!("payouts" / String)
+            .and(query::<Page>())
+            .and(use_store(store.clone()))
+            .then(address_payouts::<N>)
+            .boxed();
+
+        let address_shares = path!("shares" / String)
+            .and(query::<Page>())
+            .and(use_store(store.clone()))
+            .then(address_shares::<N>)
+            .boxed();
+
+        let solutions = path!("solutions")
+            .and(query::<Page>())
+            .and(use_store(store.clone()))
+            .then(solutions)
+            .boxed();
+
+        let event_stream = path!("events")
+            .and(query::<EventQuery>())
+            .and(use_events(events))
+            .then(event_stream::<N>)
+            .boxed();
+
+        let metrics = path!("metrics").and(use_server(server.clone())).then(metrics::<N>).boxed();
+
+        let totals = path!("totals").and(use_store(store.clone())).then(totals).boxed();
+
+        let set_prover_settings = post()
+            .and(path!("settings" / String))
+            .and(body::content_length_limit(4096))
+            .and(body::json())
+            .and(use_store(store.clone()))
+            .and(use_server(server.clone()))
+            .then(set_prover_settings::<N>)
             .boxed();
 
         let endpoints = current_round
+            .or(pool_history)
+            .or(address_history)
             .or(address_stats)
             .or(pool_stats)
-            .or(admin_current_round)
+            .or(prover_settings)
+            .or(address_balance)
+            .or(address_payouts)
+            .or(address_shares)
+            .or(solutions)
+            .or(totals)
+            .or(metrics)
+            .or(event_stream)
             .boxed();
 
         let routes = get()
             .or(head())
             .unify()
             .and(endpoints)
+            .or(set_prover_settings)
+            .or(admin::routes(admin))
             .with(warp::log("aleo_pool_server::api"));
         info!("Starting API server on port {}", port);
         serve(routes).run(([0, 0, 0, 0], port)).await;
@@ -60,19 +155,41 @@ fn use_accounting(
 ) -> impl Filter<Extract = (Arc<Accounting>,), Error = Infallible> + Clone {
     warp::any().map(move || accounting.clone())
 }
-fn use_server(server: Arc<Server>) -> impl Filter<Extract = (Arc<Server>,), Error = Infallible> + Clone {
+fn use_server<N: PoolNetwork>(
+    server: Arc<Server<N>>,
+) -> impl Filter<Extract = (Arc<Server<N>>,), Error = Infallible> + Clone {
     warp::any().map(move || server.clone())
 }
+fn use_store(
+    store: Arc<dyn PoolStore>,
+) -> impl Filter<Extract = (Arc<dyn PoolStore>,), Error = Infallible> + Clone {
+    warp::any().map(move || store.clone())
+}
+
+fn use_speed_history(
+    speed_history: Arc<SpeedHistory>,
+) -> impl Filter<Extract = (Arc<SpeedHistory>,), Error = Infallible> + Clone {
+    warp::any().map(move || speed_history.clone())
+}
 
-async fn pool_stats(server: Arc<Server>) -> Json {
+fn use_events(events: Events) -> impl Filter<Extract = (Events,), Error = Infallible> + Clone {
+    warp::any().map(move || events.clone())
+}
+
+fn error_reply(message: &str, status: StatusCode) -> reply::WithStatus<Json> {
+    reply::with_status(json(&json!({ "error": message })), status)
+}
+
+async fn pool_stats<N: PoolNetwork>(server: Arc<Server<N>>) -> Json {
     json(&json!({
         "online_addresses": server.online_addresses().await,
         "online_provers": server.online_provers().await,
         "speed": server.pool_speed().await,
+        "puzzle_delay": server.puzzle_delay().await,
     }))
 }
 
-async fn address_stats(address: String, server: Arc<Server>) -> impl Reply {
+async fn address_stats<N: PoolNetwork>(address: String, server: Arc<Server<N>>) -> impl Reply {
     if let Ok(address) = address.parse::<Address<N>>() {
         let speed = server.address_speed(address).await;
         let prover_count = server.address_prover_count(address).await;
@@ -93,6 +210,78 @@ async fn address_stats(address: String, server: Arc<Server>) -> impl Reply {
     }
 }
 
+/// Query of the history endpoints.
+#[derive(Deserialize)]
+struct HistoryQuery {
+    /// Period to return, 24 hours by default. Up to 24 hours come per minute, longer ones per hour.
+    hours: Option<u64>,
+}
+
+impl HistoryQuery {
+    fn period(&self) -> Duration {
+        Duration::from_secs(self.hours.unwrap_or(24).clamp(1, MAX_HISTORY_HOURS) * 60 * 60)
+    }
+}
+
+fn history_reply(speed_history: &SpeedHistory, series: &str, query: &HistoryQuery) -> Json {
+    let (resolution, points) = speed_history.points(series, query.period());
+    json(&json!({
+        "resolution": resolution,
+        "points": points,
+    }))
+}
+
+async fn pool_history(query: HistoryQuery, speed_history: Arc<SpeedHistory>) -> Json {
+    history_reply(&speed_history, POOL_SERIES, &query)
+}
+
+async fn address_history<N: PoolNetwork>(
+    address: String,
+    query: HistoryQuery,
+    speed_history: Arc<SpeedHistory>,
+) -> impl Reply {
+    if Address::<N>::from_str(&address).is_err() {
+        return error_reply("invalid address", StatusCode::BAD_REQUEST);
+    }
+    reply::with_status(history_reply(&speed_history, &address, &query), StatusCode::OK)
+}
+
+async fn metrics<N: PoolNetwork>(server: Arc<Server<N>>) -> reply::Response {
+    match server.metrics().await {
+        Ok(metrics) => reply::with_header(metrics, "content-type", "text/plain; version=0.0.4").into_response(),
+        Err(e) => {
+            error!("Unable to encode metrics: {}", e);
+            error_reply("internal error", StatusCode::INTERNAL_SERVER_ERROR).into_response()
+        }
+    }
+}
+
+/// Query of the event stream.
+#[derive(Deserialize)]
+struct EventQuery {
+    /// Only the events of this address, and those of the whole pool
+    address: Option<String>,
+}
+
+/// Server-sent events, named after their `type`, with the event as JSON data.
+async fn event_stream<N: PoolNetwork>(query: EventQuery, events: Events) -> reply::Response {
+    if let Some(address) = &query.address {
+        if Address::<N>::from_str(address).is_err() {
+            return error_reply("invalid address", StatusCode::BAD_REQUEST).into_response();
+        }
+    }
+    let events = stream::unfold(events.subscribe(), |mut subscription| async move {
+        let event = subscription.next().await?;
+        Some((event, subscription))
+    })
+    .filter(move |event: &PoolEvent| {
+        let address = event.address();
+        ready(query.address.is_none() || address.is_none() || address == query.address.as_deref())
+    })
+    .map(|event| sse::Event::default().event(event.name()).json_data(&event));
+    sse::reply(sse::keep_alive().stream(events)).into_response()
+}
+
 async fn current_round(accounting: Arc<Accounting>) -> Json {
     let data = accounting.current_round().await;
 
@@ -103,12 +292,218 @@ async fn current_round(accounting: Arc<Accounting>) -> Json {
     }))
 }
 
-async fn admin_current_round(addr: Option<SocketAddr>, accounting: Arc<Accounting>) -> impl Reply {
-    let addr = addr.unwrap();
-    if addr.ip().is_loopback() {
-        let pplns = accounting.current_round().await;
-        reply::with_status(json(&pplns), warp::http::StatusCode::OK)
-    } else {
-        reply::with_status(json(&"Method Not Allowed"), warp::http::StatusCode::METHOD_NOT_ALLOWED)
+/// Query of the paginated endpoints, rows are returned newest first.
+#[derive(Deserialize)]
+struct Page {
+    /// Only rows with an id below this one, the `next` of the previous page
+    before: Option<i32>,
+    limit: Option<u32>,
+}
+
+impl Page {
+    fn before(&self) -> i32 {
+        self.before.unwrap_or(i32::MAX)
+    }
+
+    fn limit(&self) -> u32 {
+        self.limit.unwrap_or(PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    }
+
+    /// Cursor of the page after one ending at `last`, none if this one was not full.
+    fn next(&self, count: usize, last: Option<i32>) -> Option<i32> {
+        last.filter(|_| count as u32 >= self.limit())
+    }
+}
+
+fn solution_json(solution: &SolutionRecord) -> Value {
+    json!({
+        "solution_id": solution.solution_id,
+        "state": solution.state.as_str(),
+        "height": solution.height,
+        "reward": solution.reward,
+        "timestamp": solution.timestamp,
+    })
+}
+
+async fn address_balance<N: PoolNetwork>(address: String, store: Arc<dyn PoolStore>) -> impl Reply {
+    if Address::<N>::from_str(&address).is_err() {
+        return error_reply("invalid address", StatusCode::BAD_REQUEST);
+    }
+    match store.get_balance(&address).await {
+        Ok(balance) => reply::with_status(json(&balance.unwrap_or_default()), StatusCode::OK),
+        Err(e) => {
+            error!("Unable to get the balance of {}: {}", address, e);
+            error_reply("internal error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn address_payouts<N: PoolNetwork>(address: String, page: Page, store: Arc<dyn PoolStore>) -> impl Reply {
+    if Address::<N>::from_str(&address).is_err() {
+        return error_reply("invalid address", StatusCode::BAD_REQUEST);
+    }
+    match store.get_address_payouts(&address, page.before(), page.limit()).await {
+        Ok(payouts) => {
+            let next = page.next(payouts.len(), payouts.last().map(|payout| payout.id));
+            reply::with_status(json(&json!({ "payouts": payouts, "next": next })), StatusCode::OK)
+        }
+        Err(e) => {
+            error!("Unable to get the payouts of {}: {}", address, e);
+            error_reply("internal error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Solutions the address has a share in. A page can hold fewer than `limit` of them, as the
+/// solutions it has no share in are counted but left out.
+async fn address_shares<N: PoolNetwork>(address: String, page: Page, store: Arc<dyn PoolStore>) -> impl Reply {
+    if Address::<N>::from_str(&address).is_err() {
+        return error_reply("invalid address", StatusCode::BAD_REQUEST);
     }
-}
\ No newline at end of file
+    match store.get_address_shares(&address, page.before(), page.limit()).await {
+        Ok(shares) => {
+            let next = page.next(shares.len(), shares.last().map(|share| share.solution.id));
+            let shares = shares
+                .iter()
+                .filter(|share| share.share > 0)
+                .map(|share| {
+                    let mut solution = solution_json(&share.solution);
+                    solution["share"] = json!(share.share);
+                    solution["total_shares"] = json!(share.total);
+                    solution
+                })
+                .collect::<Vec<_>>();
+            reply::with_status(json(&json!({ "solutions": shares, "next": next })), StatusCode::OK)
+        }
+        Err(e) => {
+            error!("Unable to get the shares of {}: {}", address, e);
+            error_reply("internal error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn solutions(page: Page, store: Arc<dyn PoolStore>) -> impl Reply {
+    match store.get_recent_solutions(page.before(), page.limit()).await {
+        Ok(solutions) => {
+            let next = page.next(solutions.len(), solutions.last().map(|solution| solution.id));
+            let solutions = solutions.iter().map(solution_json).collect::<Vec<_>>();
+            reply::with_status(json(&json!({ "solutions": solutions, "next": next })), StatusCode::OK)
+        }
+        Err(e) => {
+            error!("Unable to get the solutions: {}", e);
+            error_reply("internal error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn totals(store: Arc<dyn PoolStore>) -> impl Reply {
+    match store.get_stats().await {
+        Ok(stats) => reply::with_status(
+            json(&json!({
+                "total_paid": stats.get("total_paid").copied().unwrap_or_default(),
+                "total_fee": stats.get("total_fee").copied().unwrap_or_default(),
+            })),
+            StatusCode::OK,
+        ),
+        Err(e) => {
+            error!("Unable to get the pool totals: {}", e);
+            error_reply("internal error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Payout settings of a prover, signed with the private key of its mining address.
+#[derive(Deserialize)]
+struct SignedSettings {
+    minimum_payout: Option<u64>,
+    payout_address: Option<String>,
+    #[serde(default)]
+    donation_bps: u64,
+    referrer: Option<String>,
+    /// Unix seconds, settings only replace older ones
+    timestamp: u64,
+    signature: String,
+}
+
+/// The message provers sign, absent settings are left empty. The network and the pool address keep
+/// a signature from being replayed on another pool or network.
+fn settings_message<N: PoolNetwork>(pool_address: &Address<N>, address: &str, settings: &ProverSettings) -> String {
+    format!(
+        "aleo pool settings\nnetwork: {}\npool: {}\naddress: {}\nminimum payout: {}\npayout address: {}\n\
+         donation bps: {}\nreferrer: {}\ntimestamp: {}",
+        N::ID,
+        pool_address,
+        address,
+        settings.minimum_payout.map(|minimum| minimum.to_string()).unwrap_or_default(),
+        settings.payout_address.as_deref().unwrap_or_default(),
+        settings.donation_bps,
+        settings.referrer.as_deref().unwrap_or_default(),
+        settings.timestamp,
+    )
+}
+
+async fn prover_settings(address: String, store: Arc<dyn PoolStore>) -> impl Reply {
+    match store.get_prover_settings(&address).await {
+        Ok(settings) => reply::with_status(json(&settings.unwrap_or_default()), StatusCode::OK),
+        Err(e) => {
+            error!("Unable to get the settings of {}: {}", address, e);
+            error_reply("internal error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn set_prover_settings<N: PoolNetwork>(
+    address: String,
+    request: SignedSettings,
+    store: Arc<dyn PoolStore>,
+    server: Arc<Server<N>>,
+) -> impl Reply {
+    let Ok(signer) = Address::<N>::from_str(&address) else {
+        return error_reply("invalid address", StatusCode::BAD_REQUEST);
+    };
+    if let Some(payout_address) = &request.payout_address {
+        if Address::<N>::from_str(payout_address).is_err() {
+            return error_reply("invalid payout address", StatusCode::BAD_REQUEST);
+        }
+    }
+    if request.donation_bps > 10000 {
+        return error_reply("donation above 10000 bps", StatusCode::BAD_REQUEST);
+    }
+    if let Some(referrer) = &request.referrer {
+        if Address::<N>::from_str(referrer).map_or(true, |referrer| referrer == signer) {
+            return error_reply("invalid referrer", StatusCode::BAD_REQUEST);
+        }
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    if request.timestamp.abs_diff(now) > SETTINGS_MAX_SKEW.as_secs() {
+        return error_reply("timestamp too far from the current time", StatusCode::BAD_REQUEST);
+    }
+    let settings = ProverSettings {
+        minimum_payout: request.minimum_payout,
+        payout_address: request.payout_address,
+        donation_bps: request.donation_bps,
+        referrer: request.referrer,
+        timestamp: request.timestamp,
+    };
+    let message = settings_message(&server.pool_address(), &address, &settings);
+    let verified = Signature::<N>::from_str(&request.signature)
+        .map(|signature| signature.verify_bytes(&signer, message.as_bytes()))
+        .unwrap_or(false);
+    if !verified {
+        return error_reply("invalid signature", StatusCode::UNAUTHORIZED);
+    }
+    match store.set_prover_settings(&address, &settings).await {
+        Ok(true) => {
+            info!("Updated payout settings of {}", address);
+            reply::with_status(json(&settings), StatusCode::OK)
+        }
+        Ok(false) => error_reply("newer settings already stored", StatusCode::CONFLICT),
+        Err(e) => {
+            error!("Unable to set the settings of {}: {}", address, e);
+            error_reply("internal error", StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
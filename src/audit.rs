@@ -0,0 +1,202 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::{
+    accounting::{distribute_reward, Payout},
+    payout_model::{self, PayoutModelKind, PayoutSettings},
+    share_log,
+    store::{PoolStore, SolutionState},
+};
+
+/// Solutions read from the store at a time
+const PAGE_SIZE: u32 = 500;
+
+/// (address, share or payout kind) -> amount
+type Amounts = BTreeMap<(String, &'static str), u64>;
+
+/// An amount recorded for a solution that is not the one the audit comes to.
+struct Discrepancy {
+    address: String,
+    /// "share" for the window, the payout kind otherwise
+    what: &'static str,
+    expected: u64,
+    recorded: u64,
+}
+
+fn window_amounts(window: &HashMap<String, u64>) -> Amounts {
+    window
+        .iter()
+        .map(|(address, share)| ((address.clone(), "share"), *share))
+        .collect()
+}
+
+fn payout_amounts(payouts: &[Payout]) -> Amounts {
+    let mut amounts = Amounts::new();
+    for payout in payouts {
+        *amounts.entry((payout.address.clone(), payout.kind.as_str())).or_default() += payout.amount;
+    }
+    amounts
+}
+
+fn compare(expected: &Amounts, recorded: &Amounts, found: &mut Vec<Discrepancy>) {
+    for key in expected.keys().chain(recorded.keys().filter(|key| !expected.contains_key(*key))) {
+        let expected = expected.get(key).copied().unwrap_or_default();
+        let recorded = recorded.get(key).copied().unwrap_or_default();
+        if expected != recorded {
+            found.push(Discrepancy {
+                address: key.0.clone(),
+                what: key.1,
+                expected,
+                recorded,
+            });
+        }
+    }
+}
+
+/// Checks the recorded solutions against the share log, and logs where they disagree.
+///
+/// The windows of the solutions found since the oldest archived snapshot are rebuilt from the share
+/// log and compared with the stored ones. The distribution of every mature solution is recomputed
+/// from its stored window the way `Accounting` does, and compared with its payout rows. With `prover`,
+/// the amounts of that address are logged for every solution, matching or not.
+///
+/// Returns whether everything matched.
+pub async fn run(
+    store: Arc<dyn PoolStore>,
+    settings: &PayoutSettings,
+    data_dir: &Path,
+    solution: Option<&str>,
+    prover: Option<&str>,
+) -> Result<bool> {
+    let windows = match settings.kind {
+        PayoutModelKind::Pplnt => {
+            info!("PPLNT windows depend on the time they are taken at, they are not replayed");
+            HashMap::new()
+        }
+        _ => payout_model::new(settings)
+            .and_then(|model| share_log::replay_windows(model, data_dir))
+            .unwrap_or_else(|e| {
+                warn!("Share windows are not replayed: {}", e);
+                HashMap::new()
+            }),
+    };
+    info!("Distributions are recomputed with the current fee settings and prover options");
+
+    let mut reward_model = payout_model::new(settings)?;
+    let mut audited = 0;
+    let mut replayed = 0;
+    let mut recomputed = 0;
+    // address -> (expected, recorded) payouts of the solutions with a discrepancy
+    let mut totals = BTreeMap::<String, (u64, u64)>::new();
+    let mut discrepancies = 0;
+    let mut after = 0;
+    loop {
+        let solutions = store.get_solutions(after, PAGE_SIZE).await?;
+        let Some(last) = solutions.last() else {
+            break;
+        };
+        after = last.id;
+        for record in solutions {
+            if solution.is_some_and(|solution_id| solution_id != record.solution_id) {
+                continue;
+            }
+            audited += 1;
+            let stored = store.get_solution_shares(record.id).await?;
+            let mut expected = Amounts::new();
+            let mut recorded = Amounts::new();
+            let mut found = vec![];
+
+            if let Some(window) = windows.get(&record.solution_id) {
+                replayed += 1;
+                let (window, stored) = (window_amounts(window), window_amounts(&stored));
+                compare(&window, &stored, &mut found);
+                expected.extend(window);
+                recorded.extend(stored);
+            }
+
+            if record.state == SolutionState::Mature {
+                recomputed += 1;
+                let reward = record.reward.unwrap_or_default();
+                let credited = reward_model.solution_reward(reward);
+                reward_model.solution_matured(reward);
+                let distribution = match credited {
+                    0 => vec![],
+                    reward => {
+                        let addresses = stored.keys().cloned().collect::<Vec<_>>();
+                        let options = store.get_prover_options(&addresses).await?;
+                        distribute_reward(reward, &stored, &options, &settings.fees)?.payouts
+                    }
+                };
+                let (distribution, payouts) = (
+                    payout_amounts(&distribution),
+                    payout_amounts(&store.get_solution_payouts(record.id).await?),
+                );
+                compare(&distribution, &payouts, &mut found);
+                expected.extend(distribution);
+                recorded.extend(payouts);
+            }
+
+            if let Some(prover) = prover {
+                found.retain(|discrepancy| discrepancy.address == prover);
+                let total = stored.values().sum::<u64>();
+                info!(
+                    "Solution {} ({}): {} of {} shares",
+                    record.solution_id,
+                    record.state.as_str(),
+                    stored.get(prover).copied().unwrap_or_default(),
+                    total
+                );
+                for ((address, what), amount) in &expected {
+                    if address == prover {
+                        let recorded = recorded.get(&(address.clone(), *what)).copied().unwrap_or_default();
+                        info!(
+                            "Solution {} {}: expected {}, recorded {}",
+                            record.solution_id, what, amount, recorded
+                        );
+                    }
+                }
+            }
+            if found.is_empty() {
+                continue;
+            }
+            for discrepancy in &found {
+                warn!(
+                    "Solution {} ({}) {} {}: expected {}, recorded {}",
+                    record.solution_id,
+                    record.state.as_str(),
+                    discrepancy.address,
+                    discrepancy.what,
+                    discrepancy.expected,
+                    discrepancy.recorded
+                );
+                if discrepancy.what != "share" {
+                    let total = totals.entry(discrepancy.address.clone()).or_default();
+                    total.0 += discrepancy.expected;
+                    total.1 += discrepancy.recorded;
+                }
+            }
+            discrepancies += found.len();
+        }
+    }
+
+    for (address, (expected, recorded)) in &totals {
+        warn!(
+            "Payouts of {} in the solutions above: expected {}, recorded {} ({:+})",
+            address,
+            expected,
+            recorded,
+            *recorded as i128 - *expected as i128
+        );
+    }
+    info!(
+        "Audited {} solutions, {} windows replayed, {} distributions recomputed, {} discrepancies",
+        audited, replayed, recomputed, discrepancies
+    );
+    Ok(discrepancies == 0)
+}
@@ -11,7 +11,7 @@ use aleo_stratum::{
 use anyhow::{anyhow, Result};
 use futures_util::SinkExt;
 use semver::Version;
-use snarkvm::console::account::Address;
+use snarkvm::{console::account::Address, prelude::Network};
 use tokio::{
     net::TcpStream,
     sync::mpsc::{channel, Sender},
@@ -20,11 +20,11 @@ use tokio::{
 };
 use tokio_stream::StreamExt;
 use tokio_util::codec::Framed;
-use tracing::{error, info, trace, warn};
+use tracing::{debug, error, info, trace, warn};
 
-use crate::{server::ServerMessage, N};
+use crate::server::ServerMessage;
 
-pub struct Connection {
+pub struct Connection<N: Network> {
     user_agent: String,
     address: Option<Address<N>>,
     version: Version,
@@ -37,27 +37,27 @@ static PEER_COMM_TIMEOUT: Duration = Duration::from_secs(180);
 static MIN_SUPPORTED_VERSION: Version = Version::new(3, 0, 0);
 static MAX_SUPPORTED_VERSION: Version = Version::new(3, 0, 0);
 
-impl Connection {
+impl<N: Network> Connection<N> {
     pub async fn init(
         stream: TcpStream,
         peer_addr: SocketAddr,
-        server_sender: Sender<ServerMessage>,
+        server_sender: Sender<ServerMessage<N>>,
         pool_address: Address<N>,
     ) {
-        task::spawn(Connection::run(stream, peer_addr, server_sender, pool_address));
+        task::spawn(Self::run(stream, peer_addr, server_sender, pool_address));
     }
 
     pub async fn run(
         stream: TcpStream,
         peer_addr: SocketAddr,
-        server_sender: Sender<ServerMessage>,
+        server_sender: Sender<ServerMessage<N>>,
         pool_address: Address<N>,
     ) {
         let mut framed = Framed::new(stream, StratumCodec::default());
 
         let (sender, mut receiver) = channel(1024);
 
-        let mut conn = Connection {
+        let mut conn = Self {
             user_agent: "Unknown".to_string(),
             address: None,
             version: Version::new(0, 0, 0),
@@ -66,7 +66,7 @@ impl Connection {
 
         // Handshake
 
-        if let Ok((user_agent, version)) = Connection::handshake(&mut framed, pool_address.to_string()).await {
+        if let Ok((user_agent, version)) = Self::handshake(&mut framed, pool_address.to_string()).await {
             conn.user_agent = user_agent;
             conn.version = version;
         } else {
@@ -76,12 +76,13 @@ impl Connection {
             return;
         }
 
-        if let Ok(address) = Connection::authorize(&mut framed).await {
+        if let Ok((address, donation_bps)) = Self::authorize(&mut framed).await {
             conn.address = Some(address);
             if let Err(e) = server_sender
                 .send(ServerMessage::ProverAuthenticated(
                     peer_addr,
                     conn.address.unwrap(),
+                    donation_bps,
                     sender,
                 ))
                 .await
@@ -101,16 +102,22 @@ impl Connection {
 
         loop {
             tokio::select! {
-                Some(msg) = receiver.recv() => {
-                    if let Some(instant) = conn.last_received {
-                        if instant.elapsed() > PEER_COMM_TIMEOUT {
-                            warn!("Peer {:?} timed out", peer_addr);
-                            break;
+                msg = receiver.recv() => match msg {
+                    Some(msg) => {
+                        if let Some(instant) = conn.last_received {
+                            if instant.elapsed() > PEER_COMM_TIMEOUT {
+                                warn!("Peer {:?} timed out", peer_addr);
+                                break;
+                            }
+                        }
+                        trace!("Sending message {} to peer {:?}", msg.name(), peer_addr);
+                        if let Err(e) = framed.send(msg).await {
+                            error!("Failed to send message to peer {:?}: {:?}", peer_addr, e);
                         }
                     }
-                    trace!("Sending message {} to peer {:?}", msg.name(), peer_addr);
-                    if let Err(e) = framed.send(msg).await {
-                        error!("Failed to send message to peer {:?}: {:?}", peer_addr, e);
+                    None => {
+                        info!("Peer {:?} disconnected by the pool", peer_addr);
+                        break;
                     }
                 },
                 result = framed.next() => match result {
@@ -240,21 +247,23 @@ impl Connection {
         }
     }
 
-    pub async fn authorize(framed: &mut Framed<TcpStream, StratumCodec>) -> Result<Address<N>> {
+    /// Returns the address of the prover and the donation in its password, in basis points.
+    pub async fn authorize(framed: &mut Framed<TcpStream, StratumCodec>) -> Result<(Address<N>, u64)> {
         let peer_addr = framed.get_ref().peer_addr()?;
         match timeout(PEER_HANDSHAKE_TIMEOUT, framed.next()).await {
             Ok(Some(Ok(message))) => {
                 trace!("Received message {} from peer {:?}", message.name(), peer_addr);
                 match message {
-                    StratumMessage::Authorize(id, address, _) => {
+                    StratumMessage::Authorize(id, address, password) => {
                         let address = Address::<N>::from_str(address.as_str()).map_err(|e| {
                             warn!("Invalid address {} from peer {:?}: {:?}", address, peer_addr, e);
                             e
                         })?;
+                        let donation_bps = Self::parse_options(&password, peer_addr);
                         framed
                             .send(StratumMessage::Response(id, Some(ResponseParams::Bool(true)), None))
                             .await?;
-                        Ok(address)
+                        Ok((address, donation_bps))
                     }
                     _ => {
                         warn!("Peer {:?} sent {} before authorizing", peer_addr, message.name());
@@ -276,4 +285,27 @@ impl Connection {
             }
         }
     }
+
+    /// Reads the options in the authorize password, separated by commas, and returns the donation of
+    /// `donate=<percent>` in basis points. Nothing proves the password comes from the owner of the
+    /// address, so the donation only lasts for the session, and referrers are only taken from signed
+    /// settings. Invalid options are ignored.
+    fn parse_options(password: &str, peer_addr: SocketAddr) -> u64 {
+        let mut donation_bps = 0;
+        for option in password.split(',').map(str::trim).filter(|option| !option.is_empty()) {
+            match option.split_once('=') {
+                Some(("donate", percent)) => match percent.parse::<f64>() {
+                    Ok(percent) if (0.0..=100.0).contains(&percent) => {
+                        donation_bps = (percent * 100.0).round() as u64;
+                    }
+                    _ => warn!("Invalid donation {} from peer {:?}", percent, peer_addr),
+                },
+                Some(("ref", _)) => {
+                    debug!("Ignoring referrer from peer {:?}, referrers are set through signed settings", peer_addr);
+                }
+                _ => trace!("Ignoring option {} from peer {:?}", option, peer_addr),
+            }
+        }
+        donation_bps
+    }
 }
@@ -0,0 +1,116 @@
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError, Receiver, Sender};
+
+/// Events a subscriber may fall behind by before it misses some.
+const EVENT_BUFFER: usize = 4096;
+
+/// Something that happened in the pool, pushed to the subscribers of the event stream.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PoolEvent {
+    NewEpoch {
+        epoch_number: u32,
+        proof_target: u64,
+    },
+    SolutionFound {
+        solution_id: String,
+        address: String,
+    },
+    ShareAccepted {
+        address: String,
+        target: u64,
+    },
+    ShareRejected {
+        address: String,
+        reason: &'static str,
+    },
+    ProverConnected {
+        address: String,
+    },
+    ProverDisconnected {
+        address: String,
+    },
+    /// A transfer of the balance of `address` made it into a block
+    PayoutSent {
+        address: String,
+        amount: u64,
+        transaction_id: String,
+    },
+    /// The subscriber fell behind and missed this many events
+    Lagged {
+        missed: u64,
+    },
+}
+
+impl PoolEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PoolEvent::NewEpoch { .. } => "new_epoch",
+            PoolEvent::SolutionFound { .. } => "solution_found",
+            PoolEvent::ShareAccepted { .. } => "share_accepted",
+            PoolEvent::ShareRejected { .. } => "share_rejected",
+            PoolEvent::ProverConnected { .. } => "prover_connected",
+            PoolEvent::ProverDisconnected { .. } => "prover_disconnected",
+            PoolEvent::PayoutSent { .. } => "payout_sent",
+            PoolEvent::Lagged { .. } => "lagged",
+        }
+    }
+
+    /// Address the event is about, none for pool-wide events.
+    pub fn address(&self) -> Option<&str> {
+        match self {
+            PoolEvent::SolutionFound { address, .. }
+            | PoolEvent::ShareAccepted { address, .. }
+            | PoolEvent::ShareRejected { address, .. }
+            | PoolEvent::ProverConnected { address }
+            | PoolEvent::ProverDisconnected { address }
+            | PoolEvent::PayoutSent { address, .. } => Some(address),
+            PoolEvent::NewEpoch { .. } | PoolEvent::Lagged { .. } => None,
+        }
+    }
+}
+
+/// Hands events out to every subscriber. Publishing never waits, events nobody listens to are
+/// dropped.
+#[derive(Clone)]
+pub struct Events {
+    sender: Sender<PoolEvent>,
+}
+
+impl Events {
+    pub fn new() -> Events {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        Events { sender }
+    }
+
+    pub fn publish(&self, event: PoolEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> Subscription {
+        Subscription {
+            receiver: self.sender.subscribe(),
+        }
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Subscription {
+    receiver: Receiver<PoolEvent>,
+}
+
+impl Subscription {
+    /// Next event, or `Lagged` if some were missed. None once the pool shuts down.
+    pub async fn next(&mut self) -> Option<PoolEvent> {
+        match self.receiver.recv().await {
+            Ok(event) => Some(event),
+            Err(RecvError::Lagged(missed)) => Some(PoolEvent::Lagged { missed }),
+            Err(RecvError::Closed) => None,
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use std::{marker::PhantomData, str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use reqwest::{Client, Response, StatusCode};
+use serde_json::Value;
+use snarkvm::{ledger::puzzle::SolutionID, prelude::Block};
+use tokio::time::sleep;
+use tracing::{debug, warn};
+
+use crate::{network::PoolNetwork, solution_tracker::solution_rewards};
+
+/// What a source knows about a solution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SolutionStatus {
+    /// (height, reward)
+    Included(u32, u64),
+    NotIncluded,
+    /// The source can not tell, the next one is asked
+    Unknown,
+}
+
+/// Somewhere the fate of a broadcast solution can be looked up.
+pub trait SolutionSource: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Looks up a solution, `height` is the block it was last seen in, if any.
+    fn lookup<'a>(&'a self, solution_id: &'a str, height: Option<u32>) -> BoxFuture<'a, Result<SolutionStatus>>;
+
+    /// Latest block height the source knows of, none if it can not tell.
+    fn latest_height(&self) -> BoxFuture<'_, Result<Option<u32>>>;
+}
+
+/// Reads a height answered either as a bare number or as `{"height": ...}`.
+fn parse_height(value: &Value) -> Option<u32> {
+    value.as_u64().or_else(|| value["height"].as_u64()).map(|height| height as u32)
+}
+
+#[derive(Clone, Debug)]
+pub struct HttpSettings {
+    pub timeout: Duration,
+    /// Retries after the first attempt, for connection errors, 5xx and 429
+    pub retries: u32,
+    /// Delay before the first retry, doubled after each one
+    pub backoff: Duration,
+}
+
+/// HTTP client shared by the sources, retrying transient failures with exponential back-off.
+pub(crate) struct HttpClient {
+    client: Client,
+    settings: HttpSettings,
+}
+
+impl HttpClient {
+    pub(crate) fn new(settings: HttpSettings) -> Result<HttpClient> {
+        let client = reqwest::ClientBuilder::new()
+            .user_agent(format!("HarukaAleoPool/{}", env!("CARGO_PKG_VERSION")))
+            .timeout(settings.timeout)
+            .build()?;
+        Ok(HttpClient { client, settings })
+    }
+
+    pub(crate) async fn get(&self, url: &str) -> Result<Response> {
+        let mut backoff = self.settings.backoff;
+        let mut attempt = 0;
+        loop {
+            let error = match self.client.get(url).send().await {
+                Ok(resp) if !resp.status().is_server_error() && resp.status() != StatusCode::TOO_MANY_REQUESTS => {
+                    return Ok(resp)
+                }
+                Ok(resp) => anyhow!("{} returned {}", url, resp.status()),
+                Err(e) => anyhow!("{} failed: {}", url, e),
+            };
+            if attempt >= self.settings.retries {
+                return Err(error);
+            }
+            attempt += 1;
+            debug!("{}, retry {} in {:?}", error, attempt, backoff);
+            sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+}
+
+/// Block explorer answering `GET {url}{solution_path}` with the height and reward of a solution,
+/// and 404 for solutions not in a block, and `GET {url}{height_path}` with its latest height.
+pub struct ExplorerSource {
+    url: String,
+    /// Path template, `{id}` is replaced by the solution ID
+    solution_path: String,
+    height_path: String,
+    client: HttpClient,
+}
+
+impl ExplorerSource {
+    pub fn new(
+        url: String,
+        solution_path: String,
+        height_path: String,
+        settings: HttpSettings,
+    ) -> Result<ExplorerSource> {
+        Ok(ExplorerSource {
+            url: url.trim_end_matches('/').to_string(),
+            solution_path,
+            height_path,
+            client: HttpClient::new(settings)?,
+        })
+    }
+}
+
+impl SolutionSource for ExplorerSource {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    fn lookup<'a>(&'a self, solution_id: &'a str, _height: Option<u32>) -> BoxFuture<'a, Result<SolutionStatus>> {
+        Box::pin(async move {
+            let url = format!("{}{}", self.url, self.solution_path.replace("{id}", solution_id));
+            let resp = self.client.get(&url).await?;
+            match resp.status() {
+                StatusCode::OK => {
+                    let result = resp.json::<Value>().await?;
+                    let height = result["height"].as_u64().ok_or_else(|| anyhow!("height"))? as u32;
+                    let reward = result["reward"].as_u64().ok_or_else(|| anyhow!("reward"))?;
+                    Ok(SolutionStatus::Included(height, reward))
+                }
+                StatusCode::NOT_FOUND => Ok(SolutionStatus::NotIncluded),
+                status => Err(anyhow!("{} returned {}: {}", url, status, resp.text().await?)),
+            }
+        })
+    }
+
+    fn latest_height(&self) -> BoxFuture<'_, Result<Option<u32>>> {
+        Box::pin(async move {
+            let url = format!("{}{}", self.url, self.height_path);
+            let resp = self.client.get(&url).await?;
+            if !resp.status().is_success() {
+                return Err(anyhow!("{} returned {}", url, resp.status()));
+            }
+            let height = parse_height(&resp.json::<Value>().await?).ok_or_else(|| anyhow!("height"))?;
+            Ok(Some(height))
+        })
+    }
+}
+
+/// REST API of a snarkOS node. It has no solution index, so it can only check that a solution is
+/// still in the block it was seen in.
+pub struct NodeSource<N: PoolNetwork> {
+    url: String,
+    client: HttpClient,
+    _p: PhantomData<N>,
+}
+
+impl<N: PoolNetwork> NodeSource<N> {
+    pub fn new(url: String, settings: HttpSettings) -> Result<NodeSource<N>> {
+        Ok(NodeSource {
+            url: url.trim_end_matches('/').to_string(),
+            client: HttpClient::new(settings)?,
+            _p: PhantomData,
+        })
+    }
+}
+
+impl<N: PoolNetwork> SolutionSource for NodeSource<N> {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    fn lookup<'a>(&'a self, solution_id: &'a str, height: Option<u32>) -> BoxFuture<'a, Result<SolutionStatus>> {
+        Box::pin(async move {
+            let Some(height) = height else {
+                return Ok(SolutionStatus::Unknown);
+            };
+            let solution_id = SolutionID::<N>::from_str(solution_id)?;
+            let url = format!("{}/{}/block/{}", self.url, N::REST_PATH, height);
+            let resp = self.client.get(&url).await?;
+            if resp.status() == StatusCode::NOT_FOUND {
+                // The node is behind
+                return Ok(SolutionStatus::Unknown);
+            }
+            if !resp.status().is_success() {
+                return Err(anyhow!("{} returned {}", url, resp.status()));
+            }
+            let block = resp.json::<Block<N>>().await?;
+            Ok(solution_rewards(&block)
+                .into_iter()
+                .find(|(id, _)| *id == solution_id)
+                .map(|(_, reward)| SolutionStatus::Included(height, reward))
+                .unwrap_or(SolutionStatus::NotIncluded))
+        })
+    }
+
+    fn latest_height(&self) -> BoxFuture<'_, Result<Option<u32>>> {
+        Box::pin(async move {
+            let url = format!("{}/{}/block/height/latest", self.url, N::REST_PATH);
+            let resp = self.client.get(&url).await?;
+            if !resp.status().is_success() {
+                return Err(anyhow!("{} returned {}", url, resp.status()));
+            }
+            let height = parse_height(&resp.json::<Value>().await?).ok_or_else(|| anyhow!("height"))?;
+            Ok(Some(height))
+        })
+    }
+}
+
+/// Asks the sources in order until one has the solution in a block. A source that does not is only
+/// believed once every other one agrees or can not tell, as explorers lag behind the network.
+pub struct FallbackSource {
+    sources: Vec<Arc<dyn SolutionSource>>,
+}
+
+impl FallbackSource {
+    pub fn new(sources: Vec<Arc<dyn SolutionSource>>) -> FallbackSource {
+        FallbackSource { sources }
+    }
+}
+
+impl SolutionSource for FallbackSource {
+    fn name(&self) -> &str {
+        "fallback"
+    }
+
+    /// Fails only if no source can tell and at least one of them failed.
+    fn lookup<'a>(&'a self, solution_id: &'a str, height: Option<u32>) -> BoxFuture<'a, Result<SolutionStatus>> {
+        Box::pin(async move {
+            let mut error = None;
+            let mut not_included = false;
+            for source in &self.sources {
+                match source.lookup(solution_id, height).await {
+                    Ok(SolutionStatus::Unknown) => {}
+                    Ok(SolutionStatus::NotIncluded) => not_included = true,
+                    Ok(status) => return Ok(status),
+                    Err(e) => {
+                        warn!("Unable to look up solution {} on {}: {}", solution_id, source.name(), e);
+                        error = Some(e);
+                    }
+                }
+            }
+            match (not_included, error) {
+                (true, _) => Ok(SolutionStatus::NotIncluded),
+                (false, Some(e)) => Err(e),
+                (false, None) => Ok(SolutionStatus::Unknown),
+            }
+        })
+    }
+
+    /// Lowest latest height of the sources that know one, so every one of them is at least there.
+    fn latest_height(&self) -> BoxFuture<'_, Result<Option<u32>>> {
+        Box::pin(async move {
+            let mut error = None;
+            let mut latest_height: Option<u32> = None;
+            for source in &self.sources {
+                match source.latest_height().await {
+                    Ok(Some(height)) => latest_height = Some(latest_height.map_or(height, |h| h.min(height))),
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!("Unable to get the latest height from {}: {}", source.name(), e);
+                        error = Some(e);
+                    }
+                }
+            }
+            match (latest_height, error) {
+                (None, Some(e)) => Err(e),
+                (latest_height, _) => Ok(latest_height),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Instant,
+    };
+
+    use warp::Filter;
+
+    use super::*;
+
+    fn settings(retries: u32) -> HttpSettings {
+        HttpSettings {
+            timeout: Duration::from_secs(5),
+            retries,
+            backoff: Duration::from_millis(50),
+        }
+    }
+
+    /// Local server answering with the statuses in order, the last one for every later request.
+    /// Returns its URL and the number of requests it got.
+    fn mock_server(statuses: Vec<u16>, body: &'static str) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let route = warp::any().map(move || {
+            let request = counter.fetch_add(1, Ordering::SeqCst);
+            let status = statuses[request.min(statuses.len() - 1)];
+            warp::reply::with_status(body, warp::http::StatusCode::from_u16(status).unwrap())
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (format!("http://{}", addr), requests)
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_and_rate_limits_with_backoff() {
+        let (url, requests) = mock_server(vec![503, 429, 200], "");
+        let client = HttpClient::new(settings(3)).unwrap();
+        let started = Instant::now();
+        let resp = client.get(&url).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        // 50ms, then 100ms
+        assert!(started.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_retries() {
+        let (url, requests) = mock_server(vec![500], "");
+        let client = HttpClient::new(settings(2)).unwrap();
+        assert!(client.get(&url).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        for status in [400, 403, 404] {
+            let (url, requests) = mock_server(vec![status], "");
+            let client = HttpClient::new(settings(3)).unwrap();
+            let resp = client.get(&url).await.unwrap();
+            assert_eq!(resp.status().as_u16(), status);
+            assert_eq!(requests.load(Ordering::SeqCst), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn falls_through_to_the_next_source() {
+        let (failing_url, failing_requests) = mock_server(vec![500], "");
+        let (url, requests) = mock_server(vec![200], r#"{"height": 5, "reward": 7}"#);
+        let source = |url: String| -> Arc<dyn SolutionSource> {
+            Arc::new(
+                ExplorerSource::new(url, "/solution/{id}".to_string(), "/height".to_string(), settings(1)).unwrap(),
+            )
+        };
+
+        let fallback = FallbackSource::new(vec![source(failing_url.clone()), source(url)]);
+        let status = fallback.lookup("solution1", None).await.unwrap();
+        assert_eq!(status, SolutionStatus::Included(5, 7));
+        assert_eq!(failing_requests.load(Ordering::SeqCst), 2);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // A source lagging behind with the solution in no block is fallen through
+        let (not_found_url, not_found_requests) = mock_server(vec![404], "");
+        let (next_url, next_requests) = mock_server(vec![200], r#"{"height": 5, "reward": 7}"#);
+        let fallback = FallbackSource::new(vec![source(not_found_url.clone()), source(next_url)]);
+        assert_eq!(fallback.lookup("solution1", None).await.unwrap(), SolutionStatus::Included(5, 7));
+        assert_eq!(not_found_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(next_requests.load(Ordering::SeqCst), 1);
+
+        // In no block on any source that answers
+        let fallback = FallbackSource::new(vec![source(not_found_url), source(failing_url.clone())]);
+        assert_eq!(fallback.lookup("solution1", None).await.unwrap(), SolutionStatus::NotIncluded);
+
+        // Every source failed
+        let (other_failing_url, _) = mock_server(vec![502], "");
+        let fallback = FallbackSource::new(vec![source(failing_url), source(other_failing_url)]);
+        assert!(fallback.lookup("solution1", None).await.is_err());
+    }
+    #[tokio::test]
+    async fn latest_height_is_the_lowest_of_the_sources() {
+        let (url, _) = mock_server(vec![200], "120");
+        let (other_url, _) = mock_server(vec![200], r#"{"height": 100}"#);
+        let (failing_url, _) = mock_server(vec![500], "");
+        let source = |url: String| -> Arc<dyn SolutionSource> {
+            Arc::new(
+                ExplorerSource::new(url, "/solution/{id}".to_string(), "/height".to_string(), settings(0)).unwrap(),
+            )
+        };
+
+        let fallback = FallbackSource::new(vec![source(url), source(failing_url.clone()), source(other_url)]);
+        assert_eq!(fallback.latest_height().await.unwrap(), Some(100));
+
+        let fallback = FallbackSource::new(vec![source(failing_url)]);
+        assert!(fallback.latest_height().await.is_err());
+    }
+}
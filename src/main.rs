@@ -1,63 +1,226 @@
 
 mod accounting;
+mod admin;
 mod api;
+mod audit;
 mod connection;
+mod events;
+mod explorer;
+mod metrics;
+mod network;
+mod payout_executor;
+mod payout_model;
 mod prover_peer;
 mod server;
+mod share_log;
+mod solution_tracker;
+mod speed_history;
+#[cfg(feature = "storage")]
+mod state_storage;
+mod store;
+mod work_source;
 
-#[cfg(feature = "db")]
-mod db;
-
-use std::sync::Arc;
+use std::{
+    str::FromStr,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use dirs::home_dir;
 use futures::stream::StreamExt;
 use rand::seq::SliceRandom;
 use signal_hook::consts::{SIGABRT, SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGTSTP, SIGUSR1};
 use signal_hook_tokio::Signals;
-use snarkvm::console::{network::MainnetV0, types::Address};
+use snarkvm::console::{
+    network::{CanaryV0, MainnetV0, Network, TestnetV0},
+    types::Address,
+};
 use tokio::sync::mpsc::Sender;
 use tracing::{debug, error, info, warn};
 use tracing_log::{log, LogTracer};
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter};
 
-use crate::prover_peer::Node;
+use crate::prover_peer::{Node, NodeTimings};
 use crate::{
     accounting::{Accounting, AccountingMessage},
+    admin::Admin,
+    events::Events,
+    explorer::{ExplorerSource, FallbackSource, HttpSettings, NodeSource, SolutionSource},
+    network::PoolNetwork,
+    payout_executor::{PayoutExecutor, TransferSettings},
+    payout_model::{FeeSettings, PayoutModelKind, PayoutSettings},
     //    operator_peer::Node,
     server::{Server, ServerMessage},
+    speed_history::SpeedHistory,
+    store::StoreKind,
+    work_source::{MockSource, WorkSource},
 };
 
-pub(crate) type N = MainnetV0;
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum AleoNetwork {
+    Mainnet,
+    Testnet,
+    Canary,
+}
 
 #[derive(Debug, Parser)]
-#[clap(name = "pool_server", about = "Aleo proving pool server")]
+#[clap(name = "pool_server", about = "Aleo proving pool server", subcommand_negates_reqs = true)]
 struct Opt {
+    /// Aleo network to run the pool on
+    #[clap(long, value_enum, default_value_t = AleoNetwork::Mainnet)]
+    network: AleoNetwork,
+
     /// snarkOS node address
     #[clap(short, long)]
     node: Option<String>,
 
     /// Proving pool address (aleo1...)
-    #[clap(short, long)]
-    address: Address<N>,
+    #[clap(short, long, required = true)]
+    address: Option<String>,
 
     /// Port to listen for incoming provers
-    #[clap(short, long)]
-    port: u16,
+    #[clap(short, long, required = true)]
+    port: Option<u16>,
 
     /// API port
-    #[clap(long = "api-port")]
-    api_port: u16,
+    #[clap(long = "api-port", required = true)]
+    api_port: Option<u16>,
+
+    /// Explorer API URL roots, asked in order\n
+    /// Used to check if solution is on network, otherwise the blocks followed through the node are used
+    #[clap(short, long = "aleoscan-url", alias = "explorer-url", value_delimiter = ',')]
+    explorer_urls: Vec<String>,
+
+    /// Path of the explorer solution lookup, {id} is replaced by the solution ID
+    #[clap(long = "explorer-solution-path", default_value = "/v2/solution/{id}")]
+    explorer_solution_path: String,
+
+    /// Path of the explorer latest block height, answered as a number or as {"height": ...}
+    #[clap(long = "explorer-height-path", default_value = "/v2/block/height/latest")]
+    explorer_height_path: String,
+
+    /// snarkOS node REST API URL root, asked after the explorers to check solutions are still in their block
+    #[clap(long = "node-rest-url")]
+    node_rest_url: Option<String>,
+
+    /// Seconds before an explorer or node request times out
+    #[clap(long = "explorer-timeout", default_value_t = 10)]
+    explorer_timeout: u64,
+
+    /// Retries of a failed explorer or node request, with exponential back-off
+    #[clap(long = "explorer-retries", default_value_t = 3)]
+    explorer_retries: u32,
+
+    /// How rewards are distributed to provers
+    #[clap(long = "payout-model", value_enum, default_value_t = PayoutModelKind::Pplns)]
+    payout_model: PayoutModelKind,
+
+    /// Expected reward of a solution in microcredits, used by the per-share payout models
+    #[clap(long = "expected-reward")]
+    expected_reward: Option<u64>,
+
+    /// Size of the PPLNS window, as a multiple of the network proof target
+    #[clap(long = "pplns-multiplier", default_value_t = 5)]
+    pplns_multiplier: u64,
+
+    /// Pool fee in basis points
+    #[clap(long = "pool-fee", default_value_t = 50, value_parser = clap::value_parser!(u64).range(0..=10000))]
+    pool_fee: u64,
+
+    /// Address the pool fee is credited to (aleo1...)
+    #[clap(long = "fee-address")]
+    fee_address: Option<String>,
+
+    /// Part of the pool fee credited to another address, as ADDRESS:BPS of the fee (comma-delimited)
+    #[clap(long = "fee-split", value_delimiter = ',')]
+    fee_splits: Vec<String>,
+
+    /// Address credited with prover donations, donations are disabled without it
+    #[clap(long = "donation-address")]
+    donation_address: Option<String>,
+
+    /// Referral reward in basis points of the referred prover's earnings, paid out of the pool fee
+    #[clap(long = "referral-bps", default_value_t = 0, value_parser = clap::value_parser!(u64).range(0..=10000))]
+    referral_bps: u64,
+
+    /// Blocks on top of the one including a solution before its reward is credited
+    #[clap(long = "maturity-blocks", default_value_t = 10)]
+    maturity_blocks: u32,
+
+    /// Minutes of shares in the PPLNT window
+    #[clap(long = "pplnt-window", default_value_t = 60)]
+    pplnt_window: u64,
+
+    /// Send the unpaid balances on chain with transfer_public, from the key in POOL_PRIVATE_KEY
+    #[clap(long)]
+    transfers: bool,
 
-    /// AleoScan API URL root\n
-    /// Mandatory if db feature is enabled, used to check if solution is on network
-    #[clap(short, long = "aleoscan-url")]
-    explorer_url: Option<String>,
+    /// Only log the transfers that would be sent
+    #[clap(long = "transfer-dry-run")]
+    transfer_dry_run: bool,
+
+    /// Smallest unpaid balance that is transferred, in microcredits
+    #[clap(long = "transfer-minimum", default_value_t = 1_000_000)]
+    transfer_minimum: u64,
+
+    /// Most transfers sent in one round, each in a transaction of its own
+    #[clap(long = "transfers-per-round", default_value_t = 20)]
+    transfers_per_round: usize,
+
+    /// Minutes between two rounds of transfers
+    #[clap(long = "transfer-interval", default_value_t = 60)]
+    transfer_interval: u64,
+
+    /// Priority fee of every transfer, in microcredits
+    #[clap(long = "transfer-priority-fee", default_value_t = 0)]
+    transfer_priority_fee: u64,
+
+    /// Seconds between two puzzle requests to the node
+    #[clap(long = "puzzle-request-interval", default_value_t = 15)]
+    puzzle_request_interval: u64,
+
+    /// Milliseconds between two puzzle requests when close to an epoch boundary
+    #[clap(long = "fast-poll-interval", default_value_t = 1000)]
+    fast_poll_interval: u64,
+
+    /// Number of blocks before an epoch boundary from which the fast poll interval is used
+    #[clap(long = "epoch-boundary-window", default_value_t = 2)]
+    epoch_boundary_window: u32,
+
+    /// Seconds between two pings to the node
+    #[clap(long = "ping-interval", default_value_t = 5)]
+    ping_interval: u64,
+
+    /// Seconds before giving up on connecting to the node
+    #[clap(long = "connect-timeout", default_value_t = 5)]
+    connect_timeout: u64,
+
+    /// Seconds to wait before reconnecting to the node after an error
+    #[clap(long = "reconnect-delay", default_value_t = 25)]
+    reconnect_delay: u64,
+
+    /// Where solutions, shares and balances are kept
+    #[cfg_attr(feature = "db", clap(long, value_enum, default_value_t = StoreKind::Postgres))]
+    #[cfg_attr(not(feature = "db"), clap(long, value_enum, default_value_t = StoreKind::Memory))]
+    store: StoreKind,
 
     /// Genesis block path for testing
     #[clap(long)]
     genesis_block: Option<String>,
 
+    /// Use a scripted offline upstream instead of a snarkOS node (testing only)
+    #[clap(long)]
+    mock: bool,
+
+    /// Proof targets of the mock upstream epochs, used in order and repeated
+    #[clap(long = "mock-proof-target", value_delimiter = ',', default_value = "64")]
+    mock_proof_targets: Vec<u64>,
+
+    /// Seconds before the mock upstream moves to the next epoch if no solution is found
+    #[clap(long = "mock-epoch-interval", default_value_t = 60)]
+    mock_epoch_interval: u64,
+
     /// Enable debug logging
     #[clap(short, long)]
     debug: bool,
@@ -69,6 +232,25 @@ struct Opt {
     /// Output log to file
     #[clap(long)]
     log: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Apply the pending schema migrations of the selected store and exit
+    Migrate,
+    /// Check the recorded solutions and payouts against the share log and exit, failing on
+    /// discrepancies
+    Audit {
+        /// Only audit this solution
+        #[clap(long)]
+        solution: Option<String>,
+        /// Log the amounts of this address for every solution
+        #[clap(long)]
+        prover: Option<String>,
+    },
 }
 
 #[tokio::main]
@@ -98,7 +280,7 @@ async fn main() {
     //         .with_ansi(true)
     //         .with_writer(std::io::stdout),
     // );
-    if let Some(log) = opt.log {
+    if let Some(log) = &opt.log {
         let file = std::fs::File::create(log).unwrap();
         let file = tracing_subscriber::fmt::layer().with_writer(file).with_ansi(false);
         tracing::subscriber::set_global_default(subscriber.with(file))
@@ -113,27 +295,253 @@ async fn main() {
         .build_global()
         .unwrap();
 
-    let validator = opt.node.unwrap_or_else(|| {
-        let bootstrap = [
-            "node1.mainnet.aleoscan.org:4130",
-            "node2.mainnet.aleoscan.org:4130",
-            "node3.mainnet.aleoscan.org:4130",
-        ];
-        bootstrap.choose(&mut rand::thread_rng()).unwrap().to_string()
-    });
-    let port = opt.port;
+    match opt.network {
+        AleoNetwork::Mainnet => run::<MainnetV0>(opt).await,
+        AleoNetwork::Testnet => run::<TestnetV0>(opt).await,
+        AleoNetwork::Canary => run::<CanaryV0>(opt).await,
+    }
+}
 
-    let address = opt.address;
+async fn run<N: PoolNetwork>(opt: Opt) {
+    let data_dir = match home_dir() {
+        Some(home) => home.join(N::DATA_DIR),
+        None => panic!("No home directory found"),
+    };
+    let store = store::open(opt.store, &data_dir);
 
-    let accounting = Accounting::init(opt.explorer_url);
+    if let Some(Command::Migrate) = opt.command {
+        if let Err(e) = store.migrate().await {
+            error!("Unable to migrate the store: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Err(e) = store.check_schema().await {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+
+    let validator = opt
+        .node
+        .unwrap_or_else(|| N::BOOTSTRAP.choose(&mut rand::thread_rng()).unwrap().to_string());
+    let port = opt.port.unwrap();
+
+    let pool_address = opt.address.unwrap();
+    let address = match Address::<N>::from_str(&pool_address) {
+        Ok(address) => address,
+        Err(e) => {
+            error!("Invalid pool address {}: {}", pool_address, e);
+            std::process::exit(1);
+        }
+    };
+
+    for (name, address) in [("fee", &opt.fee_address), ("donation", &opt.donation_address)] {
+        if let Some(address) = address {
+            if let Err(e) = Address::<N>::from_str(address) {
+                error!("Invalid {} address {}: {}", name, address, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let mut fee_splits = vec![];
+    for split in &opt.fee_splits {
+        let parsed = split.split_once(':').and_then(|(address, bps)| {
+            Address::<N>::from_str(address).ok()?;
+            Some((address.to_string(), bps.parse::<u64>().ok()?))
+        });
+        match parsed {
+            Some(split) => fee_splits.push(split),
+            None => {
+                error!("Invalid fee split {}, expected ADDRESS:BPS", split);
+                std::process::exit(1);
+            }
+        }
+    }
+    if fee_splits.iter().map(|(_, bps)| bps).sum::<u64>() > 10000 {
+        error!("Fee splits add up to more than the whole pool fee");
+        std::process::exit(1);
+    }
+
+    if opt.expected_reward.is_none()
+        && matches!(
+            opt.payout_model,
+            PayoutModelKind::Pps | PayoutModelKind::PpsPlus | PayoutModelKind::Fpps
+        )
+    {
+        error!("The {:?} payout model needs --expected-reward", opt.payout_model);
+        std::process::exit(1);
+    }
+
+    let payout_settings = PayoutSettings {
+        kind: opt.payout_model,
+        expected_reward: opt.expected_reward,
+        n_multiplier: opt.pplns_multiplier,
+        pplnt_window: Duration::from_secs(opt.pplnt_window * 60),
+        fees: FeeSettings {
+            bps: opt.pool_fee,
+            address: opt.fee_address,
+            splits: fee_splits,
+            donation_address: opt.donation_address,
+            referral_bps: opt.referral_bps,
+        },
+        maturity_blocks: opt.maturity_blocks,
+    };
+
+    if let Some(Command::Audit { solution, prover }) = &opt.command {
+        match audit::run(store, &payout_settings, &data_dir, solution.as_deref(), prover.as_deref()).await {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                error!("Unable to audit: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    #[cfg(feature = "storage")]
+    let storage = Arc::new(state_storage::Storage::load(&data_dir));
+
+    let http_settings = HttpSettings {
+        timeout: Duration::from_secs(opt.explorer_timeout),
+        retries: opt.explorer_retries,
+        backoff: Duration::from_millis(500),
+    };
+    let mut sources: Vec<Arc<dyn SolutionSource>> = vec![];
+    for url in opt.explorer_urls {
+        match ExplorerSource::new(
+            url.clone(),
+            opt.explorer_solution_path.clone(),
+            opt.explorer_height_path.clone(),
+            http_settings.clone(),
+        ) {
+            Ok(source) => sources.push(Arc::new(source)),
+            Err(e) => {
+                error!("Unable to create explorer client for {}: {}", url, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(url) = opt.node_rest_url.clone() {
+        match NodeSource::<N>::new(url.clone(), http_settings.clone()) {
+            Ok(source) => sources.push(Arc::new(source)),
+            Err(e) => {
+                error!("Unable to create node client for {}: {}", url, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let solution_source: Option<Arc<dyn SolutionSource>> = match sources.len() {
+        0 => None,
+        1 => sources.pop(),
+        _ => Some(Arc::new(FallbackSource::new(sources))),
+    };
+
+    let accounting = Accounting::init(
+        solution_source,
+        data_dir.clone(),
+        payout_settings,
+        store.clone(),
+        #[cfg(feature = "storage")]
+        storage.clone(),
+    );
+
+    let work_source: Arc<dyn WorkSource<N>> = if opt.mock {
+        warn!("Using the mock upstream, solutions will not reach the network");
+        match MockSource::new(
+            opt.genesis_block,
+            opt.mock_proof_targets,
+            Duration::from_secs(opt.mock_epoch_interval),
+        ) {
+            Ok(mock) => Arc::new(mock),
+            Err(e) => {
+                error!("Unable to start the mock upstream: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        let timings = NodeTimings {
+            puzzle_request_interval: Duration::from_secs(opt.puzzle_request_interval),
+            fast_poll_interval: Duration::from_millis(opt.fast_poll_interval),
+            epoch_boundary_window: opt.epoch_boundary_window,
+            ping_interval: Duration::from_secs(opt.ping_interval),
+            connect_timeout: Duration::from_secs(opt.connect_timeout),
+            reconnect_delay: Duration::from_secs(opt.reconnect_delay),
+        };
+        Arc::new(Node::init(validator, opt.genesis_block, timings, accounting.sender()))
+    };
+
+    let speed_history = SpeedHistory::init(
+        #[cfg(feature = "storage")]
+        &storage,
+    );
+
+    let events = Events::new();
+
+    let server = Server::init(
+        port,
+        address,
+        work_source.clone(),
+        accounting.sender(),
+        events.clone(),
+        #[cfg(feature = "storage")]
+        storage,
+    )
+    .await;
+
+    speed_history.clone().start(server.clone());
 
-    let node = Node::init(validator);
+    work_source.start(server.sender());
 
-    let server = Server::init(port, address, node.sender(), accounting.sender()).await;
+    let payouts_paused = Arc::new(AtomicBool::new(false));
+    if opt.transfers || opt.transfer_dry_run {
+        let settings = TransferSettings {
+            minimum: opt.transfer_minimum,
+            per_round: opt.transfers_per_round,
+            interval: Duration::from_secs(opt.transfer_interval * 60),
+            priority_fee: opt.transfer_priority_fee,
+            dry_run: opt.transfer_dry_run,
+        };
+        match PayoutExecutor::init(
+            settings,
+            store.clone(),
+            work_source.clone(),
+            opt.node_rest_url,
+            http_settings,
+            events.clone(),
+            payouts_paused.clone(),
+        ) {
+            Ok(executor) => executor.start(),
+            Err(e) => {
+                error!("Unable to start the payout executor: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    prover_peer::start(node, server.sender(), opt.genesis_block);
+    let admin = match Admin::init(
+        server.clone(),
+        accounting.clone(),
+        store.clone(),
+        payouts_paused,
+        &data_dir,
+    ) {
+        Ok(admin) => admin,
+        Err(e) => {
+            error!("Unable to open the admin audit log: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    api::start(opt.api_port, accounting.clone(), server.clone());
+    api::start(
+        opt.api_port.unwrap(),
+        accounting.clone(),
+        server.clone(),
+        store,
+        speed_history,
+        events,
+        admin,
+    );
 
     match Signals::new([SIGABRT, SIGTERM, SIGHUP, SIGINT, SIGQUIT, SIGUSR1, SIGTSTP]) {
         Ok(signals) => {
@@ -148,7 +556,11 @@ async fn main() {
     std::future::pending::<()>().await;
 }
 
-async fn handle_signals(mut signals: Signals, accounting: Arc<Accounting>, server_sender: Sender<ServerMessage>) {
+async fn handle_signals<N: Network>(
+    mut signals: Signals,
+    accounting: Arc<Accounting>,
+    server_sender: Sender<ServerMessage<N>>,
+) {
     while let Some(signal) = signals.next().await {
         info!("Received signal: {:?}", signal);
         let accounting_sender = accounting.sender();
@@ -0,0 +1,127 @@
+use anyhow::Result;
+use prometheus::{
+    exponential_buckets,
+    Encoder,
+    Gauge,
+    Histogram,
+    HistogramOpts,
+    IntCounter,
+    IntCounterVec,
+    IntGauge,
+    Opts,
+    Registry,
+    TextEncoder,
+};
+
+/// Why a share was rejected.
+#[derive(Clone, Copy, Debug)]
+pub enum RejectReason {
+    /// For an epoch that is over, or sent before the first one
+    Stale,
+    Duplicate,
+    /// Below the target of the prover
+    LowDifficulty,
+    /// Not a valid solution, or from a prover the server does not know
+    Invalid,
+    /// From a banned IP
+    Banned,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::Stale => "stale",
+            RejectReason::Duplicate => "duplicate",
+            RejectReason::LowDifficulty => "low_difficulty",
+            RejectReason::Invalid => "invalid",
+            RejectReason::Banned => "banned",
+        }
+    }
+}
+
+/// Prometheus metrics of the server. Counters are updated as things happen, gauges are set from
+/// the server state when scraped.
+pub struct Metrics {
+    registry: Registry,
+    shares_accepted: IntCounter,
+    shares_rejected: IntCounterVec,
+    verification_seconds: Histogram,
+    pub connected_provers: IntGauge,
+    pub authenticated_provers: IntGauge,
+    pub global_target_modifier: Gauge,
+    pub proof_target: IntGauge,
+    pub epoch_number: IntGauge,
+    pub node_connected: IntGauge,
+    pub pending_solutions: IntGauge,
+    pub accounting_queue: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Metrics> {
+        let registry = Registry::new_custom(Some("aleo_pool".to_string()), None)?;
+        let shares_accepted = IntCounter::new("shares_accepted_total", "Shares accepted from provers")?;
+        let shares_rejected = IntCounterVec::new(
+            Opts::new("shares_rejected_total", "Shares rejected, by reason"),
+            &["reason"],
+        )?;
+        let verification_seconds = Histogram::with_opts(
+            HistogramOpts::new("share_verification_seconds", "Time taken to verify a share")
+                .buckets(exponential_buckets(0.0005, 2.0, 14)?),
+        )?;
+        let connected_provers = IntGauge::new("connected_provers", "Open prover connections")?;
+        let authenticated_provers = IntGauge::new("authenticated_provers", "Authorized prover connections")?;
+        let global_target_modifier =
+            Gauge::new("global_target_modifier", "Multiplier applied to the target of every prover")?;
+        let proof_target = IntGauge::new("proof_target", "Proof target of the network")?;
+        let epoch_number = IntGauge::new("epoch_number", "Latest epoch number")?;
+        let node_connected = IntGauge::new("node_connected", "1 if the upstream node is connected")?;
+        let pending_solutions = IntGauge::new("pending_solutions", "Solutions waiting for the node to connect")?;
+        let accounting_queue = IntGauge::new("accounting_queue", "Messages waiting for the accounting actor")?;
+
+        registry.register(Box::new(shares_accepted.clone()))?;
+        registry.register(Box::new(shares_rejected.clone()))?;
+        registry.register(Box::new(verification_seconds.clone()))?;
+        registry.register(Box::new(connected_provers.clone()))?;
+        registry.register(Box::new(authenticated_provers.clone()))?;
+        registry.register(Box::new(global_target_modifier.clone()))?;
+        registry.register(Box::new(proof_target.clone()))?;
+        registry.register(Box::new(epoch_number.clone()))?;
+        registry.register(Box::new(node_connected.clone()))?;
+        registry.register(Box::new(pending_solutions.clone()))?;
+        registry.register(Box::new(accounting_queue.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            shares_accepted,
+            shares_rejected,
+            verification_seconds,
+            connected_provers,
+            authenticated_provers,
+            global_target_modifier,
+            proof_target,
+            epoch_number,
+            node_connected,
+            pending_solutions,
+            accounting_queue,
+        })
+    }
+
+    pub fn share_accepted(&self) {
+        self.shares_accepted.inc();
+    }
+
+    pub fn share_rejected(&self, reason: RejectReason) {
+        self.shares_rejected.with_label_values(&[reason.as_str()]).inc();
+    }
+
+    pub fn verification_time(&self, seconds: f64) {
+        self.verification_seconds.observe(seconds);
+    }
+
+    /// Every metric in the Prometheus text format.
+    pub fn encode(&self) -> Result<String> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
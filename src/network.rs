@@ -0,0 +1,59 @@
+use snarkvm::{
+    circuit::{Aleo, AleoCanaryV0, AleoTestnetV0, AleoV0},
+    console::network::{CanaryV0, MainnetV0, Network, TestnetV0},
+};
+
+/// Network specific settings of the pool.
+pub trait PoolNetwork: Network {
+    /// Circuit environment used to synthesize the puzzle.
+    type Circuit: Aleo<Network = Self>;
+
+    /// Name of the directory under the home directory that holds the pool state.
+    const DATA_DIR: &'static str;
+
+    /// Network segment of the snarkOS REST API paths.
+    const REST_PATH: &'static str;
+
+    /// Nodes to connect to when none is given on the command line.
+    const BOOTSTRAP: &'static [&'static str];
+}
+
+impl PoolNetwork for MainnetV0 {
+    type Circuit = AleoV0;
+
+    const BOOTSTRAP: &'static [&'static str] = &[
+        "node1.mainnet.aleoscan.org:4130",
+        "node2.mainnet.aleoscan.org:4130",
+        "node3.mainnet.aleoscan.org:4130",
+    ];
+    const DATA_DIR: &'static str = ".aleo_pool_mainnet";
+    const REST_PATH: &'static str = "mainnet";
+}
+
+impl PoolNetwork for TestnetV0 {
+    type Circuit = AleoTestnetV0;
+
+    // Same bootstrap peers as snarkOS
+    const BOOTSTRAP: &'static [&'static str] = &[
+        "34.168.118.156:4130",
+        "35.231.152.213:4130",
+        "34.17.53.129:4130",
+        "35.200.149.162:4130",
+    ];
+    const DATA_DIR: &'static str = ".aleo_pool_testnet";
+    const REST_PATH: &'static str = "testnet";
+}
+
+impl PoolNetwork for CanaryV0 {
+    type Circuit = AleoCanaryV0;
+
+    // Same bootstrap peers as snarkOS
+    const BOOTSTRAP: &'static [&'static str] = &[
+        "34.74.24.41:4130",
+        "35.228.3.69:4130",
+        "34.124.178.133:4130",
+        "34.125.137.231:4130",
+    ];
+    const DATA_DIR: &'static str = ".aleo_pool_canary";
+    const REST_PATH: &'static str = "canary";
+}
@@ -0,0 +1,276 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use reqwest::StatusCode;
+use serde_json::Value as JsonValue;
+use snarkvm::{
+    ledger::{
+        query::Query,
+        store::{
+            helpers::memory::{BlockMemory, ConsensusMemory},
+            ConsensusStore,
+        },
+    },
+    prelude::{Address, PrivateKey, Transaction, Value, VM},
+};
+use tokio::{task, time::sleep};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    events::{Events, PoolEvent},
+    explorer::{HttpClient, HttpSettings},
+    network::PoolNetwork,
+    store::PoolStore,
+    work_source::WorkSource,
+};
+
+/// Age after which a transfer not seen in a block is reported as stuck. It stays pending, as it could
+/// still be included, until it is refunded through the admin API.
+static TRANSFER_EXPIRY: Duration = Duration::from_secs(60 * 60 * 6);
+
+#[derive(Clone, Debug)]
+pub struct TransferSettings {
+    /// Smallest unpaid balance that is sent, in microcredits
+    pub minimum: u64,
+    /// Most transfers broadcast in one round. Each one is a transaction of its own, as `transfer_public`
+    /// pays a single recipient.
+    pub per_round: usize,
+    pub interval: Duration,
+    /// Priority fee of every transfer, in microcredits
+    pub priority_fee: u64,
+    /// Only log the transfers that would be sent
+    pub dry_run: bool,
+}
+
+/// Pays the unpaid balances out with `credits.aleo/transfer_public` from the pool hot wallet.
+///
+/// A transfer moves its amount from unpaid to pending when it is broadcast, then to paid once the
+/// node has it in a block, or back to unpaid if it was rejected. A transfer never seen in a block is
+/// left pending for an admin to refund.
+pub struct PayoutExecutor<N: PoolNetwork> {
+    settings: TransferSettings,
+    store: Arc<dyn PoolStore>,
+    work_source: Arc<dyn WorkSource<N>>,
+    private_key: Option<PrivateKey<N>>,
+    /// snarkOS REST API, for the state root of the transfers and their confirmation
+    node_url: Option<String>,
+    client: HttpClient,
+    events: Events,
+    /// Set through the admin API, no new transfer is sent while it is
+    paused: Arc<AtomicBool>,
+}
+
+impl<N: PoolNetwork> PayoutExecutor<N> {
+    /// The hot wallet key is read from `POOL_PRIVATE_KEY`, only the dry run can do without it.
+    pub fn init(
+        settings: TransferSettings,
+        store: Arc<dyn PoolStore>,
+        work_source: Arc<dyn WorkSource<N>>,
+        node_url: Option<String>,
+        http_settings: HttpSettings,
+        events: Events,
+        paused: Arc<AtomicBool>,
+    ) -> Result<PayoutExecutor<N>> {
+        let private_key = match std::env::var("POOL_PRIVATE_KEY") {
+            Ok(key) => Some(PrivateKey::<N>::from_str(&key).map_err(|e| anyhow!("Invalid POOL_PRIVATE_KEY: {}", e))?),
+            Err(_) => None,
+        };
+        if !settings.dry_run {
+            if private_key.is_none() {
+                return Err(anyhow!("POOL_PRIVATE_KEY is needed to send transfers"));
+            }
+            if node_url.is_none() {
+                return Err(anyhow!("--node-rest-url is needed to send transfers"));
+            }
+        }
+        if let Some(private_key) = &private_key {
+            info!("Transfers are sent from {}", Address::try_from(private_key)?);
+        }
+        Ok(PayoutExecutor {
+            settings,
+            store,
+            work_source,
+            private_key,
+            node_url: node_url.map(|url| url.trim_end_matches('/').to_string()),
+            client: HttpClient::new(http_settings)?,
+            events,
+            paused,
+        })
+    }
+
+    pub fn start(self) {
+        task::spawn(async move {
+            let vm = match self.settings.dry_run {
+                true => None,
+                false => {
+                    let vm = task::spawn_blocking(|| VM::from(ConsensusStore::<N, ConsensusMemory<N>>::open(None::<u16>)?))
+                        .await
+                        .map_err(|e| anyhow!(e))
+                        .and_then(|vm| vm);
+                    match vm {
+                        Ok(vm) => Some(Arc::new(vm)),
+                        Err(e) => {
+                            error!("Unable to load the VM, no transfer will be sent: {}", e);
+                            return;
+                        }
+                    }
+                }
+            };
+            loop {
+                if let Err(e) = self.check_pending_transfers().await {
+                    error!("Unable to check pending transfers: {}", e);
+                }
+                if self.paused.load(Ordering::SeqCst) {
+                    info!("Payouts are paused, no transfer is sent");
+                } else if let Err(e) = self.send_transfers(vm.clone()).await {
+                    error!("Unable to send transfers: {}", e);
+                }
+                sleep(self.settings.interval).await;
+            }
+        });
+    }
+
+    async fn check_pending_transfers(&self) -> Result<()> {
+        if self.settings.dry_run {
+            return Ok(());
+        }
+        let node_url = self.node_url.as_deref().unwrap_or_default();
+        for transfer in self.store.get_pending_transfers().await? {
+            let url = format!(
+                "{}/{}/transaction/confirmed/{}",
+                node_url,
+                N::REST_PATH,
+                transfer.transaction_id
+            );
+            let resp = match self.client.get(&url).await {
+                Ok(resp) => resp,
+                Err(e) => {
+                    warn!("Unable to check transfer {}: {}", transfer.transaction_id, e);
+                    continue;
+                }
+            };
+            let accepted = match resp.status() {
+                StatusCode::OK => match resp.json::<JsonValue>().await {
+                    Ok(json) => json["status"].as_str() == Some("accepted"),
+                    Err(e) => {
+                        warn!("Unable to check transfer {}: invalid response: {}", transfer.transaction_id, e);
+                        continue;
+                    }
+                },
+                StatusCode::NOT_FOUND => {
+                    if now_unix_secs().saturating_sub(transfer.timestamp) >= TRANSFER_EXPIRY.as_secs() {
+                        warn!(
+                            "Transfer {} of {} to {} is still not in a block, refund it through the admin API once \
+                             it can no longer be included",
+                            transfer.transaction_id, transfer.amount, transfer.address
+                        );
+                    }
+                    continue;
+                }
+                status => {
+                    warn!("Unable to check transfer {}: {} returned {}", transfer.transaction_id, url, status);
+                    continue;
+                }
+            };
+            if let Err(e) = self.store.finish_transfer(transfer.id, accepted).await {
+                error!("Unable to finish transfer {}: {}", transfer.transaction_id, e);
+                continue;
+            }
+            if accepted {
+                info!("Sent {} to {} in {}", transfer.amount, transfer.address, transfer.transaction_id);
+                self.events.publish(PoolEvent::PayoutSent {
+                    address: transfer.address,
+                    amount: transfer.amount,
+                    transaction_id: transfer.transaction_id,
+                });
+            } else {
+                warn!(
+                    "Transfer {} of {} to {} failed, the amount is unpaid again",
+                    transfer.transaction_id, transfer.amount, transfer.address
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_transfers(&self, vm: Option<Arc<VM<N, ConsensusMemory<N>>>>) -> Result<()> {
+        let balances = self.store.get_payable_balances(self.settings.minimum).await?;
+        for balance in balances.into_iter().take(self.settings.per_round) {
+            let (address, recipient, amount) = (balance.address, balance.recipient, balance.unpaid);
+            let vm = match &vm {
+                Some(vm) => vm.clone(),
+                None => {
+                    info!("Dry run: would transfer {} of {} to {}", amount, address, recipient);
+                    continue;
+                }
+            };
+            let private_key = self.private_key.ok_or_else(|| anyhow!("No hot wallet key"))?;
+            let node_url = self.node_url.clone().unwrap_or_default();
+            let priority_fee = self.settings.priority_fee;
+            let to = recipient.clone();
+            let transaction = task::spawn_blocking(move || {
+                build_transfer(&vm, &private_key, &node_url, &to, amount, priority_fee)
+            })
+            .await?;
+            let transaction = match transaction {
+                Ok(transaction) => transaction,
+                Err(e) => {
+                    // Most likely the hot wallet can not cover the fee, the next ones would fail too
+                    return Err(anyhow!("Unable to build transfer of {} to {}: {}", amount, recipient, e));
+                }
+            };
+            let transaction_id = transaction.id().to_string();
+            let id = self
+                .store
+                .start_transfer(&address, &recipient, amount, &transaction_id)
+                .await?;
+            debug!(
+                "Broadcasting transfer {} of {} of {} to {}",
+                transaction_id, amount, address, recipient
+            );
+            if let Err(e) = self.work_source.broadcast_transaction(transaction).await {
+                error!("Unable to broadcast transfer {}: {}", transaction_id, e);
+                self.store.finish_transfer(id, false).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn build_transfer<N: PoolNetwork>(
+    vm: &VM<N, ConsensusMemory<N>>,
+    private_key: &PrivateKey<N>,
+    node_url: &str,
+    recipient: &str,
+    amount: u64,
+    priority_fee: u64,
+) -> Result<Transaction<N>> {
+    let inputs = [
+        Value::<N>::from_str(recipient)?,
+        Value::<N>::from_str(&format!("{}u64", amount))?,
+    ];
+    let query = Query::<N, BlockMemory<N>>::from(node_url);
+    vm.execute(
+        private_key,
+        ("credits.aleo", "transfer_public"),
+        inputs.iter(),
+        None,
+        priority_fee,
+        Some(query),
+        &mut rand::thread_rng(),
+    )
+}
+
+fn now_unix_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
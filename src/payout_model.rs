@@ -0,0 +1,650 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use parking_lot::RwLock;
+use savefile::{load_from_mem, save_to_mem};
+use savefile_derive::Savefile;
+use tracing::{debug, info};
+
+/// Number of confirmed solutions the FPPS reward estimate is averaged over.
+static FPPS_REWARD_WINDOW: u64 = 16;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum PayoutModelKind {
+    /// Pay per last N shares
+    Pplns,
+    /// Pay per shares of the last T minutes
+    Pplnt,
+    /// Proportional to the shares since the previous solution
+    Prop,
+    /// Pay per share, based on a fixed expected reward
+    Pps,
+    /// Pay per share, with rewards above the expected reward paid out as PPLNS
+    PpsPlus,
+    /// Pay per share, based on the average reward of recent solutions
+    Fpps,
+}
+
+pub trait PayoutModel: Send + Sync {
+    fn add_share(&mut self, share: Share);
+
+    /// Called with the network proof target of every new puzzle.
+    fn set_proof_target(&mut self, proof_target: u64);
+
+    /// Earnings credited right away for a share of the given difficulty.
+    fn credit_share(&self, _share: &Share, _difficulty: u64) -> Option<u64> {
+        None
+    }
+
+    /// Shares the reward of a solution found now is distributed over.
+    fn solution_shares(&mut self) -> HashMap<String, u64>;
+
+    /// Part of the reward of a confirmed solution that is distributed over its shares.
+    /// The rest is kept by the pool to cover the per-share earnings.
+    fn solution_reward(&self, reward: u64) -> u64 {
+        reward
+    }
+
+    /// Called once the reward of a solution has been paid.
+    fn solution_matured(&mut self, _reward: u64) {}
+
+    /// Target and current size of the round, as (n, current_n).
+    fn round(&self) -> (u64, u64);
+
+    /// Shares of every address in the current round. The map is not changed once returned, models
+    /// that keep it up to date copy it on their next change.
+    fn round_shares(&self) -> Arc<HashMap<String, u64>>;
+
+    /// Adds a share read back from the share log, found at the given unix timestamp.
+    fn replay_share(&mut self, share: Share, _timestamp: u64) {
+        self.add_share(share);
+    }
+
+    /// Serialized state, see `share_log`.
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        Ok(vec![])
+    }
+
+    fn restore(&mut self, _snapshot: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub struct PayoutSettings {
+    pub kind: PayoutModelKind,
+    /// Expected reward of a solution, for the per-share models
+    pub expected_reward: Option<u64>,
+    /// Size of the PPLNS window, as a multiple of the network proof target
+    pub n_multiplier: u64,
+    /// Age of the oldest share in the PPLNT window
+    pub pplnt_window: Duration,
+    pub fees: FeeSettings,
+    /// Blocks on top of the one including a solution before its reward is credited
+    pub maturity_blocks: u32,
+}
+
+/// Who gets a cut of the solution rewards besides the provers.
+#[derive(Clone, Debug, Default)]
+pub struct FeeSettings {
+    /// Pool fee in basis points
+    pub bps: u64,
+    /// Address credited with the pool fee left after the splits and referrals
+    pub address: Option<String>,
+    /// (address, basis points of the pool fee), e.g. a development fund
+    pub splits: Vec<(String, u64)>,
+    /// Address credited with the donations provers choose when authorizing
+    pub donation_address: Option<String>,
+    /// Referral reward in basis points of the referred prover's earnings, paid out of the pool fee
+    pub referral_bps: u64,
+}
+
+/// Creates an empty payout model, the state is recovered by `share_log`.
+pub fn new(settings: &PayoutSettings) -> Result<Box<dyn PayoutModel>> {
+    let expected_reward = || {
+        settings
+            .expected_reward
+            .ok_or_else(|| anyhow!("The {:?} payout model needs an expected reward", settings.kind))
+    };
+    info!("Using the {:?} payout model", settings.kind);
+    Ok(match settings.kind {
+        PayoutModelKind::Pplns => Box::new(PPLNS::new(settings.n_multiplier)),
+        PayoutModelKind::Pplnt => Box::new(PPLNT::new(settings.pplnt_window)),
+        PayoutModelKind::Prop => Box::new(PROP::default()),
+        PayoutModelKind::Pps => Box::new(PPS::new(expected_reward()?, settings.fees.bps)),
+        PayoutModelKind::PpsPlus => Box::new(PPSPlus {
+            pps: PPS::new(expected_reward()?, settings.fees.bps),
+            pplns: PPLNS::new(settings.n_multiplier),
+        }),
+        PayoutModelKind::Fpps => Box::new(FPPS {
+            pps: PPS::new(expected_reward()?, settings.fees.bps),
+        }),
+    })
+}
+
+fn sum_shares<'a>(shares: impl Iterator<Item = &'a Share>) -> HashMap<String, u64> {
+    let mut address_shares = HashMap::new();
+    let time = Instant::now();
+    shares.for_each(|share| {
+        if let Some(shares) = address_shares.get_mut(&share.owner) {
+            *shares += share.value;
+        } else {
+            address_shares.insert(share.owner.clone(), share.value);
+        }
+    });
+    debug!("Summing shares took {} us", time.elapsed().as_micros());
+    address_shares
+}
+
+#[derive(Clone, Savefile)]
+pub struct Share {
+    value: u64,
+    owner: String,
+}
+
+impl Share {
+    pub fn init(value: u64, owner: String) -> Self {
+        Share { value, owner }
+    }
+
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+}
+
+/// Shares are paid until N worth of newer shares pushed them out of the window. N follows the
+/// network proof target, so the window covers about `n_multiplier` solutions.
+///
+/// A prover that disconnects keeps its shares in the window for as long as the rest of the pool
+/// needs to fill it, no matter how long that takes. A prover that comes back later starts from
+/// whatever is left of its old shares.
+///
+/// The shares of every address in the window are kept up to date as shares come in and fall out,
+/// so reading them does not go through the queue. Readers get the totals behind an `Arc`, which is
+/// only copied when the queue changes while a reader still holds it.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Savefile)]
+pub struct PPLNS {
+    queue: VecDeque<Share>,
+    current_n: Arc<RwLock<u64>>,
+    n: Arc<RwLock<u64>>,
+    #[savefile_ignore]
+    n_multiplier: u64,
+    /// Shares of every address in the queue, rebuilt from it on restore
+    #[savefile_ignore]
+    totals: Arc<HashMap<String, u64>>,
+}
+
+impl PPLNS {
+    pub fn new(n_multiplier: u64) -> Self {
+        PPLNS {
+            queue: VecDeque::new(),
+            current_n: Default::default(),
+            n: Default::default(),
+            n_multiplier,
+            totals: Default::default(),
+        }
+    }
+
+    /// Drops the oldest share, returns its value.
+    fn pop_front(queue: &mut VecDeque<Share>, totals: &mut Arc<HashMap<String, u64>>) -> u64 {
+        let share = queue.pop_front().unwrap();
+        let totals = Arc::make_mut(totals);
+        if let Some(total) = totals.get_mut(&share.owner) {
+            *total -= share.value;
+            if *total == 0 {
+                totals.remove(&share.owner);
+            }
+        }
+        share.value
+    }
+
+    pub fn set_n(&mut self, n: u64) {
+        let start = Instant::now();
+        let mut current_n = self.current_n.write();
+        let mut self_n = self.n.write();
+        if n < *self_n {
+            while *current_n > n {
+                *current_n -= Self::pop_front(&mut self.queue, &mut self.totals);
+            }
+        }
+        *self_n = n;
+        debug!("set_n took {} us", start.elapsed().as_micros());
+    }
+}
+
+impl PayoutModel for PPLNS {
+    fn add_share(&mut self, share: Share) {
+        let start = Instant::now();
+        *Arc::make_mut(&mut self.totals).entry(share.owner.clone()).or_default() += share.value;
+        let mut current_n = self.current_n.write();
+        let self_n = self.n.read();
+        *current_n += share.value;
+        self.queue.push_back(share);
+        while *current_n > *self_n {
+            *current_n -= Self::pop_front(&mut self.queue, &mut self.totals);
+        }
+        debug!("add_share took {} us", start.elapsed().as_micros());
+        debug!("n: {} / {}", *current_n, self_n);
+    }
+
+    fn set_proof_target(&mut self, proof_target: u64) {
+        let n = proof_target.saturating_mul(self.n_multiplier);
+        self.set_n(n);
+        debug!("Set N to {}", n);
+    }
+
+    fn solution_shares(&mut self) -> HashMap<String, u64> {
+        (*self.totals).clone()
+    }
+
+    fn round(&self) -> (u64, u64) {
+        (*self.n.read(), *self.current_n.read())
+    }
+
+    fn round_shares(&self) -> Arc<HashMap<String, u64>> {
+        self.totals.clone()
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        save_to_mem(0, self).map_err(|e| anyhow!("Failed to save PPLNS state: {}", e))
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        let pplns = load_from_mem::<PPLNS>(snapshot, 0).map_err(|e| anyhow!("Failed to load PPLNS state: {}", e))?;
+        self.queue = pplns.queue;
+        self.current_n = pplns.current_n;
+        self.n = pplns.n;
+        self.totals = Arc::new(sum_shares(self.queue.iter()));
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Shares of the last T minutes, each weighted by its difficulty.
+///
+/// Unlike PPLNS, shares expire with time and not with the work of the other provers: a prover that
+/// disconnects is paid for the solutions found in the T minutes after its last share, and nothing
+/// after that, even if the pool finds no solution in the meantime. Shares found before a restart
+/// are kept as long as they are still inside the window.
+///
+/// As with PPLNS, the shares of every address are kept up to date as shares come in and expire.
+/// Shares that expired since the last change are left out when reading the round.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Savefile)]
+pub struct PPLNT {
+    /// (unix timestamp, share)
+    queue: VecDeque<(u64, Share)>,
+    total: u64,
+    #[savefile_ignore]
+    window: u64,
+    /// Shares of every address in the queue, rebuilt from it on restore
+    #[savefile_ignore]
+    totals: Arc<HashMap<String, u64>>,
+}
+
+impl PPLNT {
+    pub fn new(window: Duration) -> Self {
+        PPLNT {
+            queue: VecDeque::new(),
+            total: 0,
+            window: window.as_secs(),
+            totals: Default::default(),
+        }
+    }
+
+    /// Shares at the front of the queue that are out of the window at `now`.
+    fn expired(&self, now: u64) -> impl Iterator<Item = &Share> {
+        self.queue
+            .iter()
+            .take_while(move |(timestamp, _)| timestamp + self.window < now)
+            .map(|(_, share)| share)
+    }
+
+    fn expire(&mut self, now: u64) {
+        while let Some((timestamp, _)) = self.queue.front() {
+            if timestamp + self.window >= now {
+                break;
+            }
+            let (_, share) = self.queue.pop_front().unwrap();
+            self.total -= share.value;
+            let totals = Arc::make_mut(&mut self.totals);
+            if let Some(total) = totals.get_mut(&share.owner) {
+                *total -= share.value;
+                if *total == 0 {
+                    totals.remove(&share.owner);
+                }
+            }
+        }
+    }
+}
+
+impl PayoutModel for PPLNT {
+    fn add_share(&mut self, share: Share) {
+        self.replay_share(share, now());
+    }
+
+    fn set_proof_target(&mut self, _proof_target: u64) {}
+
+    fn solution_shares(&mut self) -> HashMap<String, u64> {
+        self.expire(now());
+        (*self.totals).clone()
+    }
+
+    fn round(&self) -> (u64, u64) {
+        let total = self.total - self.expired(now()).map(|share| share.value).sum::<u64>();
+        (total, total)
+    }
+
+    fn round_shares(&self) -> Arc<HashMap<String, u64>> {
+        let mut expired = self.expired(now()).peekable();
+        if expired.peek().is_none() {
+            return self.totals.clone();
+        }
+        let mut totals = (*self.totals).clone();
+        for share in expired {
+            if let Some(total) = totals.get_mut(&share.owner) {
+                *total -= share.value;
+                if *total == 0 {
+                    totals.remove(&share.owner);
+                }
+            }
+        }
+        Arc::new(totals)
+    }
+
+    fn replay_share(&mut self, share: Share, timestamp: u64) {
+        self.total += share.value;
+        *Arc::make_mut(&mut self.totals).entry(share.owner.clone()).or_default() += share.value;
+        self.queue.push_back((timestamp, share));
+        self.expire(now());
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        save_to_mem(0, self).map_err(|e| anyhow!("Failed to save PPLNT state: {}", e))
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        let pplnt = load_from_mem::<PPLNT>(snapshot, 0).map_err(|e| anyhow!("Failed to load PPLNT state: {}", e))?;
+        self.queue = pplnt.queue;
+        self.total = pplnt.total;
+        self.totals = Arc::new(sum_shares(self.queue.iter().map(|(_, share)| share)));
+        self.expire(now());
+        Ok(())
+    }
+}
+
+/// Shares since the previous solution, the round restarts with every solution.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Default, Savefile)]
+pub struct PROP {
+    shares: HashMap<String, u64>,
+    total: u64,
+}
+
+impl PayoutModel for PROP {
+    fn add_share(&mut self, share: Share) {
+        *self.shares.entry(share.owner).or_default() += share.value;
+        self.total += share.value;
+    }
+
+    fn set_proof_target(&mut self, _proof_target: u64) {}
+
+    fn solution_shares(&mut self) -> HashMap<String, u64> {
+        self.total = 0;
+        std::mem::take(&mut self.shares)
+    }
+
+    fn round(&self) -> (u64, u64) {
+        (self.total, self.total)
+    }
+
+    fn round_shares(&self) -> Arc<HashMap<String, u64>> {
+        Arc::new(self.shares.clone())
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        save_to_mem(0, self).map_err(|e| anyhow!("Failed to save PROP state: {}", e))
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        *self = load_from_mem::<PROP>(snapshot, 0).map_err(|e| anyhow!("Failed to load PROP state: {}", e))?;
+        Ok(())
+    }
+}
+
+/// Every share earns its expected value right away: the reward of a solution times the probability
+/// of the share being a solution, minus the pool fee. Solution rewards are kept by the pool.
+#[allow(clippy::upper_case_acronyms)]
+pub struct PPS {
+    expected_reward: u64,
+    proof_target: u64,
+    fee_bps: u64,
+}
+
+impl PPS {
+    pub fn new(expected_reward: u64, fee_bps: u64) -> Self {
+        PPS {
+            expected_reward,
+            proof_target: u64::MAX,
+            fee_bps,
+        }
+    }
+}
+
+impl PayoutModel for PPS {
+    fn add_share(&mut self, _share: Share) {}
+
+    fn set_proof_target(&mut self, proof_target: u64) {
+        self.proof_target = proof_target.max(1);
+    }
+
+    fn credit_share(&self, _share: &Share, difficulty: u64) -> Option<u64> {
+        let value = self.expected_reward as u128 * difficulty.min(self.proof_target) as u128 / self.proof_target as u128;
+        Some((value * (10000 - self.fee_bps) as u128 / 10000) as u64)
+    }
+
+    fn solution_shares(&mut self) -> HashMap<String, u64> {
+        HashMap::new()
+    }
+
+    fn solution_reward(&self, _reward: u64) -> u64 {
+        0
+    }
+
+    fn round(&self) -> (u64, u64) {
+        (0, 0)
+    }
+
+    fn round_shares(&self) -> Arc<HashMap<String, u64>> {
+        Default::default()
+    }
+}
+
+/// PPS for the expected reward, and PPLNS for whatever a solution earns above it.
+pub struct PPSPlus {
+    pps: PPS,
+    pplns: PPLNS,
+}
+
+impl PayoutModel for PPSPlus {
+    fn add_share(&mut self, share: Share) {
+        self.pplns.add_share(share);
+    }
+
+    fn set_proof_target(&mut self, proof_target: u64) {
+        self.pps.set_proof_target(proof_target);
+        self.pplns.set_proof_target(proof_target);
+    }
+
+    fn credit_share(&self, share: &Share, difficulty: u64) -> Option<u64> {
+        self.pps.credit_share(share, difficulty)
+    }
+
+    fn solution_shares(&mut self) -> HashMap<String, u64> {
+        self.pplns.solution_shares()
+    }
+
+    fn solution_reward(&self, reward: u64) -> u64 {
+        reward.saturating_sub(self.pps.expected_reward)
+    }
+
+    fn round(&self) -> (u64, u64) {
+        self.pplns.round()
+    }
+
+    fn round_shares(&self) -> Arc<HashMap<String, u64>> {
+        self.pplns.round_shares()
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        self.pplns.snapshot()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        self.pplns.restore(snapshot)
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Savefile)]
+struct FPPSState {
+    expected_reward: u64,
+}
+
+/// PPS where the expected reward follows the rewards of the solutions the pool actually gets,
+/// starting from the configured one.
+#[allow(clippy::upper_case_acronyms)]
+pub struct FPPS {
+    pps: PPS,
+}
+
+impl PayoutModel for FPPS {
+    fn add_share(&mut self, _share: Share) {}
+
+    fn set_proof_target(&mut self, proof_target: u64) {
+        self.pps.set_proof_target(proof_target);
+    }
+
+    fn credit_share(&self, share: &Share, difficulty: u64) -> Option<u64> {
+        self.pps.credit_share(share, difficulty)
+    }
+
+    fn solution_shares(&mut self) -> HashMap<String, u64> {
+        HashMap::new()
+    }
+
+    fn solution_reward(&self, _reward: u64) -> u64 {
+        0
+    }
+
+    fn solution_matured(&mut self, reward: u64) {
+        let expected_reward = &mut self.pps.expected_reward;
+        *expected_reward = (*expected_reward * (FPPS_REWARD_WINDOW - 1) + reward) / FPPS_REWARD_WINDOW;
+        debug!("FPPS expected reward is now {}", expected_reward);
+    }
+
+    fn round(&self) -> (u64, u64) {
+        (0, 0)
+    }
+
+    fn round_shares(&self) -> Arc<HashMap<String, u64>> {
+        Default::default()
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        let state = FPPSState {
+            expected_reward: self.pps.expected_reward,
+        };
+        save_to_mem(0, &state).map_err(|e| anyhow!("Failed to save FPPS state: {}", e))
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        let state =
+            load_from_mem::<FPPSState>(snapshot, 0).map_err(|e| anyhow!("Failed to load FPPS state: {}", e))?;
+        self.pps.expected_reward = state.expected_reward;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60 * 10);
+
+    #[test]
+    fn pplnt_keeps_every_share_of_the_window_whatever_their_count() {
+        let mut pplnt = PPLNT::new(WINDOW);
+        let recent = now() - 60;
+        for i in 0..100_000 {
+            pplnt.replay_share(Share::init(1_000_000, format!("prover{}", i % 3)), recent);
+        }
+        pplnt.add_share(Share::init(1, "late".to_string()));
+
+        let shares = pplnt.round_shares();
+        assert_eq!(shares.values().sum::<u64>(), 100_000 * 1_000_000 + 1);
+        assert_eq!(shares["late"], 1);
+        assert_eq!(pplnt.round(), (100_000 * 1_000_000 + 1, 100_000 * 1_000_000 + 1));
+    }
+
+    #[test]
+    fn pplnt_drops_shares_older_than_the_window() {
+        let mut pplnt = PPLNT::new(WINDOW);
+        let window = WINDOW.as_secs();
+        // A second of margin on each side of the cut for the clock to tick during the test
+        pplnt.replay_share(Share::init(5, "expired".to_string()), now() - window - 2);
+        pplnt.replay_share(Share::init(7, "kept".to_string()), now() - window + 2);
+        pplnt.replay_share(Share::init(11, "kept".to_string()), now());
+
+        let shares = pplnt.solution_shares();
+        assert_eq!(shares.get("expired"), None);
+        assert_eq!(shares["kept"], 18);
+        assert_eq!(pplnt.round(), (18, 18));
+    }
+
+    #[test]
+    fn pplnt_round_and_shares_agree_once_shares_expire() {
+        let mut pplnt = PPLNT::new(Duration::from_secs(1));
+        pplnt.add_share(Share::init(3, "prover".to_string()));
+        assert_eq!(pplnt.round(), (3, 3));
+        assert_eq!(pplnt.round_shares()["prover"], 3);
+
+        // Nothing comes in to prune the queue, reading the round leaves the expired share out
+        std::thread::sleep(Duration::from_millis(2500));
+        assert_eq!(pplnt.round(), (0, 0));
+        assert!(pplnt.round_shares().is_empty());
+        assert!(pplnt.solution_shares().is_empty());
+    }
+
+    #[test]
+    fn pplnt_shares_decay_with_time() {
+        let mut pplnt = PPLNT::new(WINDOW);
+        let window = WINDOW.as_secs();
+        let start = now() - window * 3;
+        // One share a minute for three windows, only the last window of them counts
+        for minute in 0..=(window * 3 / 60) {
+            pplnt.replay_share(Share::init(1, "prover".to_string()), start + minute * 60);
+        }
+        let counted = pplnt.round_shares()["prover"];
+        assert!((10..=11).contains(&counted), "{} shares counted", counted);
+
+        // Restoring a snapshot expires what aged out since
+        let snapshot = pplnt.snapshot().unwrap();
+        let mut restored = PPLNT::new(Duration::from_secs(60 * 5));
+        restored.restore(&snapshot).unwrap();
+        let counted = restored.round_shares()["prover"];
+        assert!((5..=6).contains(&counted), "{} shares counted", counted);
+    }
+}
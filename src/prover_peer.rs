@@ -1,17 +1,20 @@
-
 use std::{
     str::FromStr,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU32, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
 use futures_util::sink::SinkExt;
 use rand::{rngs::OsRng, Rng};
 use snarkos_account::Account;
 use snarkos_node_router_messages::{
+    BlockRequest,
+    BlockResponse,
     ChallengeRequest,
     ChallengeResponse,
     MessageCodec,
@@ -20,8 +23,13 @@ use snarkos_node_router_messages::{
     Pong,
     PuzzleRequest,
     PuzzleResponse,
+    UnconfirmedSolution,
+    UnconfirmedTransaction,
+};
+use snarkvm::{
+    ledger::puzzle::Solution,
+    prelude::{Block, Field, FromBytes, Network, Transaction},
 };
-use snarkvm::prelude::{Block, Field, FromBytes, Network};
 use snarkvm_ledger_narwhal_data::Data;
 use tokio::{
     net::TcpStream,
@@ -38,40 +46,160 @@ use tokio_stream::StreamExt;
 use tokio_util::codec::Framed;
 use tracing::{debug, error, info, trace, warn};
 
-use crate::{ServerMessage, N};
+use crate::{
+    solution_tracker::{SolutionEvent, SolutionTracker},
+    work_source::WorkSource,
+    AccountingMessage,
+    ServerMessage,
+};
+
+/// Timings of the link to the snarkOS node.
+///
+/// Besides the regular puzzle requests, a new epoch is detected by following the block locators
+/// peers attach to their pings, and by polling at `fast_poll_interval` once the chain is within
+/// `epoch_boundary_window` blocks of the next epoch.
+#[derive(Clone, Copy, Debug)]
+pub struct NodeTimings {
+    /// Interval between two `PuzzleRequest`s
+    pub puzzle_request_interval: Duration,
+    /// Interval between two `PuzzleRequest`s close to an epoch boundary
+    pub fast_poll_interval: Duration,
+    /// Number of blocks before an epoch boundary from which the fast poll interval is used
+    pub epoch_boundary_window: u32,
+    /// Interval between two `Ping`s
+    pub ping_interval: Duration,
+    /// Timeout of the TCP connection to the node
+    pub connect_timeout: Duration,
+    /// Delay before reconnecting after the link failed
+    pub reconnect_delay: Duration,
+}
 
-pub struct Node {
+impl Default for NodeTimings {
+    fn default() -> Self {
+        Self {
+            puzzle_request_interval: Duration::from_secs(15),
+            fast_poll_interval: Duration::from_secs(1),
+            epoch_boundary_window: 2,
+            ping_interval: Duration::from_secs(5),
+            connect_timeout: Duration::from_secs(5),
+            reconnect_delay: Duration::from_secs(25),
+        }
+    }
+}
+
+pub struct Node<N: Network> {
     operator: String,
-    sender: Arc<Sender<SnarkOSMessage>>,
-    receiver: Arc<Mutex<Receiver<SnarkOSMessage>>>,
-    pending_solutions: Arc<RwLock<Vec<SnarkOSMessage>>>,
+    genesis_path: Option<String>,
+    timings: NodeTimings,
+    sender: Arc<Sender<SnarkOSMessage<N>>>,
+    receiver: Arc<Mutex<Receiver<SnarkOSMessage<N>>>>,
+    pending_solutions: Arc<RwLock<Vec<SnarkOSMessage<N>>>>,
+    connected: Arc<AtomicBool>,
+    accounting_sender: Sender<AccountingMessage>,
+    solution_tracker: Arc<RwLock<SolutionTracker<N>>>,
+    // Latest block height and epoch known from the node or its pings
+    latest_height: Arc<AtomicU32>,
+    latest_epoch: Arc<AtomicU32>,
 }
 
-pub(crate) type SnarkOSMessage = snarkos_node_router_messages::Message<N>;
+pub(crate) type SnarkOSMessage<N> = snarkos_node_router_messages::Message<N>;
 
-impl Node {
-    pub fn init(operator: String) -> Self {
+impl<N: Network> Node<N> {
+    pub fn init(
+        operator: String,
+        genesis_path: Option<String>,
+        timings: NodeTimings,
+        accounting_sender: Sender<AccountingMessage>,
+    ) -> Self {
         let (sender, receiver) = mpsc::channel(1024);
         Self {
             operator,
+            genesis_path,
+            timings,
             sender: Arc::new(sender),
             receiver: Arc::new(Mutex::new(receiver)),
             pending_solutions: Default::default(),
+            connected: Default::default(),
+            accounting_sender,
+            solution_tracker: Default::default(),
+            latest_height: Default::default(),
+            latest_epoch: Default::default(),
         }
     }
 
-    pub fn receiver(&self) -> Arc<Mutex<Receiver<SnarkOSMessage>>> {
+    pub fn receiver(&self) -> Arc<Mutex<Receiver<SnarkOSMessage<N>>>> {
         self.receiver.clone()
     }
 
-    pub fn sender(&self) -> Arc<Sender<SnarkOSMessage>> {
+    pub fn sender(&self) -> Arc<Sender<SnarkOSMessage<N>>> {
         self.sender.clone()
     }
 }
 
-pub fn start(node: Node, server_sender: Sender<ServerMessage>, genesis_path: Option<String>) {
+impl<N: Network> WorkSource<N> for Node<N> {
+    fn start(&self, server_sender: Sender<ServerMessage<N>>) {
+        start(self, server_sender);
+    }
+
+    fn submit_solution(&self, solution: Solution<N>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.solution_tracker.write().await.submit(
+                solution.id(),
+                self.latest_epoch.load(Ordering::SeqCst),
+                self.latest_height.load(Ordering::SeqCst),
+            );
+            self.sender
+                .send(SnarkOSMessage::UnconfirmedSolution(UnconfirmedSolution {
+                    solution_id: solution.id(),
+                    solution: Data::Object(solution),
+                }))
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn broadcast_transaction(&self, transaction: Transaction<N>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.sender
+                .send(SnarkOSMessage::UnconfirmedTransaction(UnconfirmedTransaction {
+                    transaction_id: transaction.id(),
+                    transaction: Data::Object(transaction),
+                }))
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn request_puzzle(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            if !self.connected.load(Ordering::SeqCst) {
+                return Err(anyhow!("Not connected to the node"));
+            }
+            self.sender.send(SnarkOSMessage::PuzzleRequest(PuzzleRequest {})).await?;
+            Ok(())
+        })
+    }
+
+    fn connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    fn pending_solutions(&self) -> BoxFuture<'_, usize> {
+        Box::pin(async move { self.pending_solutions.read().await.len() })
+    }
+}
+
+fn start<N: Network>(node: &Node<N>, server_sender: Sender<ServerMessage<N>>) {
+    let operator = node.operator.clone();
+    let genesis_path = node.genesis_path.clone();
+    let timings = node.timings;
+    let pending_solutions = node.pending_solutions.clone();
     let receiver = node.receiver();
     let sender = node.sender();
+    let accounting_sender = node.accounting_sender.clone();
+    let solution_tracker = node.solution_tracker.clone();
+    let latest_height = node.latest_height.clone();
+    let latest_epoch = node.latest_epoch.clone();
     task::spawn(async move {
         let genesis_header = match genesis_path {
             Some(path) => {
@@ -80,39 +208,65 @@ pub fn start(node: Node, server_sender: Sender<ServerMessage>, genesis_path: Opt
             }
             None => *Block::<N>::from_bytes_le(N::genesis_bytes()).unwrap().header(),
         };
-        let connected = Arc::new(AtomicBool::new(false));
+        let connected = node.connected.clone();
         let peer_sender = sender.clone();
         let peer_sender_ping = sender.clone();
 
         let connected_req = connected.clone();
         let connected_ping = connected.clone();
-        let pending_req = node.pending_solutions.clone();
+        let pending_req = pending_solutions.clone();
+        let latest_height_req = latest_height.clone();
+        let solution_tracker_req = solution_tracker.clone();
         task::spawn(async move {
+            let mut last_request = Instant::now();
             loop {
-                sleep(Duration::from_secs(15)).await;
+                sleep(timings.fast_poll_interval.min(timings.puzzle_request_interval)).await;
                 if connected_req.load(Ordering::SeqCst) {
+                    let height = latest_height_req.load(Ordering::SeqCst);
+                    let block_request = solution_tracker_req.write().await.next_request(height);
+                    if let Some((start_height, end_height)) = block_request {
+                        trace!("Requesting blocks {} to {}", start_height, end_height);
+                        if let Err(e) = peer_sender
+                            .send(SnarkOSMessage::BlockRequest(BlockRequest { start_height, end_height }))
+                            .await
+                        {
+                            error!("Failed to send block request: {}", e);
+                        }
+                    }
+                    let blocks_left = N::NUM_BLOCKS_PER_EPOCH - height % N::NUM_BLOCKS_PER_EPOCH;
+                    let near_boundary = height > 0 && blocks_left <= timings.epoch_boundary_window;
+                    let due = last_request.elapsed() >= timings.puzzle_request_interval;
+                    if !due && !near_boundary {
+                        continue;
+                    }
                     if let Err(e) = peer_sender.send(SnarkOSMessage::PuzzleRequest(PuzzleRequest {})).await {
                         error!("Failed to send puzzle request: {}", e);
                     }
-                    let mut pending_solutions = pending_req.write().await.clone();
-                    let mut failed_solutions: Vec<SnarkOSMessage> = vec![];
+                    last_request = Instant::now();
+                    if !due {
+                        continue;
+                    }
+                    // Taken out of the queue, they are queued again if the node is gone by the time they
+                    // are sent
+                    let mut pending_solutions = std::mem::take(&mut *pending_req.write().await);
+                    let mut failed_solutions: Vec<SnarkOSMessage<N>> = vec![];
                     while let Some(message) = pending_solutions.pop() {
                         if let Err(e) = peer_sender.send(message.clone()).await {
                             failed_solutions.push(message);
                             error!("Failed to send puzzle request: {}", e);
                         }
                     }
-                    pending_solutions.extend(failed_solutions);
+                    pending_req.write().await.extend(failed_solutions);
                 }
             }
         });
         task::spawn(async move {
             loop {
-                sleep(Duration::from_secs(5)).await;
+                sleep(timings.ping_interval).await;
                 if connected_ping.load(Ordering::SeqCst) {
                     if let Err(e) = peer_sender_ping
                         .send(SnarkOSMessage::Ping(Ping {
-                            version: SnarkOSMessage::VERSION,
+                            version: SnarkOSMessage::<N>::VERSION,
                             node_type: NodeType::Prover,
                             block_locators: None,
                         }))
@@ -128,13 +282,13 @@ pub fn start(node: Node, server_sender: Sender<ServerMessage>, genesis_path: Opt
         let random_account = Account::new(rng).unwrap();
         loop {
             info!("Connecting to operator...");
-            match timeout(Duration::from_secs(5), TcpStream::connect(&node.operator)).await {
+            match timeout(timings.connect_timeout, TcpStream::connect(&operator)).await {
                 Ok(socket) => match socket {
                     Ok(socket) => {
-                        info!("Connected to {}", node.operator);
+                        info!("Connected to {}", operator);
                         let mut framed: Framed<TcpStream, MessageCodec<N>> = Framed::new(socket, Default::default());
                         let challenge = SnarkOSMessage::ChallengeRequest(ChallengeRequest {
-                            version: SnarkOSMessage::VERSION,
+                            version: SnarkOSMessage::<N>::VERSION,
                             listener_port: 4140,
                             node_type: NodeType::Prover,
                             address: random_account.address(),
@@ -150,14 +304,14 @@ pub fn start(node: Node, server_sender: Sender<ServerMessage>, genesis_path: Opt
                             tokio::select! {
                                 Some(message) = receiver.recv() => {
                                     match message {
-                                        SnarkOSMessage::UnconfirmedSolution(..) => {
+                                        SnarkOSMessage::UnconfirmedSolution(..) | SnarkOSMessage::UnconfirmedTransaction(..) => {
                                             if connected.load(Ordering::SeqCst) {
                                                 trace!("Sending {} to validator", message.name());
                                                 if let Err(e) = framed.send(message.clone()).await {
                                                     error!("Error sending {}: {:?}", message.name(), e);
                                                 }
                                             } else {
-                                                node.pending_solutions.write().await.push(message);
+                                                pending_solutions.write().await.push(message);
                                             }
                                         }
                                         _ => {
@@ -179,14 +333,14 @@ pub fn start(node: Node, server_sender: Sender<ServerMessage>, genesis_path: Opt
                                                 address: _,
                                                 nonce,
                                             }) => {
-                                                if version < SnarkOSMessage::VERSION {
+                                                if version < SnarkOSMessage::<N>::VERSION {
                                                     error!("Peer is running an older version of the protocol");
-                                                    sleep(Duration::from_secs(25)).await;
+                                                    sleep(timings.reconnect_delay).await;
                                                     break;
                                                 }
                                                 if node_type != NodeType::Validator && node_type != NodeType::Client {
                                                     error!("Peer is not a beacon or validator");
-                                                    sleep(Duration::from_secs(25)).await;
+                                                    sleep(timings.reconnect_delay).await;
                                                     break;
                                                 }
                                                 let resp_nonce: u64 = rng.gen();
@@ -209,27 +363,31 @@ pub fn start(node: Node, server_sender: Sender<ServerMessage>, genesis_path: Opt
                                                     }
                                                     false => {
                                                         error!("Peer has a different genesis block");
-                                                        sleep(Duration::from_secs(25)).await;
+                                                        sleep(timings.reconnect_delay).await;
                                                         break;
                                                     }
                                                 }
                                             }
-                                            SnarkOSMessage::Ping(..) => {
+                                            SnarkOSMessage::Ping(ping) => {
                                                 let pong = SnarkOSMessage::Pong(Pong { is_fork: None });
                                                 if let Err(e) = framed.send(pong).await {
                                                     error!("Error sending pong: {:?}", e);
                                                 } else {
                                                     debug!("Sent pong");
                                                 }
-                                                let message = SnarkOSMessage::Ping(Ping {
-                                                    version: SnarkOSMessage::VERSION,
-                                                    node_type: NodeType::Prover,
-                                                    block_locators: None,
-                                                });
-                                                if let Err(e) = framed.send(message).await {
-                                                    error!("Error sending ping: {:?}", e);
-                                                } else {
-                                                    debug!("Sent ping");
+                                                if let Some(block_locators) = ping.block_locators {
+                                                    let height = block_locators.latest_locator_height();
+                                                    if latest_height.fetch_max(height, Ordering::SeqCst) < height {
+                                                        if let Err(e) = accounting_sender.send(AccountingMessage::BlockHeight(height)).await {
+                                                            error!("Error sending block height to accounting: {}", e);
+                                                        }
+                                                    }
+                                                    if height / N::NUM_BLOCKS_PER_EPOCH > latest_epoch.load(Ordering::SeqCst) {
+                                                        debug!("Peer reached block {}, which starts a new epoch", height);
+                                                        if let Err(e) = framed.send(SnarkOSMessage::PuzzleRequest(PuzzleRequest {})).await {
+                                                            error!("Error sending puzzle request: {:?}", e);
+                                                        }
+                                                    }
                                                 }
                                             }
                                             SnarkOSMessage::Pong(..) => {
@@ -249,23 +407,59 @@ pub fn start(node: Node, server_sender: Sender<ServerMessage>, genesis_path: Opt
                                                     Err(error) => {
                                                         error!("Error deserializing block header: {:?}", error);
                                                         connected.store(false, Ordering::SeqCst);
-                                                        sleep(Duration::from_secs(25)).await;
+                                                        sleep(timings.reconnect_delay).await;
                                                         break;
                                                     }
                                                 };
-                                                let epoch_number = block_header.metadata().height() / N::NUM_BLOCKS_PER_EPOCH;
+                                                let height = block_header.metadata().height();
+                                                let epoch_number = height / N::NUM_BLOCKS_PER_EPOCH;
+                                                if latest_height.fetch_max(height, Ordering::SeqCst) < height {
+                                                    if let Err(e) = accounting_sender.send(AccountingMessage::BlockHeight(height)).await {
+                                                        error!("Error sending block height to accounting: {}", e);
+                                                    }
+                                                }
+                                                latest_epoch.fetch_max(epoch_number, Ordering::SeqCst);
                                                 if let Err(e) = server_sender.send(ServerMessage::NewEpochHash(
-                                                    epoch_hash, epoch_number, block_header.proof_target()
+                                                    epoch_hash, epoch_number, block_header.proof_target(), block_header.timestamp()
                                                 )).await {
                                                     error!("Error sending new epoch hash to pool server: {}", e);
                                                 } else {
                                                     trace!("Sent new epoch hash to pool server (epoch {})", epoch_number);
                                                 }
                                             }
+                                            SnarkOSMessage::BlockResponse(BlockResponse { request: _, blocks }) => {
+                                                let blocks = match blocks.deserialize().await {
+                                                    Ok(blocks) => blocks,
+                                                    Err(error) => {
+                                                        warn!("Error deserializing blocks: {:?}", error);
+                                                        continue;
+                                                    }
+                                                };
+                                                let mut events = vec![];
+                                                for block in blocks.iter() {
+                                                    events.extend(solution_tracker.write().await.process_block(block));
+                                                }
+                                                for event in events {
+                                                    let message = match event {
+                                                        SolutionEvent::Confirmed(solution_id, height, reward) => {
+                                                            AccountingMessage::SolutionConfirmed(solution_id.to_string(), height, reward)
+                                                        }
+                                                        SolutionEvent::Orphaned(solution_id) => {
+                                                            AccountingMessage::SolutionOrphaned(solution_id.to_string())
+                                                        }
+                                                        SolutionEvent::Dropped(solution_id) => {
+                                                            AccountingMessage::SolutionExpired(solution_id.to_string())
+                                                        }
+                                                    };
+                                                    if let Err(e) = accounting_sender.send(message).await {
+                                                        error!("Error sending solution status to accounting: {}", e);
+                                                    }
+                                                }
+                                            }
                                             SnarkOSMessage::Disconnect(message) => {
                                                 error!("Peer disconnected: {:?}", message.reason);
                                                 connected.store(false, Ordering::SeqCst);
-                                                sleep(Duration::from_secs(25)).await;
+                                                sleep(timings.reconnect_delay).await;
                                                 break;
                                             }
                                             _ => {
@@ -279,7 +473,7 @@ pub fn start(node: Node, server_sender: Sender<ServerMessage>, genesis_path: Opt
                                     None => {
                                         error!("Disconnected from operator");
                                         connected.store(false, Ordering::SeqCst);
-                                        sleep(Duration::from_secs(25)).await;
+                                        sleep(timings.reconnect_delay).await;
                                         break;
                                     }
                                 }
@@ -288,12 +482,12 @@ pub fn start(node: Node, server_sender: Sender<ServerMessage>, genesis_path: Opt
                     }
                     Err(e) => {
                         error!("Failed to connect to operator: {}", e);
-                        sleep(Duration::from_secs(25)).await;
+                        sleep(timings.reconnect_delay).await;
                     }
                 },
                 Err(_) => {
                     error!("Failed to connect to operator: Timed out");
-                    sleep(Duration::from_secs(25)).await;
+                    sleep(timings.reconnect_delay).await;
                 }
             }
         }
@@ -1,25 +1,23 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     fmt::{Display, Formatter},
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     sync::{
         atomic::{AtomicU32, AtomicU64, Ordering},
         Arc,
     },
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use aleo_stratum::{codec::ResponseParams, message::StratumMessage};
+use anyhow::{anyhow, Result};
 use flurry::HashSet as FlurryHashSet;
 use json_rpc_types::{Error, ErrorCode, Id};
-use snarkos_node_router_messages::UnconfirmedSolution;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use snarkvm::{
-    circuit::AleoV0,
     console::account::Address,
-    ledger::{
-        narwhal::Data,
-        puzzle::{PartialSolution, Puzzle, Solution},
-    },
+    ledger::puzzle::{PartialSolution, Puzzle, Solution},
     prelude::{Network, ToBytes},
 };
 use snarkvm_ledger_puzzle_epoch::SynthesisPuzzle;
@@ -34,11 +32,72 @@ use tokio::{
 };
 use tracing::{debug, error, info, trace, warn};
 
-use crate::{connection::Connection, prover_peer::SnarkOSMessage, AccountingMessage, N};
+#[cfg(feature = "storage")]
+use crate::state_storage::{Storage, StorageData, StorageType};
+use crate::{
+    connection::Connection,
+    events::{Events, PoolEvent},
+    metrics::{Metrics, RejectReason},
+    network::PoolNetwork,
+    work_source::WorkSource,
+    AccountingMessage,
+};
+
+static INITIAL_TARGET: u64 = 512;
+pub static BAN_DURATION: Duration = Duration::from_secs(60 * 60);
+pub static MAX_BAN_DURATION: Duration = Duration::from_secs(60 * 60 * 24 * 365);
+/// How long the session of a disconnected address is remembered
+static SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+fn now_unix_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// How prover targets follow their speed, adjustable at runtime through the admin API. Changes
+/// apply from the next epoch or puzzle refresh.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TargetSettings {
+    /// Modifier applied to every prover target instead of the one following the pool share rate
+    pub global_target_modifier: Option<f64>,
+    /// Seconds between two shares a prover target aims for
+    pub share_interval: f64,
+    /// Change of the ideal target, relative to the current one, before a prover is retargeted
+    pub retarget_threshold: f64,
+    /// Pool shares per second above which every target is raised
+    pub max_share_rate: f64,
+}
+
+impl Default for TargetSettings {
+    fn default() -> Self {
+        Self {
+            global_target_modifier: None,
+            share_interval: 20.0,
+            retarget_threshold: 0.1,
+            max_share_rate: 200.0,
+        }
+    }
+}
 
-type A = AleoV0;
+impl TargetSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.global_target_modifier.is_some_and(|modifier| !(modifier >= 1.0 && modifier.is_finite())) {
+            return Err(anyhow!("global_target_modifier must be at least 1"));
+        }
+        if !(self.share_interval > 0.0 && self.share_interval.is_finite()) {
+            return Err(anyhow!("share_interval must be positive"));
+        }
+        if !(0.0..1.0).contains(&self.retarget_threshold) {
+            return Err(anyhow!("retarget_threshold must be between 0 and 1"));
+        }
+        if !(self.max_share_rate > 0.0 && self.max_share_rate.is_finite()) {
+            return Err(anyhow!("max_share_rate must be positive"));
+        }
+        Ok(())
+    }
+}
 
-struct ProverState {
+struct ProverState<N: Network> {
     peer_addr: SocketAddr,
     address: Address<N>,
     speed_2m: Speedometer,
@@ -50,8 +109,8 @@ struct ProverState {
     next_target: u64,
 }
 
-impl ProverState {
-    pub fn new(peer_addr: SocketAddr, address: Address<N>) -> Self {
+impl<N: Network> ProverState<N> {
+    pub fn new(peer_addr: SocketAddr, address: Address<N>, target: u64) -> Self {
         Self {
             peer_addr,
             address,
@@ -60,25 +119,25 @@ impl ProverState {
             speed_15m: Speedometer::init_with_cache(Duration::from_secs(60 * 15), Duration::from_secs(30)),
             speed_30m: Speedometer::init_with_cache(Duration::from_secs(60 * 30), Duration::from_secs(30)),
             speed_1h: Speedometer::init_with_cache(Duration::from_secs(60 * 60), Duration::from_secs(30)),
-            current_target: 512,
-            next_target: 512,
+            current_target: target,
+            next_target: target,
         }
     }
 
-    pub async fn add_share(&mut self, value: u64) {
+    pub async fn add_share(&mut self, value: u64, share_interval: f64) {
         let now = Instant::now();
         self.speed_2m.event(value).await;
         self.speed_5m.event(value).await;
         self.speed_15m.event(value).await;
         self.speed_30m.event(value).await;
         self.speed_1h.event(value).await;
-        self.next_target = ((self.speed_2m.speed().await * 20.0) as u64).max(1);
+        self.next_target = ((self.speed_2m.speed().await * share_interval) as u64).max(1);
         debug!("add_share took {} us", now.elapsed().as_micros());
     }
 
-    pub async fn next_target(&mut self) -> u64 {
-        if self.next_target < ((self.current_target as f64) * 0.9) as u64
-            || self.next_target > ((self.current_target as f64) * 1.1) as u64
+    pub async fn next_target(&mut self, retarget_threshold: f64) -> u64 {
+        if self.next_target < ((self.current_target as f64) * (1.0 - retarget_threshold)) as u64
+            || self.next_target > ((self.current_target as f64) * (1.0 + retarget_threshold)) as u64
         {
             self.current_target = self.next_target;
         }
@@ -104,7 +163,7 @@ impl ProverState {
     }
 }
 
-impl Display for ProverState {
+impl<N: Network> Display for ProverState<N> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let addr_str = self.address.to_string();
         write!(
@@ -117,6 +176,133 @@ impl Display for ProverState {
     }
 }
 
+/// Banned prover IPs with the unix timestamp their ban ends at, persisted when the state storage
+/// is enabled.
+struct BanList {
+    bans: RwLock<HashMap<IpAddr, u64>>,
+    #[cfg(feature = "storage")]
+    storage: StorageData<IpAddr, u64>,
+}
+
+impl BanList {
+    fn new(#[cfg(feature = "storage")] storage: &Storage) -> Self {
+        #[cfg(feature = "storage")]
+        let storage = storage.init_data::<IpAddr, u64>(StorageType::Bans);
+        #[cfg(feature = "storage")]
+        let bans = storage.iter().collect::<HashMap<_, _>>();
+        #[cfg(not(feature = "storage"))]
+        let bans = HashMap::new();
+        Self {
+            bans: RwLock::new(bans),
+            #[cfg(feature = "storage")]
+            storage,
+        }
+    }
+
+    async fn is_banned(&self, ip: IpAddr) -> bool {
+        let until = match self.bans.read().await.get(&ip) {
+            Some(until) => *until,
+            None => return false,
+        };
+        if until > now_unix_secs() {
+            return true;
+        }
+        self.bans.write().await.remove(&ip);
+        #[cfg(feature = "storage")]
+        if let Err(e) = self.storage.delete(&ip) {
+            error!("Unable to remove ban of {}: {}", ip, e);
+        }
+        false
+    }
+
+    async fn ban(&self, ip: IpAddr, duration: Duration) {
+        let until = now_unix_secs().saturating_add(duration.as_secs());
+        warn!("Banning {} for {} seconds", ip, duration.as_secs());
+        self.bans.write().await.insert(ip, until);
+        #[cfg(feature = "storage")]
+        if let Err(e) = self.storage.put(&ip, &until) {
+            error!("Unable to store ban of {}: {}", ip, e);
+        }
+    }
+
+    /// Returns whether the IP was banned.
+    async fn unban(&self, ip: IpAddr) -> bool {
+        let removed = self.bans.write().await.remove(&ip);
+        #[cfg(feature = "storage")]
+        if let Err(e) = self.storage.delete(&ip) {
+            error!("Unable to remove ban of {}: {}", ip, e);
+        }
+        removed.is_some_and(|until| until > now_unix_secs())
+    }
+
+    /// Bans still in effect, with the unix timestamp they end at.
+    async fn list(&self) -> Vec<(IpAddr, u64)> {
+        let now = now_unix_secs();
+        let mut bans = self
+            .bans
+            .read()
+            .await
+            .iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(ip, until)| (*ip, *until))
+            .collect::<Vec<_>>();
+        bans.sort_by_key(|(_, until)| *until);
+        bans
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Session {
+    target: u64,
+    last_seen: u64,
+}
+
+/// Session state of every address when its last prover disconnected, persisted when the state
+/// storage is enabled, so reconnecting provers don't have to ramp up from the initial target again.
+struct SessionStore {
+    sessions: RwLock<HashMap<String, Session>>,
+    #[cfg(feature = "storage")]
+    storage: StorageData<String, Session>,
+}
+
+impl SessionStore {
+    fn new(#[cfg(feature = "storage")] storage: &Storage) -> Self {
+        #[cfg(feature = "storage")]
+        let storage = storage.init_data::<String, Session>(StorageType::Sessions);
+        #[cfg(feature = "storage")]
+        let sessions = storage.iter().collect::<HashMap<_, _>>();
+        #[cfg(not(feature = "storage"))]
+        let sessions = HashMap::new();
+        Self {
+            sessions: RwLock::new(sessions),
+            #[cfg(feature = "storage")]
+            storage,
+        }
+    }
+
+    async fn target(&self, address: &str) -> Option<u64> {
+        let session = self.sessions.read().await.get(address).cloned()?;
+        if session.last_seen + SESSION_TTL.as_secs() < now_unix_secs() {
+            return None;
+        }
+        Some(session.target)
+    }
+
+    async fn save(&self, address: String, target: u64) {
+        let session = Session {
+            target,
+            last_seen: now_unix_secs(),
+        };
+        #[cfg(feature = "storage")]
+        if let Err(e) = self.storage.put(&address, &session) {
+            error!("Unable to store session of {}: {}", address, e);
+        }
+        let mut sessions = self.sessions.write().await;
+        sessions.retain(|_, session| session.last_seen + SESSION_TTL.as_secs() >= now_unix_secs());
+        sessions.insert(address, session);
+    }
+}
+
 struct PoolState {
     speed_1m: Speedometer,
     speed_5m: Speedometer,
@@ -140,20 +326,20 @@ impl PoolState {
         }
     }
 
-    pub async fn add_share(&mut self, value: u64) {
+    pub async fn add_share(&mut self, value: u64, max_share_rate: f64) {
         let now = Instant::now();
         self.speed_1m.event(1).await;
         self.speed_5m.event(value).await;
         self.speed_15m.event(value).await;
         self.speed_30m.event(value).await;
         self.speed_1h.event(value).await;
-        self.next_global_target_modifier = (self.speed_1m.speed().await / 200.0).max(1f64);
-        // todo: make adjustable through admin api
+        self.next_global_target_modifier = (self.speed_1m.speed().await / max_share_rate).max(1f64);
         debug!("pool state add_share took {} us", now.elapsed().as_micros());
     }
 
-    pub async fn next_global_target_modifier(&mut self) -> f64 {
-        self.current_global_target_modifier = self.next_global_target_modifier;
+    /// The fixed modifier if there is one, the one following the share rate otherwise.
+    pub async fn next_global_target_modifier(&mut self, fixed: Option<f64>) -> f64 {
+        self.current_global_target_modifier = fixed.unwrap_or(self.next_global_target_modifier);
         if self.current_global_target_modifier > 1.0 {
             info!(
                 "Current global target modifier: {}",
@@ -178,18 +364,63 @@ impl PoolState {
     }
 }
 
+/// How long after the timestamp of the latest block the puzzle of a new epoch reached the pool, and
+/// its job the provers. The epoch may have started a few blocks before that one.
+struct PuzzleDelay {
+    received: VecDeque<f64>,
+    notified: VecDeque<f64>,
+}
+
+static PUZZLE_DELAY_SAMPLES: usize = 32;
+
+impl PuzzleDelay {
+    pub fn new() -> Self {
+        Self {
+            received: VecDeque::with_capacity(PUZZLE_DELAY_SAMPLES),
+            notified: VecDeque::with_capacity(PUZZLE_DELAY_SAMPLES),
+        }
+    }
+
+    pub fn record(&mut self, received: f64, notified: f64) {
+        if self.received.len() == PUZZLE_DELAY_SAMPLES {
+            self.received.pop_front();
+            self.notified.pop_front();
+        }
+        self.received.push_back(received);
+        self.notified.push_back(notified);
+    }
+
+    pub fn to_json(&self) -> Value {
+        fn average(samples: &VecDeque<f64>) -> Option<f64> {
+            if samples.is_empty() {
+                None
+            } else {
+                Some(samples.iter().sum::<f64>() / samples.len() as f64)
+            }
+        }
+        json!({
+            "last_received": self.received.back(),
+            "last_notified": self.notified.back(),
+            "average_received": average(&self.received),
+            "average_notified": average(&self.notified),
+        })
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
-pub enum ServerMessage {
+pub enum ServerMessage<N: Network> {
     ProverConnected(TcpStream, SocketAddr),
-    ProverAuthenticated(SocketAddr, Address<N>, Sender<StratumMessage>),
+    /// (peer_addr, address, session donation in basis points, sender)
+    ProverAuthenticated(SocketAddr, Address<N>, u64, Sender<StratumMessage>),
     ProverDisconnected(SocketAddr),
     ProverSubmit(Id, SocketAddr, u32, u64),
-    NewEpochHash(<N as Network>::BlockHash, u32, u64),
+    /// (epoch_hash, epoch_number, proof_target, block_timestamp)
+    NewEpochHash(<N as Network>::BlockHash, u32, u64, i64),
     Exit,
 }
 
-impl ServerMessage {
+impl<N: Network> ServerMessage<N> {
     fn name(&self) -> &'static str {
         match self {
             ServerMessage::ProverConnected(..) => "ProverConnected",
@@ -202,36 +433,44 @@ impl ServerMessage {
     }
 }
 
-impl Display for ServerMessage {
+impl<N: Network> Display for ServerMessage<N> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.name())
     }
 }
 
-pub struct Server {
-    sender: Sender<ServerMessage>,
-    prover_sender: Arc<Sender<SnarkOSMessage>>,
+pub struct Server<N: PoolNetwork> {
+    sender: Sender<ServerMessage<N>>,
+    work_source: Arc<dyn WorkSource<N>>,
     accounting_sender: Sender<AccountingMessage>,
     pool_address: Address<N>,
     connected_provers: RwLock<HashSet<SocketAddr>>,
     authenticated_provers: Arc<RwLock<HashMap<SocketAddr, Sender<StratumMessage>>>>,
     pool_state: Arc<RwLock<PoolState>>,
-    prover_states: Arc<RwLock<HashMap<SocketAddr, RwLock<ProverState>>>>,
+    prover_states: Arc<RwLock<HashMap<SocketAddr, RwLock<ProverState<N>>>>>,
     prover_address_connections: Arc<RwLock<HashMap<Address<N>, HashSet<SocketAddr>>>>,
     latest_epoch_number: AtomicU32,
     latest_epoch_hash: Arc<RwLock<Option<<N as Network>::BlockHash>>>,
     latest_proof_target: AtomicU64,
     nonce_seen: Arc<FlurryHashSet<u64>>,
     puzzle: Puzzle<N>,
+    puzzle_delay: RwLock<PuzzleDelay>,
+    bans: Arc<BanList>,
+    sessions: SessionStore,
+    metrics: Arc<Metrics>,
+    events: Events,
+    target_settings: RwLock<TargetSettings>,
 }
 
-impl Server {
+impl<N: PoolNetwork> Server<N> {
     pub async fn init(
         port: u16,
         address: Address<N>,
-        prover_sender: Arc<Sender<SnarkOSMessage>>,
+        work_source: Arc<dyn WorkSource<N>>,
         accounting_sender: Sender<AccountingMessage>,
-    ) -> Arc<Server> {
+        events: Events,
+        #[cfg(feature = "storage")] storage: Arc<Storage>,
+    ) -> Arc<Server<N>> {
         let (sender, mut receiver) = channel(1024);
 
         let (_, listener) = match TcpListener::bind(format!("0.0.0.0:{}", port)).await {
@@ -245,11 +484,11 @@ impl Server {
             }
         };
 
-        let puzzle = Puzzle::<N>::new::<SynthesisPuzzle<N, A>>();
+        let puzzle = Puzzle::<N>::new::<SynthesisPuzzle<N, N::Circuit>>();
 
         let server = Arc::new(Server {
             sender,
-            prover_sender,
+            work_source,
             accounting_sender,
             pool_address: address,
             connected_provers: Default::default(),
@@ -262,6 +501,18 @@ impl Server {
             latest_proof_target: AtomicU64::new(u64::MAX),
             nonce_seen: Arc::new(FlurryHashSet::with_capacity(10 << 20)),
             puzzle,
+            puzzle_delay: RwLock::new(PuzzleDelay::new()),
+            bans: Arc::new(BanList::new(
+                #[cfg(feature = "storage")]
+                &storage,
+            )),
+            sessions: SessionStore::new(
+                #[cfg(feature = "storage")]
+                &storage,
+            ),
+            metrics: Arc::new(Metrics::new().expect("Failed to register metrics")),
+            events,
+            target_settings: Default::default(),
         });
 
         // clear nonce
@@ -315,26 +566,42 @@ impl Server {
         self.nonce_seen.pin().clear()
     }
 
-    pub fn sender(&self) -> Sender<ServerMessage> {
+    pub fn sender(&self) -> Sender<ServerMessage<N>> {
         self.sender.clone()
     }
 
-    pub async fn process_message(&self, msg: ServerMessage) {
+    pub async fn process_message(&self, msg: ServerMessage<N>) {
         trace!("Received message: {}", msg);
         match msg {
             ServerMessage::ProverConnected(stream, peer_addr) => {
+                if self.bans.is_banned(peer_addr.ip()).await {
+                    info!("Rejected connection from banned peer {}", peer_addr);
+                    return;
+                }
                 self.connected_provers.write().await.insert(peer_addr);
                 Connection::init(stream, peer_addr, self.sender.clone(), self.pool_address).await;
             }
-            ServerMessage::ProverAuthenticated(peer_addr, address, sender) => {
+            ServerMessage::ProverAuthenticated(peer_addr, address, donation_bps, sender) => {
+                if let Err(e) = self
+                    .accounting_sender
+                    .send(AccountingMessage::SessionDonation(address.to_string(), donation_bps))
+                    .await
+                {
+                    error!("Error sending accounting message: {}", e);
+                }
                 self.authenticated_provers
                     .write()
                     .await
                     .insert(peer_addr, sender.clone());
+                let target = self
+                    .sessions
+                    .target(&address.to_string())
+                    .await
+                    .unwrap_or(INITIAL_TARGET);
                 self.prover_states
                     .write()
                     .await
-                    .insert(peer_addr, ProverState::new(peer_addr, address).into());
+                    .insert(peer_addr, ProverState::new(peer_addr, address, target).into());
                 let mut pac_write = self.prover_address_connections.write().await;
                 if let Some(address) = pac_write.get_mut(&address) {
                     address.insert(peer_addr);
@@ -342,7 +609,10 @@ impl Server {
                     pac_write.insert(address, HashSet::from([peer_addr]));
                 }
                 drop(pac_write);
-                if let Err(e) = sender.send(StratumMessage::SetTarget(512)).await {
+                self.events.publish(PoolEvent::ProverConnected {
+                    address: address.to_string(),
+                });
+                if let Err(e) = sender.send(StratumMessage::SetTarget(target)).await {
                     error!("Error sending initial target to prover: {}", e);
                 }
                 if let Some(epoch_challenge) = self.latest_epoch_hash.read().await.as_ref() {
@@ -366,9 +636,20 @@ impl Server {
             ServerMessage::ProverDisconnected(peer_addr) => {
                 let state = self.prover_states.write().await.remove(&peer_addr);
                 let address = match state {
-                    Some(state) => Some(state.read().await.address()),
+                    Some(state) => {
+                        let state = state.read().await;
+                        self.sessions
+                            .save(state.address().to_string(), state.current_target())
+                            .await;
+                        Some(state.address())
+                    }
                     None => None,
                 };
+                if let Some(address) = address {
+                    self.events.publish(PoolEvent::ProverDisconnected {
+                        address: address.to_string(),
+                    });
+                }
                 if address.is_some() {
                     let mut pac_write = self.prover_address_connections.write().await;
                     let pac = pac_write.get_mut(&address.unwrap());
@@ -376,16 +657,33 @@ impl Server {
                         pac.remove(&peer_addr);
                         if pac.is_empty() {
                             pac_write.remove(&address.unwrap());
+                            if let Err(e) = self
+                                .accounting_sender
+                                .send(AccountingMessage::SessionDonation(address.unwrap().to_string(), 0))
+                                .await
+                            {
+                                error!("Error sending accounting message: {}", e);
+                            }
                         }
                     }
                 }
                 self.connected_provers.write().await.remove(&peer_addr);
                 self.authenticated_provers.write().await.remove(&peer_addr);
             }
-            ServerMessage::NewEpochHash(epoch_hash, epoch_number, proof_target) => {
+            ServerMessage::NewEpochHash(epoch_hash, epoch_number, proof_target, block_timestamp) => {
+                let received = Instant::now();
+                let block_age = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|now| (now.as_secs_f64() - block_timestamp as f64).max(0.0))
+                    .unwrap_or_default();
                 let latest_epoch = self.latest_epoch_number.load(Ordering::SeqCst);
-                if latest_epoch < epoch_number || (epoch_number == 0 && latest_epoch == 0) {
+                let new_epoch = latest_epoch < epoch_number || (epoch_number == 0 && latest_epoch == 0);
+                if new_epoch {
                     info!("New epoch: {}", epoch_number);
+                    self.events.publish(PoolEvent::NewEpoch {
+                        epoch_number,
+                        proof_target,
+                    });
                     self.latest_epoch_number.store(epoch_number, Ordering::SeqCst);
                     self.latest_epoch_hash.write().await.replace(epoch_hash.clone());
                     self.clear_nonce();
@@ -397,12 +695,18 @@ impl Server {
                 self.latest_proof_target.store(proof_target, Ordering::SeqCst);
                 if let Err(e) = self
                     .accounting_sender
-                    .send(AccountingMessage::SetN(proof_target * 5))
+                    .send(AccountingMessage::SetProofTarget(proof_target))
                     .await
                 {
                     error!("Error sending accounting message: {}", e);
                 }
-                let global_difficulty_modifier = self.pool_state.write().await.next_global_target_modifier().await;
+                let target_settings = *self.target_settings.read().await;
+                let global_difficulty_modifier = self
+                    .pool_state
+                    .write()
+                    .await
+                    .next_global_target_modifier(target_settings.global_target_modifier)
+                    .await;
                 debug!("Global difficulty modifier: {}", global_difficulty_modifier);
                 let job_id = hex::encode(epoch_number.to_le_bytes());
                 let epoch_challenge_hex = hex::encode(epoch_hash.to_bytes_le().unwrap());
@@ -418,8 +722,12 @@ impl Server {
 
                     let prover_display = format!("{}", prover_state.read().await);
                     let current_difficulty = prover_state.read().await.current_target();
-                    let mut next_difficulty =
-                        (prover_state.write().await.next_target().await as f64 * global_difficulty_modifier) as u64;
+                    let next_target = prover_state
+                        .write()
+                        .await
+                        .next_target(target_settings.retarget_threshold)
+                        .await;
+                    let mut next_difficulty = (next_target as f64 * global_difficulty_modifier) as u64;
                     drop(states);
                     if next_difficulty > proof_target {
                         next_difficulty = proof_target;
@@ -441,6 +749,14 @@ impl Server {
                         error!("Error sending block template to prover {}: {}", prover_display, e);
                     }
                 }
+                if new_epoch {
+                    let notified = block_age + received.elapsed().as_secs_f64();
+                    info!(
+                        "Puzzle of epoch {} received {:.3}s after the latest block, provers notified after {:.3}s",
+                        epoch_number, block_age, notified
+                    );
+                    self.puzzle_delay.write().await.record(block_age, notified);
+                }
             }
             ServerMessage::ProverSubmit(id, peer_addr, epoch_number, counter) => {
                 let prover_states = self.prover_states.clone();
@@ -450,11 +766,15 @@ impl Server {
                 let current_global_difficulty_modifier = self.pool_state.read().await.current_global_target_modifier();
                 let latest_epoch_hash = self.latest_epoch_hash.clone();
                 let accounting_sender = self.accounting_sender.clone();
-                let prover_sender = self.prover_sender.clone();
+                let work_source = self.work_source.clone();
                 let seen_nonce = self.nonce_seen.clone();
                 let global_proof_target = self.latest_proof_target.load(Ordering::SeqCst);
                 let pool_address = self.pool_address;
                 let puzzle = self.puzzle.clone();
+                let bans = self.bans.clone();
+                let metrics = self.metrics.clone();
+                let events = self.events.clone();
+                let target_settings = *self.target_settings.read().await;
                 task::spawn(async move {
                     async fn send_result(
                         sender: &Sender<StratumMessage>,
@@ -494,6 +814,7 @@ impl Server {
                         Some(state) => state,
                         None => {
                             error!("Received solution from unknown prover: {}", peer_addr);
+                            metrics.share_rejected(RejectReason::Invalid);
                             send_result(
                                 sender,
                                 id,
@@ -506,6 +827,26 @@ impl Server {
                         }
                     };
                     let prover_display = format!("{}", prover_state.read().await);
+                    let address = prover_state.read().await.address().to_string();
+                    let rejected = |reason: RejectReason| {
+                        metrics.share_rejected(reason);
+                        events.publish(PoolEvent::ShareRejected {
+                            address: address.clone(),
+                            reason: reason.as_str(),
+                        });
+                    };
+                    if bans.is_banned(peer_addr.ip()).await {
+                        rejected(RejectReason::Banned);
+                        send_result(
+                            sender,
+                            id,
+                            false,
+                            Some(ErrorCode::from_code(24)),
+                            Some("Banned".to_string()),
+                        )
+                        .await;
+                        return;
+                    }
                     let epoch_hash = match latest_epoch_hash.read().await.clone() {
                         Some(template) => template,
                         None => {
@@ -513,6 +854,7 @@ impl Server {
                                 "Received solution from prover {} while no epoch challenge is available",
                                 prover_display
                             );
+                            rejected(RejectReason::Stale);
                             send_result(
                                 sender,
                                 id,
@@ -529,6 +871,7 @@ impl Server {
                             "Received stale solution from prover {} with epoch number: {} (expected {})",
                             prover_display, epoch_number, latest_epoch_number
                         );
+                        rejected(RejectReason::Stale);
                         send_result(
                             sender,
                             id,
@@ -539,8 +882,9 @@ impl Server {
                         .await;
                         return;
                     }
-                    if Server::seen_nonce(seen_nonce, counter) {
+                    if Self::seen_nonce(seen_nonce, counter) {
                         warn!("Received duplicate nonce from prover {}", prover_display);
+                        rejected(RejectReason::Duplicate);
                         send_result(
                             sender,
                             id,
@@ -556,6 +900,7 @@ impl Server {
                     if prover_target > global_proof_target {
                         prover_target = global_proof_target;
                     }
+                    let verification = Instant::now();
                     let partial_solution = match PartialSolution::new(epoch_hash, pool_address, counter) {
                         Ok(partial_solution) => partial_solution,
                         Err(e) => {
@@ -563,6 +908,7 @@ impl Server {
                                 "Failed to construct partial solution from prover {}: {}",
                                 prover_display, e
                             );
+                            rejected(RejectReason::Invalid);
                             send_result(
                                 sender,
                                 id,
@@ -581,6 +927,7 @@ impl Server {
                                 "Failed to get proof target from partial solution from prover {}: {}",
                                 prover_display, e
                             );
+                            rejected(RejectReason::Invalid);
                             send_result(
                                 sender,
                                 id,
@@ -592,12 +939,14 @@ impl Server {
                             return;
                         }
                     };
+                    metrics.verification_time(verification.elapsed().as_secs_f64());
 
                     if proof_target < prover_target {
                         warn!(
                             "Received solution with target {} from prover {} (expected {})",
                             proof_target, prover_display, prover_target
                         );
+                        rejected(RejectReason::LowDifficulty);
                         send_result(
                             sender,
                             id,
@@ -611,17 +960,31 @@ impl Server {
 
                     let solution = Solution::new(partial_solution, proof_target);
 
-                    prover_state.write().await.add_share(prover_target).await;
-                    pool_state.write().await.add_share(prover_target).await;
+                    prover_state
+                        .write()
+                        .await
+                        .add_share(prover_target, target_settings.share_interval)
+                        .await;
+                    pool_state
+                        .write()
+                        .await
+                        .add_share(prover_target, target_settings.max_share_rate)
+                        .await;
                     if let Err(e) = accounting_sender
                         .send(AccountingMessage::NewShare(
-                            prover_state.read().await.address().to_string(),
+                            address.clone(),
                             proof_target.min(global_proof_target * 2),
+                            prover_target,
                         ))
                         .await
                     {
                         error!("Failed to send accounting message: {}", e);
                     }
+                    metrics.share_accepted();
+                    events.publish(PoolEvent::ShareAccepted {
+                        address: address.clone(),
+                        target: prover_target,
+                    });
                     send_result(sender, id, true, None, None).await;
                     drop(provers);
                     drop(states);
@@ -635,19 +998,16 @@ impl Server {
                             "Received unconfirmed solution from prover {} with solution target {} (target {})",
                             prover_display, proof_target, global_proof_target
                         );
-                        // TODO: dummy operator
-                        if let Err(e) = prover_sender
-                            .send(SnarkOSMessage::UnconfirmedSolution(UnconfirmedSolution {
-                                solution_id: solution.id(),
-                                solution: Data::Object(solution),
-                            }))
-                            .await
-                        {
-                            error!("Failed to report unconfirmed block to operator: {}", e);
+                        events.publish(PoolEvent::SolutionFound {
+                            solution_id: solution.id().to_string(),
+                            address: address.clone(),
+                        });
+                        if let Err(e) = work_source.submit_solution(solution).await {
+                            error!("Failed to report unconfirmed solution to upstream: {}", e);
                         }
                         if let Err(e) = {
                             accounting_sender
-                                .send(AccountingMessage::NewSolution(solution.id()))
+                                .send(AccountingMessage::NewSolution(solution.id().to_string()))
                                 .await
                         } {
                             error!("Failed to send accounting message: {}", e);
@@ -659,14 +1019,56 @@ impl Server {
         }
     }
 
+    /// Every metric in the Prometheus text format, with the gauges read from the current state.
+    pub async fn metrics(&self) -> Result<String> {
+        let metrics = &self.metrics;
+        metrics
+            .connected_provers
+            .set(self.connected_provers.read().await.len() as i64);
+        metrics
+            .authenticated_provers
+            .set(self.authenticated_provers.read().await.len() as i64);
+        metrics
+            .global_target_modifier
+            .set(self.pool_state.read().await.current_global_target_modifier());
+        // u64::MAX until the first epoch
+        let proof_target = self.latest_proof_target.load(Ordering::SeqCst);
+        metrics
+            .proof_target
+            .set(if proof_target == u64::MAX { 0 } else { proof_target as i64 });
+        metrics
+            .epoch_number
+            .set(self.latest_epoch_number.load(Ordering::SeqCst) as i64);
+        metrics.node_connected.set(self.work_source.connected() as i64);
+        metrics
+            .pending_solutions
+            .set(self.work_source.pending_solutions().await as i64);
+        metrics
+            .accounting_queue
+            .set((self.accounting_sender.max_capacity() - self.accounting_sender.capacity()) as i64);
+        metrics.encode()
+    }
+
     pub async fn online_provers(&self) -> u32 {
         self.authenticated_provers.read().await.len() as u32
     }
 
+    pub fn pool_address(&self) -> Address<N> {
+        self.pool_address
+    }
+
     pub async fn online_addresses(&self) -> u32 {
         self.prover_address_connections.read().await.len() as u32
     }
 
+    pub async fn online_address_list(&self) -> Vec<Address<N>> {
+        self.prover_address_connections.read().await.keys().copied().collect()
+    }
+
+    pub async fn puzzle_delay(&self) -> Value {
+        self.puzzle_delay.read().await.to_json()
+    }
+
     pub async fn pool_speed(&self) -> Vec<f64> {
         self.pool_state.write().await.speed().await
     }
@@ -702,4 +1104,77 @@ impl Server {
         }
         speed
     }
+
+    /// Authenticated provers with their address, target and speed.
+    pub async fn prover_list(&self) -> Vec<Value> {
+        let mut provers = vec![];
+        for (peer_addr, state) in self.prover_states.read().await.iter() {
+            let mut state = state.write().await;
+            provers.push(json!({
+                "peer_addr": peer_addr,
+                "address": state.address().to_string(),
+                "target": state.current_target(),
+                "speed": state.speed().await,
+            }));
+        }
+        provers
+    }
+
+    /// Closes the connection of an authenticated prover, returns whether there was one. Nothing
+    /// keeps the prover from connecting again.
+    pub async fn kick(&self, peer_addr: SocketAddr) -> bool {
+        // The connection ends once its sender is gone, and reports the disconnection itself
+        let kicked = self.authenticated_provers.write().await.remove(&peer_addr).is_some();
+        if kicked {
+            info!("Kicked prover {}", peer_addr);
+        }
+        kicked
+    }
+
+    /// Bans an IP and kicks its provers, returns the kicked ones.
+    pub async fn ban(&self, ip: IpAddr, duration: Duration) -> Vec<SocketAddr> {
+        self.bans.ban(ip, duration).await;
+        let peers = self
+            .authenticated_provers
+            .read()
+            .await
+            .keys()
+            .filter(|peer_addr| peer_addr.ip() == ip)
+            .copied()
+            .collect::<Vec<_>>();
+        for peer_addr in &peers {
+            self.kick(*peer_addr).await;
+        }
+        peers
+    }
+
+    /// Returns whether the IP was banned.
+    pub async fn unban(&self, ip: IpAddr) -> bool {
+        let unbanned = self.bans.unban(ip).await;
+        if unbanned {
+            info!("Unbanned {}", ip);
+        }
+        unbanned
+    }
+
+    pub async fn bans(&self) -> Vec<(IpAddr, u64)> {
+        self.bans.list().await
+    }
+
+    pub async fn target_settings(&self) -> TargetSettings {
+        *self.target_settings.read().await
+    }
+
+    pub async fn set_target_settings(&self, settings: TargetSettings) -> Result<()> {
+        settings.validate()?;
+        info!("Target settings changed to {:?}", settings);
+        *self.target_settings.write().await = settings;
+        Ok(())
+    }
+
+    /// Asks the upstream for the current puzzle, which is pushed to the provers with their new
+    /// targets once it arrives.
+    pub async fn refresh_puzzle(&self) -> Result<()> {
+        self.work_source.request_puzzle().await
+    }
 }
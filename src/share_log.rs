@@ -0,0 +1,524 @@
+use std::{
+    collections::HashMap,
+    fs::{create_dir_all, read_dir, remove_file, rename, File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use blake2::{Blake2s256, Digest};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::payout_model::{PayoutModel, Share};
+#[cfg(feature = "storage")]
+use crate::state_storage::{Storage, StorageData, StorageType};
+
+#[cfg(not(feature = "storage"))]
+const SNAPSHOT_FILE: &str = "snapshot";
+const LOG_FILE: &str = "share_log";
+/// Shares written by versions without the share log
+const LEGACY_STATE_FILE: &str = "state";
+/// Rotated logs and hourly snapshots, for `audit` to replay
+const ARCHIVE_DIR: &str = "share_log_archive";
+
+static ARCHIVE_RETENTION: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+static ARCHIVE_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const CHECKSUM_LENGTH: usize = 8;
+
+/// Everything that changes the state of a payout model.
+#[derive(Serialize, Deserialize)]
+enum LogRecord {
+    Share(String, u64),
+    ProofTarget(u64),
+    /// Solution found by versions that did not log its ID
+    Solution,
+    SolutionReward(u64),
+    SolutionFound(String),
+    /// Per-share earnings of a share
+    Credit(String, u64),
+    /// Per-share earnings written to the balances
+    CreditsFlushed(HashMap<String, u64>),
+    /// All the per-share earnings not written to the balances yet, logged with every snapshot
+    PendingCredits(HashMap<String, u64>),
+}
+
+#[derive(Serialize, Deserialize)]
+struct LogEntry {
+    sequence: u64,
+    timestamp: u64,
+    record: LogRecord,
+}
+
+fn checksum(data: &[u8]) -> [u8; CHECKSUM_LENGTH] {
+    let hash = Blake2s256::digest(data);
+    let mut checksum = [0u8; CHECKSUM_LENGTH];
+    checksum.copy_from_slice(&hash[..CHECKSUM_LENGTH]);
+    checksum
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Reads the entries of a log file, stopping at the first incomplete or corrupted one.
+/// Returns the entries and the length of the valid part of the file.
+fn read_log(path: &Path) -> Result<(Vec<LogEntry>, u64)> {
+    let mut data = vec![];
+    if path.exists() {
+        File::open(path)?.read_to_end(&mut data)?;
+    }
+    let mut entries = vec![];
+    let mut offset = 0;
+    // [u32 length][checksum][bincode entry]
+    while offset + 4 + CHECKSUM_LENGTH <= data.len() {
+        let length = u32::from_le_bytes(data[offset..offset + 4].try_into()?) as usize;
+        let start = offset + 4 + CHECKSUM_LENGTH;
+        if start + length > data.len() {
+            warn!("Share log {} ends with an incomplete entry", path.display());
+            break;
+        }
+        let payload = &data[start..start + length];
+        if checksum(payload) != data[offset + 4..start] {
+            warn!("Share log {} has a corrupted entry at offset {}", path.display(), offset);
+            break;
+        }
+        entries.push(bincode::deserialize(payload)?);
+        offset = start + length;
+    }
+    Ok((entries, offset as u64))
+}
+
+/// Snapshot layout: [u64 sequence][checksum][model state]
+fn decode_snapshot(data: &[u8]) -> Result<(u64, Vec<u8>)> {
+    if data.len() < 8 + CHECKSUM_LENGTH {
+        return Err(anyhow!("Snapshot is truncated"));
+    }
+    let (header, state) = data.split_at(8 + CHECKSUM_LENGTH);
+    if checksum(&data[..8].iter().chain(state).copied().collect::<Vec<_>>()) != header[8..] {
+        return Err(anyhow!("Snapshot checksum mismatch"));
+    }
+    Ok((u64::from_le_bytes(header[..8].try_into()?), state.to_vec()))
+}
+
+fn encode_snapshot(sequence: u64, state: &[u8]) -> Vec<u8> {
+    let mut data = sequence.to_le_bytes().to_vec();
+    let checksum = checksum(&data.iter().chain(state).copied().collect::<Vec<_>>());
+    data.extend_from_slice(&checksum);
+    data.extend_from_slice(state);
+    data
+}
+
+/// Where the latest and the previous snapshot are kept.
+enum SnapshotStore {
+    #[cfg(not(feature = "storage"))]
+    Files(PathBuf),
+    #[cfg(feature = "storage")]
+    Storage(StorageData<u8, Vec<u8>>),
+}
+
+impl SnapshotStore {
+    const LATEST: u8 = 0;
+    const PREVIOUS: u8 = 1;
+
+    fn read(&self, key: u8) -> Result<Option<Vec<u8>>> {
+        match self {
+            #[cfg(not(feature = "storage"))]
+            SnapshotStore::Files(path) => {
+                let path = match key {
+                    Self::LATEST => path.clone(),
+                    _ => with_suffix(path, ".prev"),
+                };
+                if !path.exists() {
+                    return Ok(None);
+                }
+                let mut data = vec![];
+                File::open(path)?.read_to_end(&mut data)?;
+                Ok(Some(data))
+            }
+            #[cfg(feature = "storage")]
+            SnapshotStore::Storage(storage) => storage.get(&key),
+        }
+    }
+
+    fn write(&self, data: Vec<u8>) -> Result<()> {
+        match self {
+            #[cfg(not(feature = "storage"))]
+            SnapshotStore::Files(path) => {
+                let tmp_path = with_suffix(path, ".tmp");
+                let mut file = File::create(&tmp_path)?;
+                file.write_all(&data)?;
+                file.sync_all()?;
+                if path.exists() {
+                    rename(path, with_suffix(path, ".prev"))?;
+                }
+                rename(tmp_path, path)?;
+            }
+            #[cfg(feature = "storage")]
+            SnapshotStore::Storage(storage) => {
+                if let Some(latest) = storage.get(&Self::LATEST)? {
+                    storage.put(&Self::PREVIOUS, &latest)?;
+                }
+                storage.put(&Self::LATEST, &data)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Applies a log entry to a model, returns the window of the solution it records if any.
+fn apply(model: &mut dyn PayoutModel, entry: LogEntry) -> Option<(Option<String>, HashMap<String, u64>)> {
+    match entry.record {
+        LogRecord::Share(owner, value) => model.replay_share(Share::init(value, owner), entry.timestamp),
+        LogRecord::ProofTarget(proof_target) => model.set_proof_target(proof_target),
+        LogRecord::Solution => return Some((None, model.solution_shares())),
+        LogRecord::SolutionFound(solution_id) => return Some((Some(solution_id), model.solution_shares())),
+        LogRecord::SolutionReward(reward) => model.solution_matured(reward),
+        LogRecord::Credit(..) | LogRecord::CreditsFlushed(_) | LogRecord::PendingCredits(_) => {}
+    }
+    None
+}
+
+/// Applies a log entry to the per-share earnings not written to the balances yet, returns false if it
+/// does not record any.
+fn apply_credits(credits: &mut HashMap<String, u64>, record: &LogRecord) -> bool {
+    match record {
+        LogRecord::Credit(address, amount) => *credits.entry(address.clone()).or_default() += amount,
+        LogRecord::CreditsFlushed(flushed) => subtract_credits(credits, flushed),
+        LogRecord::PendingCredits(pending) => *credits = pending.clone(),
+        _ => return false,
+    }
+    true
+}
+
+fn subtract_credits(credits: &mut HashMap<String, u64>, flushed: &HashMap<String, u64>) {
+    for (address, amount) in flushed {
+        if let Some(credit) = credits.get_mut(address) {
+            *credit = credit.saturating_sub(*amount);
+            if *credit == 0 {
+                credits.remove(address);
+            }
+        }
+    }
+}
+
+/// Removes the archived files older than the retention.
+fn prune_archive(archive_dir: &Path) -> Result<()> {
+    for entry in read_dir(archive_dir)? {
+        let entry = entry?;
+        let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+        if age > ARCHIVE_RETENTION {
+            remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Replays the archived share log from the oldest archived snapshot, and returns the window every
+/// solution found since then was paid over, by solution ID.
+pub fn replay_windows(
+    mut model: Box<dyn PayoutModel>,
+    data_dir: &Path,
+) -> Result<HashMap<String, HashMap<String, u64>>> {
+    let archive_dir = data_dir.join(ARCHIVE_DIR);
+    let mut snapshots = vec![];
+    let mut logs = vec![];
+    if archive_dir.exists() {
+        for entry in read_dir(&archive_dir)? {
+            let path = entry?.path();
+            match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) if name.starts_with("snapshot.") => snapshots.push(path),
+                Some(name) if name.starts_with("log.") => logs.push(path),
+                _ => {}
+            }
+        }
+    }
+    // Zero-padded sequence numbers sort in order
+    snapshots.sort();
+    logs.sort();
+
+    let mut snapshot_sequence = None;
+    for path in &snapshots {
+        let mut data = vec![];
+        let result = File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut data))
+            .map_err(|e| anyhow!(e))
+            .and_then(|_| decode_snapshot(&data))
+            .and_then(|(sequence, state)| model.restore(&state).map(|_| sequence));
+        match result {
+            Ok(sequence) => {
+                info!("Replaying from archived snapshot {}", path.display());
+                snapshot_sequence = Some(sequence);
+                break;
+            }
+            Err(e) => warn!("Unable to load archived snapshot {}: {}", path.display(), e),
+        }
+    }
+    let mut sequence =
+        snapshot_sequence.ok_or_else(|| anyhow!("No archived snapshot in {}", archive_dir.display()))?;
+
+    let log_path = data_dir.join(LOG_FILE);
+    logs.push(with_suffix(&log_path, ".prev"));
+    logs.push(log_path);
+    let mut entries = vec![];
+    for path in &logs {
+        entries.extend(read_log(path)?.0);
+    }
+    entries.sort_by_key(|entry| entry.sequence);
+
+    let mut windows = HashMap::new();
+    for entry in entries {
+        if entry.sequence <= sequence {
+            continue;
+        }
+        if entry.sequence != sequence + 1 {
+            warn!("Share log skips from {} to {}", sequence, entry.sequence);
+        }
+        sequence = entry.sequence;
+        if let Some((Some(solution_id), window)) = apply(model.as_mut(), entry) {
+            windows.insert(solution_id, window);
+        }
+    }
+    Ok(windows)
+}
+
+/// Payout model with crash-safe state.
+///
+/// Every change to the model is appended to a write-ahead log before it is applied, and the whole
+/// state is written to a checksummed snapshot every minute and on exit. The log is rotated with
+/// every snapshot, keeping the entries since the previous snapshot around.
+///
+/// On startup the latest snapshot is loaded and the log is replayed from its sequence number. If the
+/// snapshot is corrupted, the previous one is used with the entries of both logs instead.
+///
+/// Per-share earnings are logged as well until they are written to the balances, and carried over
+/// to the next log with every snapshot.
+///
+/// Snapshots are kept in the state storage when it is enabled, next to the log otherwise. Rotated logs
+/// and a snapshot every hour are also archived for a week, for `audit`.
+pub struct LoggedModel {
+    model: Box<dyn PayoutModel>,
+    data_dir: PathBuf,
+    snapshots: SnapshotStore,
+    log: Option<File>,
+    sequence: u64,
+    /// Per-share earnings not written to the balances yet
+    credits: HashMap<String, u64>,
+    /// Unix timestamp of the latest archived snapshot
+    archived_at: u64,
+}
+
+impl LoggedModel {
+    pub fn recover(
+        mut model: Box<dyn PayoutModel>,
+        data_dir: &Path,
+        #[cfg(feature = "storage")] storage: &Storage,
+    ) -> Result<Self> {
+        create_dir_all(data_dir.join(ARCHIVE_DIR))?;
+        #[cfg(not(feature = "storage"))]
+        let snapshots = SnapshotStore::Files(data_dir.join(SNAPSHOT_FILE));
+        #[cfg(feature = "storage")]
+        let snapshots = SnapshotStore::Storage(storage.init_data(StorageType::PPLNS));
+        let log_path = data_dir.join(LOG_FILE);
+
+        let mut snapshot_sequence = None;
+        for (key, name) in [(SnapshotStore::LATEST, "latest"), (SnapshotStore::PREVIOUS, "previous")] {
+            let result = snapshots.read(key).and_then(|data| match data {
+                Some(data) => {
+                    let (sequence, state) = decode_snapshot(&data)?;
+                    model.restore(&state)?;
+                    Ok(Some(sequence))
+                }
+                None => Ok(None),
+            });
+            match result {
+                Ok(Some(sequence)) => {
+                    info!("Loaded {} snapshot at sequence {}", name, sequence);
+                    snapshot_sequence = Some(sequence);
+                    break;
+                }
+                Ok(None) => {}
+                Err(e) => error!("Unable to load {} snapshot: {}", name, e),
+            }
+        }
+        if snapshot_sequence.is_none() {
+            let legacy_path = data_dir.join(LEGACY_STATE_FILE);
+            if legacy_path.exists() {
+                let mut state = vec![];
+                File::open(&legacy_path)?.read_to_end(&mut state)?;
+                match model.restore(&state) {
+                    Ok(_) => info!("Loaded legacy state {}", legacy_path.display()),
+                    Err(e) => error!("Unable to load legacy state {}: {}", legacy_path.display(), e),
+                }
+            }
+        }
+
+        let snapshot_sequence = snapshot_sequence.unwrap_or(0);
+        let (previous_entries, _) = read_log(&with_suffix(&log_path, ".prev"))?;
+        let (entries, valid_length) = read_log(&log_path)?;
+        let mut sequence = snapshot_sequence;
+        let mut replayed = 0;
+        let mut credits = HashMap::new();
+        for entry in previous_entries.into_iter().chain(entries) {
+            if entry.sequence <= sequence {
+                continue;
+            }
+            if entry.sequence != sequence + 1 {
+                warn!("Share log skips from {} to {}", sequence, entry.sequence);
+            }
+            sequence = entry.sequence;
+            replayed += 1;
+            if !apply_credits(&mut credits, &entry.record) {
+                apply(model.as_mut(), entry);
+            }
+        }
+        info!("Replayed {} share log entries", replayed);
+        if !credits.is_empty() {
+            info!("Recovered per-share earnings of {} addresses", credits.len());
+        }
+
+        // Drop a torn write at the end of the log before appending to it
+        let log = OpenOptions::new().create(true).append(true).open(&log_path)?;
+        log.set_len(valid_length)?;
+
+        Ok(LoggedModel {
+            model,
+            data_dir: data_dir.to_path_buf(),
+            snapshots,
+            log: Some(log),
+            sequence,
+            credits,
+            archived_at: 0,
+        })
+    }
+
+    fn append(&mut self, record: LogRecord) {
+        self.sequence += 1;
+        let entry = LogEntry {
+            sequence: self.sequence,
+            timestamp: now(),
+            record,
+        };
+        let result = bincode::serialize(&entry).map_err(|e| anyhow!(e)).and_then(|payload| {
+            let mut data = (payload.len() as u32).to_le_bytes().to_vec();
+            data.extend_from_slice(&checksum(&payload));
+            data.extend_from_slice(&payload);
+            self.log
+                .as_mut()
+                .ok_or_else(|| anyhow!("Share log is not open"))?
+                .write_all(&data)?;
+            Ok(())
+        });
+        if let Err(e) = result {
+            error!("Unable to write share log: {}", e);
+        }
+    }
+
+    pub fn add_share(&mut self, share: Share) {
+        self.append(LogRecord::Share(share.owner().to_string(), share.value()));
+        self.model.add_share(share);
+    }
+
+    pub fn set_proof_target(&mut self, proof_target: u64) {
+        self.append(LogRecord::ProofTarget(proof_target));
+        self.model.set_proof_target(proof_target);
+    }
+
+    pub fn credit_share(&mut self, share: &Share, difficulty: u64) {
+        if let Some(credit) = self.model.credit_share(share, difficulty) {
+            self.append(LogRecord::Credit(share.owner().to_string(), credit));
+            *self.credits.entry(share.owner().to_string()).or_default() += credit;
+        }
+    }
+
+    /// Per-share earnings not written to the balances yet.
+    pub fn credits(&self) -> &HashMap<String, u64> {
+        &self.credits
+    }
+
+    /// Records per-share earnings as written to the balances.
+    pub fn credits_flushed(&mut self, flushed: HashMap<String, u64>) {
+        subtract_credits(&mut self.credits, &flushed);
+        self.append(LogRecord::CreditsFlushed(flushed));
+    }
+
+    pub fn solution_shares(&mut self, solution_id: &str) -> HashMap<String, u64> {
+        self.append(LogRecord::SolutionFound(solution_id.to_string()));
+        self.model.solution_shares()
+    }
+
+    pub fn solution_reward(&self, reward: u64) -> u64 {
+        self.model.solution_reward(reward)
+    }
+
+    pub fn solution_matured(&mut self, reward: u64) {
+        self.append(LogRecord::SolutionReward(reward));
+        self.model.solution_matured(reward);
+    }
+
+    pub fn round(&self) -> (u64, u64) {
+        self.model.round()
+    }
+
+    pub fn round_shares(&self) -> Arc<HashMap<String, u64>> {
+        self.model.round_shares()
+    }
+
+    /// Writes a snapshot of the model and rotates the log.
+    pub fn snapshot(&mut self) -> Result<()> {
+        let state = self.model.snapshot()?;
+        let sequence = self.sequence;
+        if let Some(log) = &self.log {
+            log.sync_all()?;
+        }
+        let snapshot = encode_snapshot(sequence, &state);
+        let archive_dir = self.data_dir.join(ARCHIVE_DIR);
+        if now() >= self.archived_at + ARCHIVE_SNAPSHOT_INTERVAL.as_secs() {
+            let result = File::create(archive_dir.join(format!("snapshot.{:020}", sequence)))
+                .and_then(|mut file| file.write_all(&snapshot));
+            match result {
+                Ok(_) => self.archived_at = now(),
+                Err(e) => error!("Unable to archive snapshot: {}", e),
+            }
+        }
+        self.snapshots.write(snapshot)?;
+
+        let rotated = self.rotate(sequence);
+        // Logged past the snapshot sequence, so the earnings logged before it are replayed, into the
+        // old log if it was not rotated
+        if !self.credits.is_empty() {
+            self.append(LogRecord::PendingCredits(self.credits.clone()));
+        }
+        rotated?;
+        if let Err(e) = prune_archive(&archive_dir) {
+            error!("Unable to prune share log archive: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Moves the log to the previous one, archiving the one before.
+    fn rotate(&mut self, sequence: u64) -> Result<()> {
+        let archive_dir = self.data_dir.join(ARCHIVE_DIR);
+        let log_path = self.data_dir.join(LOG_FILE);
+        let previous_log_path = with_suffix(&log_path, ".prev");
+        // The open handle follows the renamed file, so the log keeps being written to until the new
+        // one is open, and both files are replayed on startup if that fails
+        if previous_log_path.exists() {
+            // Named after the sequence of the snapshot retiring it, to sort in order
+            rename(&previous_log_path, archive_dir.join(format!("log.{:020}", sequence)))?;
+        }
+        rename(&log_path, &previous_log_path)?;
+        let log = OpenOptions::new().create(true).append(true).open(&log_path)?;
+        self.log = Some(log);
+        Ok(())
+    }
+}
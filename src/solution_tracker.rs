@@ -0,0 +1,237 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use snarkvm::{
+    ledger::{block::Ratify, puzzle::SolutionID},
+    prelude::{Block, Network},
+};
+use tracing::{debug, info, warn};
+
+/// Blocks a confirmed solution is kept around for, in case the block is replaced. Orphans deeper than
+/// this are not noticed, so it matches the default `--maturity-blocks`.
+static CONFIRMATION_DEPTH: u32 = 10;
+/// Maximum number of blocks in a `BlockResponse`.
+static MAX_BLOCKS_PER_REQUEST: u32 = 5;
+static BLOCK_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the blocks including a solution are fetched again once caught up, to notice them being
+/// replaced
+static RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+pub enum SolutionEvent<N: Network> {
+    /// (solution_id, height, reward)
+    Confirmed(SolutionID<N>, u32, u64),
+    /// The block including the solution was replaced by one that does not.
+    Orphaned(SolutionID<N>),
+    /// The epoch of the solution ended before it was included in a block.
+    Dropped(SolutionID<N>),
+}
+
+struct TrackedSolution<N: Network> {
+    epoch_number: u32,
+    included: Option<(u32, <N as Network>::BlockHash)>,
+}
+
+/// Follows the blocks of the upstream node to find out what happened to the solutions we broadcast.
+pub struct SolutionTracker<N: Network> {
+    solutions: HashMap<SolutionID<N>, TrackedSolution<N>>,
+    blocks: BTreeMap<u32, <N as Network>::BlockHash>,
+    followed_height: Option<u32>,
+    pending_request: Option<Instant>,
+    last_recheck: Option<Instant>,
+}
+
+impl<N: Network> Default for SolutionTracker<N> {
+    fn default() -> Self {
+        Self {
+            solutions: Default::default(),
+            blocks: Default::default(),
+            followed_height: None,
+            pending_request: None,
+            last_recheck: None,
+        }
+    }
+}
+
+impl<N: Network> SolutionTracker<N> {
+    pub fn submit(&mut self, solution_id: SolutionID<N>, epoch_number: u32, height: u32) {
+        self.solutions.insert(solution_id, TrackedSolution {
+            epoch_number,
+            included: None,
+        });
+        if self.followed_height.is_none() {
+            self.followed_height = Some(height);
+        }
+    }
+
+    /// Range of blocks to request next, if any.
+    ///
+    /// Once caught up, the blocks are walked again from the oldest one including a solution every
+    /// `RECHECK_INTERVAL`, so a replaced block comes back with a different hash.
+    pub fn next_request(&mut self, latest_height: u32) -> Option<(u32, u32)> {
+        let followed_height = self.followed_height?;
+        if self.solutions.is_empty() {
+            return None;
+        }
+        if let Some(instant) = self.pending_request {
+            if instant.elapsed() < BLOCK_REQUEST_TIMEOUT {
+                return None;
+            }
+        }
+        let start_height = if followed_height < latest_height {
+            followed_height + 1
+        } else {
+            let oldest_included = self
+                .solutions
+                .values()
+                .filter_map(|solution| solution.included.map(|(height, _)| height))
+                .min()?;
+            if self.last_recheck.is_some_and(|instant| instant.elapsed() < RECHECK_INTERVAL) {
+                return None;
+            }
+            self.last_recheck = Some(Instant::now());
+            oldest_included
+        };
+        self.pending_request = Some(Instant::now());
+        let end_height = (latest_height + 1).min(start_height + MAX_BLOCKS_PER_REQUEST);
+        Some((start_height, end_height))
+    }
+
+    pub fn process_block(&mut self, block: &Block<N>) -> Vec<SolutionEvent<N>> {
+        self.process(block.height(), block.hash(), solution_rewards(block))
+    }
+
+    /// Follows a block given its height, hash and solution rewards.
+    fn process(
+        &mut self,
+        height: u32,
+        hash: <N as Network>::BlockHash,
+        rewards: Vec<(SolutionID<N>, u64)>,
+    ) -> Vec<SolutionEvent<N>> {
+        self.pending_request = None;
+        let mut events = vec![];
+
+        if let Some(previous_hash) = self.blocks.get(&height).copied() {
+            if previous_hash == hash {
+                self.followed_height = Some(height);
+                return events;
+            }
+            warn!("Block {} was replaced", height);
+            self.blocks.retain(|block_height, _| *block_height < height);
+            for (solution_id, solution) in self.solutions.iter_mut() {
+                if matches!(solution.included, Some((included_height, _)) if included_height >= height) {
+                    solution.included = None;
+                    events.push(SolutionEvent::Orphaned(*solution_id));
+                }
+            }
+        }
+        self.blocks.insert(height, hash);
+        self.followed_height = Some(height);
+
+        for (solution_id, reward) in rewards {
+            if let Some(tracked) = self.solutions.get_mut(&solution_id) {
+                if tracked.included.is_some() {
+                    continue;
+                }
+                tracked.included = Some((height, hash));
+                info!("Solution {} included in block {}", solution_id, height);
+                events.push(SolutionEvent::Confirmed(solution_id, height, reward));
+            }
+        }
+
+        let epoch_number = height / N::NUM_BLOCKS_PER_EPOCH;
+        self.solutions.retain(|solution_id, solution| match solution.included {
+            None if solution.epoch_number < epoch_number => {
+                events.push(SolutionEvent::Dropped(*solution_id));
+                false
+            }
+            Some((included_height, _)) => height < included_height + CONFIRMATION_DEPTH,
+            None => true,
+        });
+        if self.solutions.is_empty() {
+            debug!("No more solutions to follow");
+            self.followed_height = None;
+            self.blocks.clear();
+        } else {
+            self.blocks = self.blocks.split_off(&height.saturating_sub(CONFIRMATION_DEPTH));
+        }
+
+        events
+    }
+}
+
+/// Solutions of a block with their share of the puzzle reward, in proportion to their targets.
+pub fn solution_rewards<N: Network>(block: &Block<N>) -> Vec<(SolutionID<N>, u64)> {
+    let Some(solutions) = block.solutions().as_ref() else {
+        return vec![];
+    };
+    let puzzle_reward = block
+        .ratifications()
+        .iter()
+        .find_map(|ratification| match ratification {
+            Ratify::PuzzleReward(reward) => Some(*reward),
+            _ => None,
+        })
+        .unwrap_or(0);
+    let combined_target: u128 = solutions.values().map(|solution| solution.target() as u128).sum();
+    solutions
+        .iter()
+        .map(|(solution_id, solution)| {
+            let reward = match combined_target {
+                0 => 0,
+                _ => (puzzle_reward as u128 * solution.target() as u128 / combined_target) as u64,
+            };
+            (*solution_id, reward)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use snarkvm::prelude::{Field, MainnetV0};
+
+    use super::*;
+
+    type N = MainnetV0;
+
+    fn hash(n: u32) -> <N as Network>::BlockHash {
+        Field::<N>::from_u32(n).into()
+    }
+
+    #[test]
+    fn replaced_block_orphans_its_solutions() {
+        let mut tracker = SolutionTracker::<N>::default();
+        let solution_id = SolutionID::from(1u64);
+        tracker.submit(solution_id, 0, 100);
+
+        assert_eq!(tracker.next_request(103), Some((101, 104)));
+        assert!(tracker.process(101, hash(101), vec![]).is_empty());
+        let events = tracker.process(102, hash(102), vec![(solution_id, 50)]);
+        assert!(matches!(events[..], [SolutionEvent::Confirmed(id, 102, 50)] if id == solution_id));
+        assert!(tracker.process(103, hash(103), vec![]).is_empty());
+
+        // Caught up, the blocks from the one including the solution are fetched again
+        assert_eq!(tracker.next_request(103), Some((102, 104)));
+        let events = tracker.process(102, hash(1102), vec![]);
+        assert!(matches!(events[..], [SolutionEvent::Orphaned(id)] if id == solution_id));
+
+        // The walk goes on past the replaced block, then waits for the next recheck
+        assert_eq!(tracker.next_request(103), Some((103, 104)));
+        assert!(tracker.process(103, hash(1103), vec![]).is_empty());
+        assert_eq!(tracker.next_request(103), None);
+    }
+
+    #[test]
+    fn unchanged_blocks_are_rechecked_once_per_interval() {
+        let mut tracker = SolutionTracker::<N>::default();
+        let solution_id = SolutionID::from(1u64);
+        tracker.submit(solution_id, 0, 100);
+
+        assert_eq!(tracker.next_request(101), Some((101, 102)));
+        assert_eq!(tracker.process(101, hash(101), vec![(solution_id, 50)]).len(), 1);
+        assert_eq!(tracker.next_request(101), Some((101, 102)));
+        assert!(tracker.process(101, hash(101), vec![(solution_id, 50)]).is_empty());
+        assert_eq!(tracker.next_request(101), None);
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use parking_lot::RwLock;
+use serde::Serialize;
+use tokio::{task, time::interval};
+#[cfg(feature = "storage")]
+use tracing::error;
+
+#[cfg(feature = "storage")]
+use crate::state_storage::{Storage, StorageData, StorageType};
+use crate::{network::PoolNetwork, server::Server};
+
+static MINUTE: Duration = Duration::from_secs(60);
+static MINUTE_RETENTION: Duration = Duration::from_secs(60 * 60 * 24);
+static HOUR: Duration = Duration::from_secs(60 * 60);
+static HOUR_RETENTION: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+
+/// Series of the whole pool, the others are named after their address.
+pub const POOL_SERIES: &str = "";
+
+/// Speed of the pool or of an address over an interval.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct SpeedPoint {
+    /// Unix timestamp the interval starts at
+    pub timestamp: u64,
+    /// Average of the 5 minute speed over the interval
+    pub speed: f64,
+    /// Most provers connected during the interval
+    pub provers: u32,
+}
+
+fn interval_start(timestamp: u64, interval: Duration) -> u64 {
+    timestamp - timestamp % interval.as_secs()
+}
+
+#[derive(Default)]
+struct Series {
+    minutes: VecDeque<SpeedPoint>,
+    hours: VecDeque<SpeedPoint>,
+    /// Minute points before this timestamp are in an hour point already
+    downsampled_until: u64,
+}
+
+impl Series {
+    /// Turns the minute points of every hour that ended before `now` into an hour point, returns
+    /// the new hour points.
+    ///
+    /// Minutes without a point count as a speed of 0, so an hour only partly spent online is
+    /// averaged over the whole hour.
+    fn downsample(&mut self, now: u64) -> Vec<SpeedPoint> {
+        let current = interval_start(now, HOUR);
+        let minutes_per_hour = (HOUR.as_secs() / MINUTE.as_secs()) as f64;
+        let mut points = vec![];
+        loop {
+            let next = self
+                .minutes
+                .iter()
+                .find(|point| point.timestamp >= self.downsampled_until)
+                .map(|point| interval_start(point.timestamp, HOUR));
+            let Some(start) = next.filter(|start| *start < current) else {
+                break;
+            };
+            let end = start + HOUR.as_secs();
+            let (speed, provers) = self
+                .minutes
+                .iter()
+                .filter(|point| point.timestamp >= start && point.timestamp < end)
+                .fold((0.0, 0), |(speed, provers), point| (speed + point.speed, provers.max(point.provers)));
+            let point = SpeedPoint {
+                timestamp: start,
+                speed: speed / minutes_per_hour,
+                provers,
+            };
+            self.hours.push_back(point);
+            self.downsampled_until = end;
+            points.push(point);
+        }
+        points
+    }
+
+    fn prune(&mut self, now: u64) {
+        let oldest_minute = now.saturating_sub(MINUTE_RETENTION.as_secs());
+        while self.minutes.front().is_some_and(|point| point.timestamp < oldest_minute) {
+            self.minutes.pop_front();
+        }
+        let oldest_hour = now.saturating_sub(HOUR_RETENTION.as_secs());
+        while self.hours.front().is_some_and(|point| point.timestamp < oldest_hour) {
+            self.hours.pop_front();
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.minutes.is_empty() && self.hours.is_empty()
+    }
+}
+
+/// Speed of the pool and of every address sampled once a minute, kept per minute for a day and per
+/// hour for 30 days. With the state storage the points survive restarts.
+pub struct SpeedHistory {
+    series: RwLock<HashMap<String, Series>>,
+    #[cfg(feature = "storage")]
+    minute_storage: StorageData<(u64, String), (f64, u32)>,
+    #[cfg(feature = "storage")]
+    hour_storage: StorageData<(u64, String), (f64, u32)>,
+}
+
+impl SpeedHistory {
+    pub fn init(#[cfg(feature = "storage")] storage: &Storage) -> Arc<SpeedHistory> {
+        #[allow(unused_mut)]
+        let mut series = HashMap::<String, Series>::new();
+        #[cfg(feature = "storage")]
+        let minute_storage = storage.init_data::<(u64, String), (f64, u32)>(StorageType::SpeedMinutes);
+        #[cfg(feature = "storage")]
+        let hour_storage = storage.init_data::<(u64, String), (f64, u32)>(StorageType::SpeedHours);
+        #[cfg(feature = "storage")]
+        {
+            for ((timestamp, name), (speed, provers)) in minute_storage.iter() {
+                let point = SpeedPoint {
+                    timestamp,
+                    speed,
+                    provers,
+                };
+                series.entry(name).or_default().minutes.push_back(point);
+            }
+            for ((timestamp, name), (speed, provers)) in hour_storage.iter() {
+                let point = SpeedPoint {
+                    timestamp,
+                    speed,
+                    provers,
+                };
+                let series = series.entry(name).or_default();
+                series.hours.push_back(point);
+                series.downsampled_until = timestamp + HOUR.as_secs();
+            }
+        }
+        Arc::new(SpeedHistory {
+            series: RwLock::new(series),
+            #[cfg(feature = "storage")]
+            minute_storage,
+            #[cfg(feature = "storage")]
+            hour_storage,
+        })
+    }
+
+    /// Samples the speeds of the server every minute.
+    pub fn start<N: PoolNetwork>(self: Arc<Self>, server: Arc<Server<N>>) {
+        task::spawn(async move {
+            let mut ticker = interval(MINUTE);
+            loop {
+                ticker.tick().await;
+                let pool_speed = server.pool_speed().await;
+                let mut samples = vec![(
+                    POOL_SERIES.to_string(),
+                    pool_speed[0],
+                    server.online_provers().await,
+                )];
+                for address in server.online_address_list().await {
+                    let speed = server.address_speed(address).await;
+                    let provers = server.address_prover_count(address).await;
+                    samples.push((address.to_string(), speed[0], provers));
+                }
+                self.record(now_unix_secs(), samples);
+            }
+        });
+    }
+
+    /// Adds the (series, speed, provers) samples taken at `now`, and downsamples and prunes every
+    /// series.
+    fn record(&self, now: u64, samples: Vec<(String, f64, u32)>) {
+        let timestamp = interval_start(now, MINUTE);
+        let mut hour_points = vec![];
+        {
+            let mut series = self.series.write();
+            for (name, speed, provers) in &samples {
+                series.entry(name.clone()).or_default().minutes.push_back(SpeedPoint {
+                    timestamp,
+                    speed: *speed,
+                    provers: *provers,
+                });
+            }
+            for (name, series) in series.iter_mut() {
+                for point in series.downsample(now) {
+                    hour_points.push((name.clone(), point));
+                }
+                series.prune(now);
+            }
+            series.retain(|_, series| !series.is_empty());
+        }
+
+        #[cfg(feature = "storage")]
+        {
+            let result = samples
+                .into_iter()
+                .try_for_each(|(name, speed, provers)| self.minute_storage.put(&(timestamp, name), &(speed, provers)))
+                .and_then(|_| {
+                    hour_points.into_iter().try_for_each(|(name, point)| {
+                        self.hour_storage.put(&(point.timestamp, name), &(point.speed, point.provers))
+                    })
+                });
+            if let Err(e) = result {
+                error!("Unable to record speed history: {}", e);
+            }
+            Self::prune_storage(&self.minute_storage, now.saturating_sub(MINUTE_RETENTION.as_secs()));
+            Self::prune_storage(&self.hour_storage, now.saturating_sub(HOUR_RETENTION.as_secs()));
+        }
+    }
+
+    #[cfg(feature = "storage")]
+    fn prune_storage(storage: &StorageData<(u64, String), (f64, u32)>, oldest: u64) {
+        let expired = storage
+            .iter()
+            .take_while(|((timestamp, _), _)| *timestamp < oldest)
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        for key in expired {
+            if let Err(e) = storage.delete(&key) {
+                error!("Unable to prune speed history: {}", e);
+                return;
+            }
+        }
+    }
+
+    /// Points of a series over the last `period`, per minute if the minute points go back that far,
+    /// per hour otherwise. Returns the resolution in seconds with the points, oldest first.
+    pub fn points(&self, series: &str, period: Duration) -> (u64, Vec<SpeedPoint>) {
+        let oldest = now_unix_secs().saturating_sub(period.as_secs());
+        let resolution = if period <= MINUTE_RETENTION { MINUTE } else { HOUR };
+        let points = match self.series.read().get(series) {
+            Some(series) => {
+                let points = if resolution == MINUTE { &series.minutes } else { &series.hours };
+                points.iter().filter(|point| point.timestamp >= oldest).copied().collect()
+            }
+            None => vec![],
+        };
+        (resolution.as_secs(), points)
+    }
+}
+
+fn now_unix_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -1,56 +1,65 @@
-
-use std::{marker::PhantomData, sync::Arc};
+use std::{fs::create_dir_all, marker::PhantomData, path::Path, sync::Arc};
 
 use anyhow::Result;
 use bincode::Options;
-use dirs::home_dir;
 use rocksdb::{DBWithThreadMode, SingleThreaded, DB};
 use serde::{de::DeserializeOwned, Serialize};
-use tracing::error;
+use tracing::{error, info};
 
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Clone)]
 pub enum StorageType {
+    /// Payout model snapshots
     PPLNS,
+    /// Banned prover IPs, with the unix timestamp the ban ends at
+    Bans,
+    /// Session state of every prover address, such as its last target
+    Sessions,
+    /// Share value of every address per second
+    ShareHistory,
+    /// Speed of the pool and of every address per minute
+    SpeedMinutes,
+    /// Speed of the pool and of every address per hour
+    SpeedHours,
 }
 
 impl StorageType {
     pub fn prefix(&self) -> &'static [u8; 1] {
         match self {
             StorageType::PPLNS => &[0],
+            StorageType::Bans => &[1],
+            StorageType::Sessions => &[2],
+            StorageType::ShareHistory => &[3],
+            StorageType::SpeedMinutes => &[4],
+            StorageType::SpeedHours => &[5],
         }
     }
 }
 
+fn options() -> impl Options + Copy {
+    // Big endian fixed size integers keep the keys of a prefix in numeric order
+    bincode::config::DefaultOptions::new()
+        .with_big_endian()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+}
+
 pub struct Storage {
     db: Arc<DB>,
 }
 
 impl Storage {
-    pub fn load() -> Storage {
-        let home = home_dir();
-        if home.is_none() {
-            panic!("No home directory found");
-        }
-        let db_path = home.unwrap().join(".aleo_pool_testnet3/state.db");
+    pub fn load(data_dir: &Path) -> Storage {
+        create_dir_all(data_dir).expect("Failed to create data directory");
+        let db_path = data_dir.join("state.db");
         let mut db_options = rocksdb::Options::default();
         db_options.create_if_missing(true);
         db_options.set_compression_type(rocksdb::DBCompressionType::Zstd);
         db_options.set_use_fsync(true);
         db_options.set_prefix_extractor(rocksdb::SliceTransform::create_fixed_prefix(1));
-        db_options.set_comparator("comparator_v1", |a, b| {
-            if !(a[0] == 1 && b[0] == 1) {
-                a.cmp(b)
-            } else if a == [1] {
-                std::cmp::Ordering::Less
-            } else if b == [1] {
-                std::cmp::Ordering::Greater
-            } else {
-                a.cmp(b).reverse()
-            }
-        });
 
         let db = DB::open(&db_options, db_path.to_str().unwrap()).expect("Failed to open DB");
+        info!("Opened state storage at {}", db_path.display());
 
         Storage { db: Arc::new(db) }
     }
@@ -75,34 +84,33 @@ pub struct StorageData<K: Serialize + DeserializeOwned, V: Serialize + Deseriali
 }
 
 impl<K: Serialize + DeserializeOwned, V: Serialize + DeserializeOwned> StorageData<K, V> {
-    pub fn get(&self, key: &K) -> Result<Option<V>> {
-        let options = bincode::config::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding()
-            .allow_trailing_bytes();
+    fn key(&self, key: &K) -> Result<Vec<u8>> {
+        let options = options();
         let mut key_buf = vec![self.storage_type.prefix()[0]];
         key_buf.reserve(options.serialized_size(&key)? as usize);
         options.serialize_into(&mut key_buf, key)?;
-        match self.db.get(key_buf)? {
-            Some(value) => Ok(Some(options.deserialize(&value)?)),
+        Ok(key_buf)
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        match self.db.get(self.key(key)?)? {
+            Some(value) => Ok(Some(options().deserialize(&value)?)),
             None => Ok(None),
         }
     }
 
     pub fn put(&self, key: &K, value: &V) -> Result<()> {
-        let options = bincode::config::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding()
-            .allow_trailing_bytes();
-        let mut key_buf = vec![self.storage_type.prefix()[0]];
-        key_buf.reserve(options.serialized_size(&key)? as usize);
-        options.serialize_into(&mut key_buf, key)?;
-        let value_buf = options.serialize(value)?;
-        self.db.put(key_buf, value_buf)?;
+        let value_buf = options().serialize(value)?;
+        self.db.put(self.key(key)?, value_buf)?;
         Ok(())
     }
 
-    #[allow(dead_code)]
+    pub fn delete(&self, key: &K) -> Result<()> {
+        self.db.delete(self.key(key)?)?;
+        Ok(())
+    }
+
+    /// Entries in key order.
     pub fn iter(&self) -> StorageIter<'_, K, V> {
         StorageIter {
             storage_type: self.storage_type.clone(),
@@ -122,10 +130,7 @@ impl<'a, K: Serialize + DeserializeOwned, V: Serialize + DeserializeOwned> Itera
     type Item = (K, V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let options = bincode::config::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding()
-            .allow_trailing_bytes();
+        let options = options();
         match self.iter.next()? {
             Ok((raw_key, raw_value)) => {
                 if raw_key[0] == self.storage_type.prefix()[0] {
@@ -150,4 +155,4 @@ impl<'a, K: Serialize + DeserializeOwned, V: Serialize + DeserializeOwned> Itera
             }
         }
     }
-}
\ No newline at end of file
+}
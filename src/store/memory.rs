@@ -0,0 +1,541 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+
+use super::{
+    AddressBalance,
+    PayableBalance,
+    PayoutRecord,
+    PendingTransfer,
+    PoolStore,
+    ProverOptions,
+    ProverSettings,
+    SolutionRecord,
+    SolutionShare,
+    SolutionShares,
+    SolutionState,
+};
+use crate::accounting::{Distribution, Payout};
+
+struct SolutionRow {
+    id: i32,
+    solution_id: String,
+    state: SolutionState,
+    height: Option<u32>,
+    reward: Option<u64>,
+    timestamp: u64,
+}
+
+impl SolutionRow {
+    fn record(&self) -> SolutionRecord {
+        SolutionRecord {
+            id: self.id,
+            solution_id: self.solution_id.clone(),
+            state: self.state,
+            height: self.height,
+            reward: self.reward,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+struct PayoutRow {
+    id: i32,
+    solution_id: Option<i32>,
+    address: String,
+    amount: u64,
+    timestamp: u64,
+    transaction_id: Option<String>,
+    kind: &'static str,
+    status: &'static str,
+    recipient: Option<String>,
+    origin: Option<String>,
+}
+
+#[derive(Default)]
+struct Balance {
+    unpaid: u64,
+    pending: u64,
+    paid: u64,
+    settings: Option<ProverSettings>,
+    options: ProverOptions,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    solutions: Vec<SolutionRow>,
+    shares: HashMap<i32, SolutionShares>,
+    payouts: Vec<PayoutRow>,
+    balances: HashMap<String, Balance>,
+    stats: HashMap<&'static str, u64>,
+}
+
+impl MemoryState {
+    fn solution_mut(&mut self, id: i32) -> Result<&mut SolutionRow> {
+        self.solutions
+            .iter_mut()
+            .find(|solution| solution.id == id)
+            .ok_or_else(|| anyhow!("Solution id does not exist"))
+    }
+
+    /// Window of a solution, walking back to its keyframe. Addresses that left it are kept at 0.
+    fn window(&self, id: i32) -> HashMap<String, i64> {
+        let mut window = HashMap::<String, i64>::new();
+        let mut next = Some(id);
+        while let Some(shares) = next.and_then(|id| self.shares.get(&id)) {
+            for (address, share) in &shares.shares {
+                *window.entry(address.clone()).or_default() += share;
+            }
+            next = shares.base;
+        }
+        window
+    }
+
+    /// Moves a solution to `to`, failing if it is not in one of the states allowed before it.
+    fn transition(&mut self, id: i32, to: SolutionState) -> Result<()> {
+        let solution = self.solution_mut(id)?;
+        if !to.previous().contains(&solution.state) {
+            return Err(anyhow!(
+                "Solution can not move from {} to {}",
+                solution.state.as_str(),
+                to.as_str()
+            ));
+        }
+        solution.state = to;
+        Ok(())
+    }
+
+    fn add_payout(&mut self, solution_id: i32, payout: &Payout) {
+        let id = self.payouts.len() as i32 + 1;
+        self.payouts.push(PayoutRow {
+            id,
+            solution_id: Some(solution_id),
+            address: payout.address.clone(),
+            amount: payout.amount,
+            timestamp: now_unix_secs(),
+            transaction_id: None,
+            kind: payout.kind.as_str(),
+            status: "done",
+            recipient: None,
+            origin: payout.origin.clone(),
+        });
+    }
+
+    /// Records a pending transfer, returns the id of its payout.
+    fn add_transfer(&mut self, address: &str, recipient: &str, amount: u64, transaction_id: &str) -> i32 {
+        let id = self.payouts.len() as i32 + 1;
+        self.payouts.push(PayoutRow {
+            id,
+            solution_id: None,
+            address: address.to_string(),
+            amount,
+            timestamp: now_unix_secs(),
+            transaction_id: Some(transaction_id.to_string()),
+            kind: "transfer",
+            status: "pending",
+            recipient: Some(recipient.to_string()),
+            origin: None,
+        });
+        id
+    }
+}
+
+fn now_unix_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Store that keeps everything in memory, for running the pool without a database.
+#[derive(Default)]
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+}
+
+impl PoolStore for MemoryStore {
+    fn save_solution<'a>(&'a self, solution_id: &'a str, shares: &'a SolutionShares) -> BoxFuture<'a, Result<i32>> {
+        Box::pin(async move {
+            let mut state = self.state.lock();
+            let id = state.solutions.len() as i32 + 1;
+            state.solutions.push(SolutionRow {
+                id,
+                solution_id: solution_id.to_string(),
+                state: SolutionState::Pending,
+                height: None,
+                reward: None,
+                timestamp: now_unix_secs(),
+            });
+            state.shares.insert(id, shares.clone());
+            Ok(id)
+        })
+    }
+
+    fn confirm_solution<'a>(&'a self, solution_id: &'a str, height: u32, reward: u64) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let allowed = SolutionState::Confirmed.previous();
+            let mut state = self.state.lock();
+            for solution in state
+                .solutions
+                .iter_mut()
+                .filter(|s| s.solution_id == solution_id && allowed.contains(&s.state))
+            {
+                solution.state = SolutionState::Confirmed;
+                solution.height = Some(height);
+                solution.reward = Some(reward);
+            }
+            Ok(())
+        })
+    }
+
+    fn set_solution_state<'a>(&'a self, solution_id: &'a str, state: SolutionState) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let allowed = state.previous();
+            let mut moved = false;
+            for solution in self
+                .state
+                .lock()
+                .solutions
+                .iter_mut()
+                .filter(|s| s.solution_id == solution_id && allowed.contains(&s.state))
+            {
+                solution.state = state;
+                moved = true;
+            }
+            Ok(moved)
+        })
+    }
+
+    fn get_unsettled_solutions(&self) -> BoxFuture<'_, Result<Vec<SolutionRecord>>> {
+        Box::pin(async move {
+            Ok(self
+                .state
+                .lock()
+                .solutions
+                .iter()
+                .filter(|s| matches!(s.state, SolutionState::Pending | SolutionState::Confirmed))
+                .map(SolutionRow::record)
+                .collect())
+        })
+    }
+
+    fn get_solutions(&self, after: i32, limit: u32) -> BoxFuture<'_, Result<Vec<SolutionRecord>>> {
+        Box::pin(async move {
+            Ok(self
+                .state
+                .lock()
+                .solutions
+                .iter()
+                .filter(|s| s.id > after)
+                .take(limit as usize)
+                .map(SolutionRow::record)
+                .collect())
+        })
+    }
+
+    fn get_recent_solutions(&self, before: i32, limit: u32) -> BoxFuture<'_, Result<Vec<SolutionRecord>>> {
+        Box::pin(async move {
+            Ok(self
+                .state
+                .lock()
+                .solutions
+                .iter()
+                .rev()
+                .filter(|s| s.id < before)
+                .take(limit as usize)
+                .map(SolutionRow::record)
+                .collect())
+        })
+    }
+
+    fn get_solution_payouts(&self, solution_id: i32) -> BoxFuture<'_, Result<Vec<Payout>>> {
+        Box::pin(async move {
+            self.state
+                .lock()
+                .payouts
+                .iter()
+                .filter(|payout| payout.solution_id == Some(solution_id))
+                .map(|payout| {
+                    Ok(Payout {
+                        address: payout.address.clone(),
+                        amount: payout.amount,
+                        kind: payout.kind.parse()?,
+                        origin: payout.origin.clone(),
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn get_solution_shares(&self, solution_id: i32) -> BoxFuture<'_, Result<HashMap<String, u64>>> {
+        Box::pin(async move {
+            Ok(self
+                .state
+                .lock()
+                .window(solution_id)
+                .into_iter()
+                .filter(|(_, share)| *share > 0)
+                .map(|(address, share)| (address, share as u64))
+                .collect())
+        })
+    }
+
+    fn pay_solution<'a>(
+        &'a self,
+        solution_id: i32,
+        distribution: &'a Distribution,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut state = self.state.lock();
+            state.transition(solution_id, SolutionState::Mature)?;
+
+            let mut total_paid = 0;
+            for payout in &distribution.payouts {
+                state.add_payout(solution_id, payout);
+                state.balances.entry(payout.address.clone()).or_default().unpaid += payout.amount;
+                if !payout.kind.from_fee() {
+                    total_paid += payout.amount;
+                }
+            }
+            *state.stats.entry("total_paid").or_default() += total_paid;
+            *state.stats.entry("total_fee").or_default() += distribution.fee;
+            Ok(())
+        })
+    }
+
+    fn close_solution(&self, solution_id: i32) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.state.lock().transition(solution_id, SolutionState::Mature)
+        })
+    }
+
+    fn credit_balances<'a>(&'a self, credits: &'a HashMap<String, u64>) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut state = self.state.lock();
+            for (address, amount) in credits {
+                state.balances.entry(address.clone()).or_default().unpaid += amount;
+            }
+            *state.stats.entry("total_paid").or_default() += credits.values().sum::<u64>();
+            Ok(())
+        })
+    }
+
+    fn get_payable_balances(&self, minimum: u64) -> BoxFuture<'_, Result<Vec<PayableBalance>>> {
+        Box::pin(async move {
+            let mut balances = self
+                .state
+                .lock()
+                .balances
+                .iter()
+                .filter(|(_, balance)| {
+                    let settings = balance.settings.as_ref();
+                    let prover_minimum = settings.and_then(|settings| settings.minimum_payout).unwrap_or_default();
+                    balance.unpaid > 0 && balance.unpaid >= minimum.max(prover_minimum)
+                })
+                .map(|(address, balance)| PayableBalance {
+                    address: address.clone(),
+                    unpaid: balance.unpaid,
+                    recipient: balance
+                        .settings
+                        .as_ref()
+                        .and_then(|settings| settings.payout_address.clone())
+                        .unwrap_or_else(|| address.clone()),
+                })
+                .collect::<Vec<_>>();
+            balances.sort_by(|a, b| b.unpaid.cmp(&a.unpaid));
+            Ok(balances)
+        })
+    }
+
+    fn start_transfer<'a>(
+        &'a self,
+        address: &'a str,
+        recipient: &'a str,
+        amount: u64,
+        transaction_id: &'a str,
+    ) -> BoxFuture<'a, Result<i32>> {
+        Box::pin(async move {
+            let mut state = self.state.lock();
+            let balance = state.balances.entry(address.to_string()).or_default();
+            if balance.unpaid < amount {
+                return Err(anyhow!("Balance of {} is lower than {}", address, amount));
+            }
+            balance.unpaid -= amount;
+            balance.pending += amount;
+            Ok(state.add_transfer(address, recipient, amount, transaction_id))
+        })
+    }
+
+    fn get_pending_transfers(&self) -> BoxFuture<'_, Result<Vec<PendingTransfer>>> {
+        Box::pin(async move {
+            Ok(self
+                .state
+                .lock()
+                .payouts
+                .iter()
+                .filter(|payout| payout.kind == "transfer" && payout.status == "pending")
+                .map(|payout| PendingTransfer {
+                    id: payout.id,
+                    address: payout.address.clone(),
+                    amount: payout.amount,
+                    transaction_id: payout.transaction_id.clone().unwrap_or_default(),
+                    timestamp: payout.timestamp,
+                })
+                .collect())
+        })
+    }
+
+    fn get_balance<'a>(&'a self, address: &'a str) -> BoxFuture<'a, Result<Option<AddressBalance>>> {
+        Box::pin(async move {
+            Ok(self.state.lock().balances.get(address).map(|balance| AddressBalance {
+                unpaid: balance.unpaid,
+                pending: balance.pending,
+                paid: balance.paid,
+            }))
+        })
+    }
+
+    fn get_address_payouts<'a>(
+        &'a self,
+        address: &'a str,
+        before: i32,
+        limit: u32,
+    ) -> BoxFuture<'a, Result<Vec<PayoutRecord>>> {
+        Box::pin(async move {
+            let state = self.state.lock();
+            Ok(state
+                .payouts
+                .iter()
+                .rev()
+                .filter(|payout| payout.address == address && payout.id < before)
+                .take(limit as usize)
+                .map(|payout| PayoutRecord {
+                    id: payout.id,
+                    solution_id: payout.solution_id.and_then(|id| {
+                        let solution = state.solutions.iter().find(|solution| solution.id == id)?;
+                        Some(solution.solution_id.clone())
+                    }),
+                    amount: payout.amount,
+                    kind: payout.kind.to_string(),
+                    status: payout.status.to_string(),
+                    transaction_id: payout.transaction_id.clone(),
+                    recipient: payout.recipient.clone(),
+                    origin: payout.origin.clone(),
+                    timestamp: payout.timestamp,
+                })
+                .collect())
+        })
+    }
+
+    fn get_address_shares<'a>(
+        &'a self,
+        address: &'a str,
+        before: i32,
+        limit: u32,
+    ) -> BoxFuture<'a, Result<Vec<SolutionShare>>> {
+        Box::pin(async move {
+            let state = self.state.lock();
+            Ok(state
+                .solutions
+                .iter()
+                .rev()
+                .filter(|s| s.id < before)
+                .take(limit as usize)
+                .map(|solution| {
+                    let window = state.window(solution.id);
+                    SolutionShare {
+                        solution: solution.record(),
+                        share: window.get(address).copied().unwrap_or_default().max(0) as u64,
+                        total: window.values().map(|share| (*share).max(0) as u64).sum(),
+                    }
+                })
+                .collect())
+        })
+    }
+
+    fn get_stats(&self) -> BoxFuture<'_, Result<HashMap<String, u64>>> {
+        Box::pin(async move {
+            Ok(self
+                .state
+                .lock()
+                .stats
+                .iter()
+                .map(|(key, value)| (key.to_string(), *value))
+                .collect())
+        })
+    }
+
+    fn finish_transfer(&self, id: i32, accepted: bool) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let mut state = self.state.lock();
+            let payout = state
+                .payouts
+                .iter_mut()
+                .find(|payout| payout.id == id && payout.status == "pending")
+                .ok_or_else(|| anyhow!("Transfer is not pending"))?;
+            payout.status = if accepted { "done" } else { "failed" };
+            let (address, amount) = (payout.address.clone(), payout.amount);
+            let balance = state.balances.entry(address).or_default();
+            balance.pending -= amount;
+            if accepted {
+                balance.paid += amount;
+            } else {
+                balance.unpaid += amount;
+            }
+            Ok(())
+        })
+    }
+
+    fn get_prover_settings<'a>(&'a self, address: &'a str) -> BoxFuture<'a, Result<Option<ProverSettings>>> {
+        Box::pin(async move {
+            Ok(self
+                .state
+                .lock()
+                .balances
+                .get(address)
+                .and_then(|balance| balance.settings.clone()))
+        })
+    }
+
+    fn set_prover_settings<'a>(
+        &'a self,
+        address: &'a str,
+        settings: &'a ProverSettings,
+    ) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let mut state = self.state.lock();
+            let balance = state.balances.entry(address.to_string()).or_default();
+            if matches!(&balance.settings, Some(current) if current.timestamp >= settings.timestamp) {
+                return Ok(false);
+            }
+            let options = &mut balance.options;
+            options.donation_bps = settings.donation_bps;
+            if options.referrer.is_none() {
+                options.referrer = settings.referrer.clone();
+            }
+            balance.settings = Some(ProverSettings {
+                referrer: options.referrer.clone(),
+                ..settings.clone()
+            });
+            Ok(true)
+        })
+    }
+
+    fn get_prover_options<'a>(
+        &'a self,
+        addresses: &'a [String],
+    ) -> BoxFuture<'a, Result<HashMap<String, ProverOptions>>> {
+        Box::pin(async move {
+            let state = self.state.lock();
+            Ok(addresses
+                .iter()
+                .filter_map(|address| {
+                    let balance = state.balances.get(address)?;
+                    Some((address.clone(), balance.options.clone()))
+                })
+                .collect())
+        })
+    }
+}
@@ -0,0 +1,316 @@
+mod memory;
+#[cfg(feature = "db")]
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::{collections::HashMap, path::Path, str::FromStr, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+pub use memory::MemoryStore;
+#[cfg(feature = "db")]
+pub use postgres::PostgresStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+use crate::accounting::{Distribution, Payout};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum StoreKind {
+    /// Keep everything in memory, lost on exit
+    Memory,
+    /// PostgreSQL, configured with the DB_* environment variables
+    #[cfg(feature = "db")]
+    Postgres,
+    /// SQLite database in the data directory, for small pools
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SolutionState {
+    /// Broadcast to the network, not seen in a block yet
+    Pending,
+    /// Included in a block that is not deep enough yet
+    Confirmed,
+    /// Buried under enough blocks, the reward is credited
+    Mature,
+    /// The block including it was replaced, the reward it had is lost
+    Orphaned,
+    /// Never included in a block, there is no reward
+    Expired,
+}
+
+impl SolutionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SolutionState::Pending => "pending",
+            SolutionState::Confirmed => "confirmed",
+            SolutionState::Mature => "mature",
+            SolutionState::Orphaned => "orphaned",
+            SolutionState::Expired => "expired",
+        }
+    }
+
+    /// States a solution can move to this one from.
+    pub fn previous(&self) -> &'static [SolutionState] {
+        match self {
+            SolutionState::Pending => &[],
+            // An orphaned solution can still be included in a later block
+            SolutionState::Confirmed => &[SolutionState::Pending, SolutionState::Confirmed, SolutionState::Orphaned],
+            SolutionState::Mature => &[SolutionState::Confirmed],
+            SolutionState::Orphaned => &[SolutionState::Confirmed],
+            SolutionState::Expired => &[SolutionState::Pending],
+        }
+    }
+}
+
+impl FromStr for SolutionState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pending" => Ok(SolutionState::Pending),
+            "confirmed" => Ok(SolutionState::Confirmed),
+            "mature" => Ok(SolutionState::Mature),
+            "orphaned" => Ok(SolutionState::Orphaned),
+            "expired" => Ok(SolutionState::Expired),
+            _ => Err(anyhow!("Unknown solution state {}", s)),
+        }
+    }
+}
+
+/// Share window of a solution, as stored.
+///
+/// Every solution is paid over the whole window of the payout model at the moment it was found, so
+/// when solutions are found close together their windows overlap and the shares in the overlap count
+/// for each of them, as PPLNS pays a share for every solution found while it is in the window. The
+/// windows are then nearly the same, and instead of a full copy a solution stores how its window
+/// differs from the one of an earlier solution, its base. A solution without a base is a keyframe
+/// holding its whole window.
+#[derive(Clone, Debug, Default)]
+pub struct SolutionShares {
+    pub base: Option<i32>,
+    /// Share of every address in the window, or the change from the base for each address whose
+    /// share differs
+    pub shares: HashMap<String, i64>,
+}
+
+/// A solution found by the pool.
+pub struct SolutionRecord {
+    pub id: i32,
+    pub solution_id: String,
+    pub state: SolutionState,
+    pub height: Option<u32>,
+    pub reward: Option<u64>,
+    /// Unix timestamp the solution was found at
+    pub timestamp: u64,
+}
+
+/// Share of an address in the window of a solution.
+pub struct SolutionShare {
+    pub solution: SolutionRecord,
+    pub share: u64,
+    /// Shares of all addresses in the window
+    pub total: u64,
+}
+
+/// Balance of an address, in microcredits.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AddressBalance {
+    /// Credited and not sent yet
+    pub unpaid: u64,
+    /// In transfers not confirmed yet
+    pub pending: u64,
+    pub paid: u64,
+}
+
+/// Payout row of an address, credited from a solution or transferring its balance.
+#[derive(Clone, Debug, Serialize)]
+pub struct PayoutRecord {
+    pub id: i32,
+    /// Solution the amount was credited from, none for transfers
+    pub solution_id: Option<String>,
+    pub amount: u64,
+    /// A `PayoutKind`, or "transfer"
+    pub kind: String,
+    /// "done", or "pending" and "failed" for transfers
+    pub status: String,
+    pub transaction_id: Option<String>,
+    pub recipient: Option<String>,
+    /// Address whose earnings a donation or referral reward comes from
+    pub origin: Option<String>,
+    pub timestamp: u64,
+}
+
+/// Payout preferences a prover signed for its mining address.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ProverSettings {
+    /// Smallest balance to transfer, it can only raise the pool minimum
+    pub minimum_payout: Option<u64>,
+    /// Address the balance is transferred to instead of the mining address
+    pub payout_address: Option<String>,
+    /// Part of the earnings donated to the pool, in basis points
+    #[serde(default)]
+    pub donation_bps: u64,
+    /// Address referring the prover, only the first one is kept
+    pub referrer: Option<String>,
+    /// Unix timestamp of the signed message, changes need a newer one
+    pub timestamp: u64,
+}
+
+/// Donation and referrer of a prover, as used to distribute rewards.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProverOptions {
+    /// Part of the earnings donated to the pool, in basis points
+    pub donation_bps: u64,
+    /// Address referring the prover, credited with the referral reward
+    pub referrer: Option<String>,
+}
+
+/// Balance due for a transfer.
+pub struct PayableBalance {
+    pub address: String,
+    pub unpaid: u64,
+    /// Payout address of the prover, or the mining address
+    pub recipient: String,
+}
+
+/// On-chain transfer of a balance that is not confirmed yet.
+#[derive(Serialize)]
+pub struct PendingTransfer {
+    pub id: i32,
+    pub address: String,
+    pub amount: u64,
+    pub transaction_id: String,
+    /// Unix timestamp the transfer was broadcast at
+    pub timestamp: u64,
+}
+
+/// Where solutions, their shares, balances and payouts are kept.
+pub trait PoolStore: Send + Sync {
+    /// Fails unless the schema is at the version this build expects.
+    fn check_schema(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Applies the pending schema migrations.
+    fn migrate(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Records a solution found by the pool, with the shares its reward is distributed over. Returns
+    /// the id of the solution.
+    fn save_solution<'a>(&'a self, solution_id: &'a str, shares: &'a SolutionShares) -> BoxFuture<'a, Result<i32>>;
+
+    /// Records the block a solution was included in. Mature and expired solutions are left alone.
+    fn confirm_solution<'a>(&'a self, solution_id: &'a str, height: u32, reward: u64) -> BoxFuture<'a, Result<()>>;
+
+    /// Moves a solution to `state` if it is in one of `state.previous()`, returns whether it moved.
+    /// Only for the orphaned and expired states, the others have their own methods.
+    fn set_solution_state<'a>(&'a self, solution_id: &'a str, state: SolutionState) -> BoxFuture<'a, Result<bool>>;
+
+    /// Pending and confirmed solutions, oldest first.
+    fn get_unsettled_solutions(&self) -> BoxFuture<'_, Result<Vec<SolutionRecord>>>;
+
+    /// Up to `limit` solutions with an id above `after`, oldest first.
+    fn get_solutions(&self, after: i32, limit: u32) -> BoxFuture<'_, Result<Vec<SolutionRecord>>>;
+
+    /// Up to `limit` solutions with an id below `before`, newest first.
+    fn get_recent_solutions(&self, before: i32, limit: u32) -> BoxFuture<'_, Result<Vec<SolutionRecord>>>;
+
+    /// Rewards, fees, donations and referral rewards credited for a solution.
+    fn get_solution_payouts(&self, solution_id: i32) -> BoxFuture<'_, Result<Vec<Payout>>>;
+
+    /// Whole window of a solution, rebuilt from its keyframe and the changes stored since.
+    fn get_solution_shares(&self, solution_id: i32) -> BoxFuture<'_, Result<HashMap<String, u64>>>;
+
+    /// Writes the payouts of a confirmed solution, credits the balances and marks it mature, all or
+    /// nothing.
+    fn pay_solution<'a>(
+        &'a self,
+        solution_id: i32,
+        distribution: &'a Distribution,
+    ) -> BoxFuture<'a, Result<()>>;
+
+    /// Marks a confirmed solution mature without distributing anything, for rewards the payout model
+    /// keeps.
+    fn close_solution(&self, solution_id: i32) -> BoxFuture<'_, Result<()>>;
+
+    /// Adds per-share earnings to the unpaid balances.
+    fn credit_balances<'a>(&'a self, credits: &'a HashMap<String, u64>) -> BoxFuture<'a, Result<()>>;
+
+    /// Balances with at least `minimum` unpaid, or the minimum of the prover if higher, largest first.
+    fn get_payable_balances(&self, minimum: u64) -> BoxFuture<'_, Result<Vec<PayableBalance>>>;
+
+    /// Moves `amount` of a balance from unpaid to pending and records the transfer carrying it to
+    /// `recipient`, returns the id of the transfer.
+    fn start_transfer<'a>(
+        &'a self,
+        address: &'a str,
+        recipient: &'a str,
+        amount: u64,
+        transaction_id: &'a str,
+    ) -> BoxFuture<'a, Result<i32>>;
+
+    fn get_pending_transfers(&self) -> BoxFuture<'_, Result<Vec<PendingTransfer>>>;
+
+    /// Balance of an address, none if it was never credited or configured.
+    fn get_balance<'a>(&'a self, address: &'a str) -> BoxFuture<'a, Result<Option<AddressBalance>>>;
+
+    /// Up to `limit` payout rows of an address with an id below `before`, newest first.
+    fn get_address_payouts<'a>(
+        &'a self,
+        address: &'a str,
+        before: i32,
+        limit: u32,
+    ) -> BoxFuture<'a, Result<Vec<PayoutRecord>>>;
+
+    /// Share of an address in the windows of up to `limit` solutions with an id below `before`,
+    /// newest first. Solutions the address has no share in are included with a share of 0.
+    fn get_address_shares<'a>(
+        &'a self,
+        address: &'a str,
+        before: i32,
+        limit: u32,
+    ) -> BoxFuture<'a, Result<Vec<SolutionShare>>>;
+
+    /// Pool totals, such as `total_paid` and `total_fee`.
+    fn get_stats(&self) -> BoxFuture<'_, Result<HashMap<String, u64>>>;
+
+    /// Moves the amount of a transfer from pending to paid if it was accepted, back to unpaid otherwise.
+    fn finish_transfer(&self, id: i32, accepted: bool) -> BoxFuture<'_, Result<()>>;
+
+    fn get_prover_settings<'a>(&'a self, address: &'a str) -> BoxFuture<'a, Result<Option<ProverSettings>>>;
+
+    /// Replaces the settings of an address unless the stored ones are as new, returns whether they
+    /// were replaced. The referrer is only set if the address has none yet.
+    fn set_prover_settings<'a>(
+        &'a self,
+        address: &'a str,
+        settings: &'a ProverSettings,
+    ) -> BoxFuture<'a, Result<bool>>;
+
+    /// Options of the given addresses, those without a balance are left out.
+    fn get_prover_options<'a>(
+        &'a self,
+        addresses: &'a [String],
+    ) -> BoxFuture<'a, Result<HashMap<String, ProverOptions>>>;
+}
+
+pub fn open(
+    kind: StoreKind,
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))] data_dir: &Path,
+) -> Arc<dyn PoolStore> {
+    match kind {
+        StoreKind::Memory => Arc::new(MemoryStore::default()),
+        #[cfg(feature = "db")]
+        StoreKind::Postgres => Arc::new(PostgresStore::init()),
+        #[cfg(feature = "sqlite")]
+        StoreKind::Sqlite => Arc::new(SqliteStore::open(&data_dir.join("pool.sqlite"))),
+    }
+}
@@ -0,0 +1,774 @@
+use std::{collections::HashMap, env};
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use deadpool_postgres::{
+    ClientWrapper,
+    Config,
+    Hook,
+    HookError,
+    Manager,
+    ManagerConfig,
+    Pool,
+    RecyclingMethod,
+    Runtime,
+};
+use tokio_postgres::{Client, NoTls, Row};
+use tracing::{info, warn};
+
+use super::{
+    AddressBalance,
+    PayableBalance,
+    PayoutRecord,
+    PendingTransfer,
+    PoolStore,
+    ProverOptions,
+    ProverSettings,
+    SolutionRecord,
+    SolutionShare,
+    SolutionShares,
+    SolutionState,
+};
+use crate::accounting::{Distribution, Payout};
+
+/// Embedded schema migrations as (version, name, sql), applied in order by `migrate`.
+static MIGRATIONS: &[(i32, &str, &str)] = &[
+    (1, "initial", include_str!("../../migrations/postgres/0001_initial.sql")),
+    (2, "drop_pay_solution", include_str!("../../migrations/postgres/0002_drop_pay_solution.sql")),
+    (3, "solution_state", include_str!("../../migrations/postgres/0003_solution_state.sql")),
+    (4, "transfers", include_str!("../../migrations/postgres/0004_transfers.sql")),
+    (5, "prover_settings", include_str!("../../migrations/postgres/0005_prover_settings.sql")),
+    (6, "fee_splits", include_str!("../../migrations/postgres/0006_fee_splits.sql")),
+    (7, "share_deltas", include_str!("../../migrations/postgres/0007_share_deltas.sql")),
+    (8, "unsigned_options", include_str!("../../migrations/postgres/0008_unsigned_options.sql")),
+];
+
+fn solution_record(row: &Row) -> Result<SolutionRecord> {
+    let state: String = row.get("state");
+    let height: Option<i64> = row.get("height");
+    let reward: Option<i64> = row.get("reward");
+    let timestamp: i64 = row.get("timestamp");
+    Ok(SolutionRecord {
+        id: row.get("id"),
+        solution_id: row.get("solution_id"),
+        state: state.parse()?,
+        height: height.map(|height| height as u32),
+        reward: reward.map(|reward| reward as u64),
+        timestamp: timestamp as u64,
+    })
+}
+
+fn latest_schema_version() -> i32 {
+    MIGRATIONS.last().map(|(version, _, _)| *version).unwrap_or_default()
+}
+
+fn state_names(states: &[SolutionState]) -> Vec<&'static str> {
+    states.iter().map(SolutionState::as_str).collect()
+}
+
+async fn table_exists(conn: &Client, table: &str) -> Result<bool> {
+    let row = conn
+        .query_one("SELECT to_regclass($1)::text AS name", &[&table])
+        .await?;
+    Ok(row.get::<_, Option<String>>("name").is_some())
+}
+
+pub struct PostgresStore {
+    connection_pool: Pool,
+    schema: String,
+}
+
+impl PostgresStore {
+    pub fn init() -> PostgresStore {
+        let mut cfg = Config::new();
+        cfg.host = Some(env::var("DB_HOST").expect("No database host defined"));
+        cfg.port = Some(
+            env::var("DB_PORT")
+                .unwrap_or_else(|_| "5432".to_string())
+                .parse::<u16>()
+                .expect("Invalid database port"),
+        );
+        cfg.dbname = Some(env::var("DB_DATABASE").expect("No database name defined"));
+        cfg.user = Some(env::var("DB_USER").expect("No database user defined"));
+        cfg.password = Some(env::var("DB_PASSWORD").expect("No database password defined"));
+        let schema = env::var("DB_SCHEMA").unwrap_or_else(|_| {
+            warn!("Using schema public as default");
+            "public".to_string()
+        });
+        cfg.manager = Some(ManagerConfig {
+            recycling_method: RecyclingMethod::Verified,
+        });
+        // This is almost like directly using deadpool, but we really need the hooks
+        // The helper methods from deadpool_postgres helps as well
+        let pool = Pool::builder(Manager::from_config(
+            cfg.get_pg_config().expect("Invalid database config"),
+            NoTls,
+            cfg.get_manager_config(),
+        ))
+        .config(cfg.get_pool_config())
+        .post_create(Hook::async_fn({
+            let schema = schema.clone();
+            move |client: &mut ClientWrapper, _| {
+                let schema = schema.clone();
+                Box::pin(async move {
+                    client
+                        .simple_query(&format!("set search_path = {}", schema))
+                        .await
+                        .map_err(|e| HookError::Backend(e))?;
+                    Ok(())
+                })
+            }
+        }))
+        .runtime(Runtime::Tokio1)
+        .build()
+        .expect("Failed to create database connection pool");
+        PostgresStore {
+            connection_pool: pool,
+            schema,
+        }
+    }
+
+    /// Version of the schema in the database. Schemas loaded from the old `pg_dump.sql` have no
+    /// version table and count as version 1.
+    async fn schema_version(&self) -> Result<i32> {
+        let conn = self.connection_pool.get().await?;
+        if table_exists(&conn, "schema_version").await? {
+            let row = conn
+                .query_one("SELECT COALESCE(MAX(version), 0) AS version FROM schema_version", &[])
+                .await?;
+            return Ok(row.get("version"));
+        }
+        if table_exists(&conn, "solution").await? {
+            return Ok(1);
+        }
+        Ok(0)
+    }
+}
+
+impl PoolStore for PostgresStore {
+    fn check_schema(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let version = self.schema_version().await?;
+            let latest = latest_schema_version();
+            if version < latest {
+                return Err(anyhow!(
+                    "Database schema {} is at version {} but this build needs version {}, run the migrate command first",
+                    self.schema,
+                    version,
+                    latest
+                ));
+            }
+            if version > latest {
+                return Err(anyhow!(
+                    "Database schema {} is at version {}, newer than version {} this build knows about",
+                    self.schema,
+                    version,
+                    latest
+                ));
+            }
+            Ok(())
+        })
+    }
+
+    /// Applies the pending migrations, each in its own transaction.
+    fn migrate(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let current = self.schema_version().await?;
+            let mut conn = self.connection_pool.get().await?;
+            conn.batch_execute(&format!(
+                "CREATE SCHEMA IF NOT EXISTS {}; \
+                 CREATE TABLE IF NOT EXISTS schema_version (\
+                     version integer PRIMARY KEY, \
+                     name text NOT NULL, \
+                     applied_at bigint DEFAULT EXTRACT(epoch FROM now()) NOT NULL\
+                 )",
+                self.schema
+            ))
+            .await?;
+            for (version, name, sql) in MIGRATIONS {
+                if *version <= current {
+                    // Record what an unversioned schema already has
+                    conn.execute(
+                        "INSERT INTO schema_version (version, name) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                        &[version, name],
+                    )
+                    .await?;
+                    continue;
+                }
+                info!("Applying migration {} ({})", version, name);
+                let transaction = conn.transaction().await?;
+                transaction.batch_execute(sql).await?;
+                transaction
+                    .execute(
+                        "INSERT INTO schema_version (version, name) VALUES ($1, $2)",
+                        &[version, name],
+                    )
+                    .await?;
+                transaction.commit().await?;
+            }
+            info!("Database schema {} is at version {}", self.schema, latest_schema_version());
+            Ok(())
+        })
+    }
+
+    fn save_solution<'a>(&'a self, solution_id: &'a str, shares: &'a SolutionShares) -> BoxFuture<'a, Result<i32>> {
+        Box::pin(async move {
+            let mut conn = self.connection_pool.get().await?;
+            let transaction = conn.transaction().await?;
+
+            let solution_id: i32 = transaction
+                .query_one(
+                    "INSERT INTO solution (solution_id, base_solution_id) VALUES ($1, $2) RETURNING id",
+                    &[&solution_id, &shares.base],
+                )
+                .await?
+                .try_get("id")?;
+
+            let stmt = transaction
+                .prepare_cached("INSERT INTO share (solution_id, address, share) VALUES ($1, $2, $3)")
+                .await?;
+            for (address, share) in &shares.shares {
+                transaction.query(&stmt, &[&solution_id, address, share]).await?;
+            }
+
+            transaction.commit().await?;
+            Ok(solution_id)
+        })
+    }
+
+    fn confirm_solution<'a>(&'a self, solution_id: &'a str, height: u32, reward: u64) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached(
+                    "UPDATE solution SET state = 'confirmed', height = $1, reward = $2 \
+                     WHERE solution_id = $3 AND state = ANY($4)",
+                )
+                .await?;
+            conn.query(
+                &stmt,
+                &[
+                    &(height as i64),
+                    &(reward as i64),
+                    &solution_id,
+                    &state_names(SolutionState::Confirmed.previous()),
+                ],
+            )
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn set_solution_state<'a>(&'a self, solution_id: &'a str, state: SolutionState) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached("UPDATE solution SET state = $1 WHERE solution_id = $2 AND state = ANY($3)")
+                .await?;
+            let moved = conn
+                .execute(&stmt, &[&state.as_str(), &solution_id, &state_names(state.previous())])
+                .await?;
+            Ok(moved > 0)
+        })
+    }
+
+    fn get_unsettled_solutions(&self) -> BoxFuture<'_, Result<Vec<SolutionRecord>>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached(
+                    "SELECT id, solution_id, state, height, reward, timestamp FROM solution \
+                     WHERE state IN ('pending', 'confirmed') ORDER BY id",
+                )
+                .await?;
+            let rows = conn.query(&stmt, &[]).await?;
+            rows.iter().map(solution_record).collect()
+        })
+    }
+
+    fn get_solutions(&self, after: i32, limit: u32) -> BoxFuture<'_, Result<Vec<SolutionRecord>>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached(
+                    "SELECT id, solution_id, state, height, reward, timestamp FROM solution \
+                     WHERE id > $1 ORDER BY id LIMIT $2",
+                )
+                .await?;
+            let rows = conn.query(&stmt, &[&after, &(limit as i64)]).await?;
+            rows.iter().map(solution_record).collect()
+        })
+    }
+
+    fn get_recent_solutions(&self, before: i32, limit: u32) -> BoxFuture<'_, Result<Vec<SolutionRecord>>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached(
+                    "SELECT id, solution_id, state, height, reward, timestamp FROM solution \
+                     WHERE id < $1 ORDER BY id DESC LIMIT $2",
+                )
+                .await?;
+            let rows = conn.query(&stmt, &[&before, &(limit as i64)]).await?;
+            rows.iter().map(solution_record).collect()
+        })
+    }
+
+    fn get_solution_payouts(&self, solution_id: i32) -> BoxFuture<'_, Result<Vec<Payout>>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached("SELECT address, amount, kind, origin FROM payout WHERE solution_id = $1 ORDER BY id")
+                .await?;
+            let rows = conn.query(&stmt, &[&solution_id]).await?;
+            rows.into_iter()
+                .map(|row| {
+                    let amount: i64 = row.get("amount");
+                    let kind: String = row.get("kind");
+                    Ok(Payout {
+                        address: row.get("address"),
+                        amount: amount as u64,
+                        kind: kind.parse()?,
+                        origin: row.get("origin"),
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn get_solution_shares(&self, solution_id: i32) -> BoxFuture<'_, Result<HashMap<String, u64>>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached(
+                    "WITH RECURSIVE chain (id, base_solution_id) AS ( \
+                         SELECT id, base_solution_id FROM solution WHERE id = $1 \
+                         UNION ALL \
+                         SELECT solution.id, solution.base_solution_id FROM solution \
+                         JOIN chain ON solution.id = chain.base_solution_id \
+                     ) \
+                     SELECT address, SUM(share)::bigint AS share FROM share \
+                     WHERE solution_id IN (SELECT id FROM chain) GROUP BY address HAVING SUM(share) > 0",
+                )
+                .await?;
+            let rows = conn.query(&stmt, &[&solution_id]).await?;
+            Ok(rows
+                .into_iter()
+                .map(|row| {
+                    let address: String = row.get("address");
+                    let share: i64 = row.get("share");
+                    (address, share as u64)
+                })
+                .collect())
+        })
+    }
+
+    fn pay_solution<'a>(
+        &'a self,
+        solution_id: i32,
+        distribution: &'a Distribution,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut conn = self.connection_pool.get().await?;
+            let transaction = conn.transaction().await?;
+
+            let state: String = transaction
+                .query_one("SELECT state FROM solution WHERE id = $1 FOR UPDATE", &[&solution_id])
+                .await?
+                .try_get("state")?;
+            if state != SolutionState::Confirmed.as_str() {
+                return Err(anyhow!("Solution is {}, not confirmed", state));
+            }
+
+            let payout_stmt = transaction
+                .prepare_cached(
+                    "INSERT INTO payout (solution_id, address, amount, kind, origin) VALUES ($1, $2, $3, $4, $5)",
+                )
+                .await?;
+            let balance_stmt = transaction
+                .prepare_cached(
+                    "INSERT INTO balance (address, unpaid) VALUES ($1, $2) \
+                     ON CONFLICT (address) DO UPDATE SET unpaid = balance.unpaid + $2",
+                )
+                .await?;
+            let stats_stmt = transaction
+                .prepare_cached(
+                    "INSERT INTO stats (key, value) VALUES ($1, $2) ON CONFLICT (key) DO UPDATE SET value = stats.value + $2",
+                )
+                .await?;
+
+            let mut total_paid = 0u64;
+            for payout in &distribution.payouts {
+                let amount = payout.amount as i64;
+                transaction
+                    .query(
+                        &payout_stmt,
+                        &[&solution_id, &payout.address, &amount, &payout.kind.as_str(), &payout.origin],
+                    )
+                    .await?;
+                transaction.query(&balance_stmt, &[&payout.address, &amount]).await?;
+                if !payout.kind.from_fee() {
+                    total_paid += payout.amount;
+                }
+            }
+            transaction
+                .query(&stats_stmt, &[&"total_paid", &(total_paid as i64)])
+                .await?;
+            transaction
+                .query(&stats_stmt, &[&"total_fee", &(distribution.fee as i64)])
+                .await?;
+            transaction
+                .query(
+                    "UPDATE solution SET paid = true, state = 'mature' WHERE id = $1",
+                    &[&solution_id],
+                )
+                .await?;
+
+            transaction.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn close_solution(&self, solution_id: i32) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached("UPDATE solution SET paid = true, state = 'mature' WHERE id = $1 AND state = 'confirmed'")
+                .await?;
+            if conn.execute(&stmt, &[&solution_id]).await? == 0 {
+                return Err(anyhow!("Solution is not confirmed"));
+            }
+            Ok(())
+        })
+    }
+
+    fn credit_balances<'a>(&'a self, credits: &'a HashMap<String, u64>) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut conn = self.connection_pool.get().await?;
+            let transaction = conn.transaction().await?;
+            let stmt = transaction
+                .prepare_cached(
+                    "INSERT INTO balance (address, unpaid) VALUES ($1, $2) \
+                     ON CONFLICT (address) DO UPDATE SET unpaid = balance.unpaid + $2",
+                )
+                .await?;
+            for (address, amount) in credits {
+                transaction.query(&stmt, &[address, &(*amount as i64)]).await?;
+            }
+            transaction
+                .query(
+                    "INSERT INTO stats (key, value) VALUES ('total_paid', $1) \
+                     ON CONFLICT (key) DO UPDATE SET value = stats.value + $1",
+                    &[&(credits.values().sum::<u64>() as i64)],
+                )
+                .await?;
+            transaction.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn get_payable_balances(&self, minimum: u64) -> BoxFuture<'_, Result<Vec<PayableBalance>>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached(
+                    "SELECT address, unpaid, COALESCE(payout_address, address) AS recipient FROM balance \
+                     WHERE unpaid > 0 AND unpaid >= GREATEST(minimum_payout, $1) ORDER BY unpaid DESC",
+                )
+                .await?;
+            let rows = conn.query(&stmt, &[&(minimum as i64)]).await?;
+            Ok(rows
+                .into_iter()
+                .map(|row| {
+                    let unpaid: i64 = row.get("unpaid");
+                    PayableBalance {
+                        address: row.get("address"),
+                        unpaid: unpaid as u64,
+                        recipient: row.get("recipient"),
+                    }
+                })
+                .collect())
+        })
+    }
+
+    fn start_transfer<'a>(
+        &'a self,
+        address: &'a str,
+        recipient: &'a str,
+        amount: u64,
+        transaction_id: &'a str,
+    ) -> BoxFuture<'a, Result<i32>> {
+        Box::pin(async move {
+            let mut conn = self.connection_pool.get().await?;
+            let transaction = conn.transaction().await?;
+            let moved = transaction
+                .execute(
+                    "UPDATE balance SET unpaid = unpaid - $2, pending = pending + $2 \
+                     WHERE address = $1 AND unpaid >= $2",
+                    &[&address, &(amount as i64)],
+                )
+                .await?;
+            if moved == 0 {
+                return Err(anyhow!("Balance of {} is lower than {}", address, amount));
+            }
+            let id: i32 = transaction
+                .query_one(
+                    "INSERT INTO payout (address, amount, transaction_id, kind, status, recipient) \
+                     VALUES ($1, $2, $3, 'transfer', 'pending', $4) RETURNING id",
+                    &[&address, &(amount as i64), &transaction_id, &recipient],
+                )
+                .await?
+                .try_get("id")?;
+            transaction.commit().await?;
+            Ok(id)
+        })
+    }
+
+    fn get_pending_transfers(&self) -> BoxFuture<'_, Result<Vec<PendingTransfer>>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached(
+                    "SELECT id, address, amount, transaction_id, timestamp FROM payout \
+                     WHERE kind = 'transfer' AND status = 'pending' ORDER BY id",
+                )
+                .await?;
+            let rows = conn.query(&stmt, &[]).await?;
+            Ok(rows
+                .into_iter()
+                .map(|row| {
+                    let amount: i64 = row.get("amount");
+                    let timestamp: Option<i32> = row.get("timestamp");
+                    PendingTransfer {
+                        id: row.get("id"),
+                        address: row.get("address"),
+                        amount: amount as u64,
+                        transaction_id: row.get("transaction_id"),
+                        timestamp: timestamp.unwrap_or_default() as u64,
+                    }
+                })
+                .collect())
+        })
+    }
+
+    fn get_balance<'a>(&'a self, address: &'a str) -> BoxFuture<'a, Result<Option<AddressBalance>>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached("SELECT unpaid, pending, paid FROM balance WHERE address = $1")
+                .await?;
+            Ok(conn.query_opt(&stmt, &[&address]).await?.map(|row| {
+                let unpaid: i64 = row.get("unpaid");
+                let pending: i64 = row.get("pending");
+                let paid: i64 = row.get("paid");
+                AddressBalance {
+                    unpaid: unpaid as u64,
+                    pending: pending as u64,
+                    paid: paid as u64,
+                }
+            }))
+        })
+    }
+
+    fn get_address_payouts<'a>(
+        &'a self,
+        address: &'a str,
+        before: i32,
+        limit: u32,
+    ) -> BoxFuture<'a, Result<Vec<PayoutRecord>>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached(
+                    "SELECT payout.id, solution.solution_id, amount, kind, status, transaction_id, recipient, origin, \
+                     payout.timestamp FROM payout LEFT JOIN solution ON solution.id = payout.solution_id \
+                     WHERE address = $1 AND payout.id < $2 ORDER BY payout.id DESC LIMIT $3",
+                )
+                .await?;
+            let rows = conn.query(&stmt, &[&address, &before, &(limit as i64)]).await?;
+            Ok(rows
+                .into_iter()
+                .map(|row| {
+                    let amount: i64 = row.get("amount");
+                    let timestamp: Option<i32> = row.get("timestamp");
+                    PayoutRecord {
+                        id: row.get("id"),
+                        solution_id: row.get("solution_id"),
+                        amount: amount as u64,
+                        kind: row.get("kind"),
+                        status: row.get("status"),
+                        transaction_id: row.get("transaction_id"),
+                        recipient: row.get("recipient"),
+                        origin: row.get("origin"),
+                        timestamp: timestamp.unwrap_or_default() as u64,
+                    }
+                })
+                .collect())
+        })
+    }
+
+    fn get_address_shares<'a>(
+        &'a self,
+        address: &'a str,
+        before: i32,
+        limit: u32,
+    ) -> BoxFuture<'a, Result<Vec<SolutionShare>>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            // The shares of the chain of each solution add up to its window, addresses that left it
+            // add up to 0
+            let stmt = conn
+                .prepare_cached(
+                    "WITH RECURSIVE page AS ( \
+                         SELECT id FROM solution WHERE id < $2 ORDER BY id DESC LIMIT $3 \
+                     ), chain (page_id, id, base_solution_id) AS ( \
+                         SELECT solution.id, solution.id, solution.base_solution_id FROM solution \
+                         JOIN page ON solution.id = page.id \
+                         UNION ALL \
+                         SELECT chain.page_id, solution.id, solution.base_solution_id FROM solution \
+                         JOIN chain ON solution.id = chain.base_solution_id \
+                     ), totals AS ( \
+                         SELECT chain.page_id, \
+                         COALESCE(SUM(share.share) FILTER (WHERE share.address = $1), 0)::bigint AS share, \
+                         COALESCE(SUM(share.share), 0)::bigint AS total \
+                         FROM chain LEFT JOIN share ON share.solution_id = chain.id GROUP BY chain.page_id \
+                     ) \
+                     SELECT id, solution_id, state, height, reward, timestamp, totals.share, totals.total \
+                     FROM solution JOIN totals ON totals.page_id = solution.id ORDER BY id DESC",
+                )
+                .await?;
+            let rows = conn.query(&stmt, &[&address, &before, &(limit as i64)]).await?;
+            rows.iter()
+                .map(|row| {
+                    let share: i64 = row.get("share");
+                    let total: i64 = row.get("total");
+                    Ok(SolutionShare {
+                        solution: solution_record(row)?,
+                        share: share.max(0) as u64,
+                        total: total.max(0) as u64,
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn get_stats(&self) -> BoxFuture<'_, Result<HashMap<String, u64>>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let rows = conn.query("SELECT key, value FROM stats", &[]).await?;
+            Ok(rows
+                .into_iter()
+                .map(|row| {
+                    let value: Option<i64> = row.get("value");
+                    (row.get("key"), value.unwrap_or_default() as u64)
+                })
+                .collect())
+        })
+    }
+
+    fn finish_transfer(&self, id: i32, accepted: bool) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let mut conn = self.connection_pool.get().await?;
+            let transaction = conn.transaction().await?;
+            let row = transaction
+                .query_opt(
+                    "UPDATE payout SET status = $2 WHERE id = $1 AND status = 'pending' RETURNING address, amount",
+                    &[&id, &(if accepted { "done" } else { "failed" })],
+                )
+                .await?
+                .ok_or_else(|| anyhow!("Transfer is not pending"))?;
+            let address: String = row.get("address");
+            let amount: i64 = row.get("amount");
+            let stmt = if accepted {
+                "UPDATE balance SET pending = pending - $2, paid = paid + $2 WHERE address = $1"
+            } else {
+                "UPDATE balance SET pending = pending - $2, unpaid = unpaid + $2 WHERE address = $1"
+            };
+            transaction.execute(stmt, &[&address, &amount]).await?;
+            transaction.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn get_prover_settings<'a>(&'a self, address: &'a str) -> BoxFuture<'a, Result<Option<ProverSettings>>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached(
+                    "SELECT minimum_payout, payout_address, donation_bps, referrer, settings_timestamp FROM balance \
+                     WHERE address = $1 AND settings_timestamp IS NOT NULL",
+                )
+                .await?;
+            Ok(conn.query_opt(&stmt, &[&address]).await?.map(|row| {
+                let minimum_payout: Option<i64> = row.get("minimum_payout");
+                let donation_bps: i32 = row.get("donation_bps");
+                let timestamp: i64 = row.get("settings_timestamp");
+                ProverSettings {
+                    minimum_payout: minimum_payout.map(|minimum| minimum as u64),
+                    payout_address: row.get("payout_address"),
+                    donation_bps: donation_bps as u64,
+                    referrer: row.get("referrer"),
+                    timestamp: timestamp as u64,
+                }
+            }))
+        })
+    }
+
+    fn set_prover_settings<'a>(
+        &'a self,
+        address: &'a str,
+        settings: &'a ProverSettings,
+    ) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached(
+                    "INSERT INTO balance \
+                     (address, minimum_payout, payout_address, settings_timestamp, donation_bps, referrer) \
+                     VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (address) DO UPDATE SET \
+                     minimum_payout = $2, payout_address = $3, settings_timestamp = $4, donation_bps = $5, \
+                     referrer = COALESCE(balance.referrer, $6) \
+                     WHERE balance.settings_timestamp IS NULL OR balance.settings_timestamp < $4",
+                )
+                .await?;
+            let changed = conn
+                .execute(
+                    &stmt,
+                    &[
+                        &address,
+                        &settings.minimum_payout.map(|minimum| minimum as i64),
+                        &settings.payout_address,
+                        &(settings.timestamp as i64),
+                        &(settings.donation_bps as i32),
+                        &settings.referrer,
+                    ],
+                )
+                .await?;
+            Ok(changed > 0)
+        })
+    }
+
+    fn get_prover_options<'a>(
+        &'a self,
+        addresses: &'a [String],
+    ) -> BoxFuture<'a, Result<HashMap<String, ProverOptions>>> {
+        Box::pin(async move {
+            let conn = self.connection_pool.get().await?;
+            let stmt = conn
+                .prepare_cached("SELECT address, donation_bps, referrer FROM balance WHERE address = ANY($1)")
+                .await?;
+            let rows = conn.query(&stmt, &[&addresses]).await?;
+            Ok(rows
+                .into_iter()
+                .map(|row| {
+                    let donation_bps: i32 = row.get("donation_bps");
+                    let options = ProverOptions {
+                        donation_bps: donation_bps as u64,
+                        referrer: row.get("referrer"),
+                    };
+                    (row.get("address"), options)
+                })
+                .collect())
+        })
+    }
+}
@@ -0,0 +1,668 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension};
+use tokio::task;
+use tracing::info;
+
+use super::{
+    AddressBalance,
+    PayableBalance,
+    PayoutRecord,
+    PendingTransfer,
+    PoolStore,
+    ProverOptions,
+    ProverSettings,
+    SolutionRecord,
+    SolutionShare,
+    SolutionShares,
+    SolutionState,
+};
+use crate::accounting::{Distribution, Payout};
+
+/// Embedded schema migrations as (version, name, sql), applied in order by `migrate`.
+static MIGRATIONS: &[(i32, &str, &str)] = &[
+    (1, "initial", include_str!("../../migrations/sqlite/0001_initial.sql")),
+    (2, "solution_state", include_str!("../../migrations/sqlite/0002_solution_state.sql")),
+    (3, "transfers", include_str!("../../migrations/sqlite/0003_transfers.sql")),
+    (4, "prover_settings", include_str!("../../migrations/sqlite/0004_prover_settings.sql")),
+    (5, "fee_splits", include_str!("../../migrations/sqlite/0005_fee_splits.sql")),
+    (6, "share_deltas", include_str!("../../migrations/sqlite/0006_share_deltas.sql")),
+    (7, "unsigned_options", include_str!("../../migrations/sqlite/0007_unsigned_options.sql")),
+];
+
+/// Reads solution rows selected as (id, solution_id, state, height, reward, timestamp).
+fn read_solutions(
+    stmt: &mut rusqlite::CachedStatement<'_>,
+    params: &[&dyn rusqlite::ToSql],
+) -> Result<Vec<SolutionRecord>> {
+    let rows = stmt.query_map(params, |row| {
+        let reward: Option<i64> = row.get(4)?;
+        let timestamp: i64 = row.get(5)?;
+        Ok((
+            row.get::<_, i32>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, Option<u32>>(3)?,
+            reward.map(|reward| reward as u64),
+            timestamp as u64,
+        ))
+    })?;
+    rows.map(|row| {
+        let (id, solution_id, state, height, reward, timestamp) = row?;
+        Ok(SolutionRecord {
+            id,
+            solution_id,
+            state: state.parse()?,
+            height,
+            reward,
+            timestamp,
+        })
+    })
+    .collect()
+}
+
+fn latest_schema_version() -> i32 {
+    MIGRATIONS.last().map(|(version, _, _)| *version).unwrap_or_default()
+}
+
+fn schema_version(conn: &Connection) -> Result<i32> {
+    let exists = conn
+        .query_row(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'schema_version'",
+            [],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .is_some();
+    if !exists {
+        return Ok(0);
+    }
+    Ok(conn.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| {
+        row.get(0)
+    })?)
+}
+
+/// `state IN (...)` condition matching any of the states.
+fn state_condition(states: &[SolutionState]) -> String {
+    let names = states
+        .iter()
+        .map(|state| format!("'{}'", state.as_str()))
+        .collect::<Vec<_>>();
+    format!("state IN ({})", names.join(", "))
+}
+
+const CREDIT_BALANCE: &str =
+    "INSERT INTO balance (address, unpaid) VALUES (?1, ?2) ON CONFLICT (address) DO UPDATE SET unpaid = unpaid + ?2";
+const ADD_STAT: &str =
+    "INSERT INTO stats (key, value) VALUES (?1, ?2) ON CONFLICT (key) DO UPDATE SET value = value + ?2";
+
+/// Store in a single SQLite file, for pools that don't want to run PostgreSQL.
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> SqliteStore {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("Failed to create data directory");
+        }
+        let conn = Connection::open(path).expect("Failed to open SQLite database");
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .expect("Failed to enable SQLite WAL mode");
+        conn.pragma_update(None, "foreign_keys", true)
+            .expect("Failed to enable SQLite foreign keys");
+        info!("Opened SQLite database at {}", path.display());
+        SqliteStore {
+            conn: Arc::new(Mutex::new(conn)),
+        }
+    }
+
+    /// Runs a closure against the connection on the blocking thread pool.
+    fn run<T, F>(&self, f: F) -> BoxFuture<'static, Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        Box::pin(async move { task::spawn_blocking(move || f(&mut conn.lock())).await? })
+    }
+}
+
+impl PoolStore for SqliteStore {
+    fn check_schema(&self) -> BoxFuture<'_, Result<()>> {
+        self.run(|conn| {
+            let version = schema_version(conn)?;
+            let latest = latest_schema_version();
+            if version < latest {
+                return Err(anyhow!(
+                    "SQLite schema is at version {} but this build needs version {}, run the migrate command first",
+                    version,
+                    latest
+                ));
+            }
+            if version > latest {
+                return Err(anyhow!(
+                    "SQLite schema is at version {}, newer than version {} this build knows about",
+                    version,
+                    latest
+                ));
+            }
+            Ok(())
+        })
+    }
+
+    fn migrate(&self) -> BoxFuture<'_, Result<()>> {
+        self.run(|conn| {
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS schema_version (\
+                     version INTEGER PRIMARY KEY, \
+                     name TEXT NOT NULL, \
+                     applied_at INTEGER DEFAULT (strftime('%s', 'now')) NOT NULL\
+                 )",
+            )?;
+            let current = schema_version(conn)?;
+            for (version, name, sql) in MIGRATIONS {
+                if *version <= current {
+                    continue;
+                }
+                info!("Applying migration {} ({})", version, name);
+                let transaction = conn.transaction()?;
+                transaction.execute_batch(sql)?;
+                transaction.execute(
+                    "INSERT INTO schema_version (version, name) VALUES (?1, ?2)",
+                    params![version, name],
+                )?;
+                transaction.commit()?;
+            }
+            info!("SQLite schema is at version {}", latest_schema_version());
+            Ok(())
+        })
+    }
+
+    fn save_solution<'a>(&'a self, solution_id: &'a str, shares: &'a SolutionShares) -> BoxFuture<'a, Result<i32>> {
+        let solution_id = solution_id.to_string();
+        let shares = shares.clone();
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+            transaction.execute(
+                "INSERT INTO solution (solution_id, base_solution_id) VALUES (?1, ?2)",
+                params![solution_id, shares.base],
+            )?;
+            let id = transaction.last_insert_rowid();
+            {
+                let mut stmt =
+                    transaction.prepare_cached("INSERT INTO share (solution_id, address, share) VALUES (?1, ?2, ?3)")?;
+                for (address, share) in &shares.shares {
+                    stmt.execute(params![id, address, share])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(id as i32)
+        })
+    }
+
+    fn confirm_solution<'a>(&'a self, solution_id: &'a str, height: u32, reward: u64) -> BoxFuture<'a, Result<()>> {
+        let solution_id = solution_id.to_string();
+        self.run(move |conn| {
+            conn.execute(
+                &format!(
+                    "UPDATE solution SET state = 'confirmed', height = ?1, reward = ?2 WHERE solution_id = ?3 AND {}",
+                    state_condition(SolutionState::Confirmed.previous())
+                ),
+                params![height, reward as i64, solution_id],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn set_solution_state<'a>(&'a self, solution_id: &'a str, state: SolutionState) -> BoxFuture<'a, Result<bool>> {
+        let solution_id = solution_id.to_string();
+        self.run(move |conn| {
+            let moved = conn.execute(
+                &format!(
+                    "UPDATE solution SET state = ?1 WHERE solution_id = ?2 AND {}",
+                    state_condition(state.previous())
+                ),
+                params![state.as_str(), solution_id],
+            )?;
+            Ok(moved > 0)
+        })
+    }
+
+    fn get_unsettled_solutions(&self) -> BoxFuture<'_, Result<Vec<SolutionRecord>>> {
+        self.run(|conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT id, solution_id, state, height, reward, timestamp FROM solution \
+                 WHERE state IN ('pending', 'confirmed') ORDER BY id",
+            )?;
+            read_solutions(&mut stmt, params![])
+        })
+    }
+
+    fn get_solutions(&self, after: i32, limit: u32) -> BoxFuture<'_, Result<Vec<SolutionRecord>>> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT id, solution_id, state, height, reward, timestamp FROM solution \
+                 WHERE id > ?1 ORDER BY id LIMIT ?2",
+            )?;
+            read_solutions(&mut stmt, params![after, limit])
+        })
+    }
+
+    fn get_recent_solutions(&self, before: i32, limit: u32) -> BoxFuture<'_, Result<Vec<SolutionRecord>>> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT id, solution_id, state, height, reward, timestamp FROM solution \
+                 WHERE id < ?1 ORDER BY id DESC LIMIT ?2",
+            )?;
+            read_solutions(&mut stmt, params![before, limit])
+        })
+    }
+
+    fn get_solution_payouts(&self, solution_id: i32) -> BoxFuture<'_, Result<Vec<Payout>>> {
+        self.run(move |conn| {
+            let mut stmt =
+                conn.prepare_cached("SELECT address, amount, kind, origin FROM payout WHERE solution_id = ?1 ORDER BY id")?;
+            let rows = stmt.query_map(params![solution_id], |row| {
+                let amount: i64 = row.get(1)?;
+                Ok((
+                    row.get::<_, String>(0)?,
+                    amount as u64,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                ))
+            })?;
+            rows.map(|row| {
+                let (address, amount, kind, origin) = row?;
+                Ok(Payout {
+                    address,
+                    amount,
+                    kind: kind.parse()?,
+                    origin,
+                })
+            })
+            .collect()
+        })
+    }
+
+    fn get_solution_shares(&self, solution_id: i32) -> BoxFuture<'_, Result<HashMap<String, u64>>> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "WITH RECURSIVE chain (id, base_solution_id) AS ( \
+                     SELECT id, base_solution_id FROM solution WHERE id = ?1 \
+                     UNION ALL \
+                     SELECT solution.id, solution.base_solution_id FROM solution \
+                     JOIN chain ON solution.id = chain.base_solution_id \
+                 ) \
+                 SELECT address, SUM(share) FROM share \
+                 WHERE solution_id IN (SELECT id FROM chain) GROUP BY address HAVING SUM(share) > 0",
+            )?;
+            let rows = stmt.query_map(params![solution_id], |row| {
+                let share: i64 = row.get(1)?;
+                Ok((row.get(0)?, share as u64))
+            })?;
+            Ok(rows.collect::<rusqlite::Result<HashMap<_, _>>>()?)
+        })
+    }
+
+    fn pay_solution<'a>(
+        &'a self,
+        solution_id: i32,
+        distribution: &'a Distribution,
+    ) -> BoxFuture<'a, Result<()>> {
+        let fee = distribution.fee;
+        let payouts = distribution.payouts.clone();
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+            let state: String = transaction.query_row(
+                "SELECT state FROM solution WHERE id = ?1",
+                params![solution_id],
+                |row| row.get(0),
+            )?;
+            if state != SolutionState::Confirmed.as_str() {
+                return Err(anyhow!("Solution is {}, not confirmed", state));
+            }
+
+            let mut total_paid = 0u64;
+            {
+                let mut payout_stmt = transaction.prepare_cached(
+                    "INSERT INTO payout (solution_id, address, amount, kind, origin) VALUES (?1, ?2, ?3, ?4, ?5)",
+                )?;
+                let mut balance_stmt = transaction.prepare_cached(CREDIT_BALANCE)?;
+                for payout in &payouts {
+                    let amount = payout.amount as i64;
+                    payout_stmt.execute(params![
+                        solution_id,
+                        payout.address,
+                        amount,
+                        payout.kind.as_str(),
+                        payout.origin
+                    ])?;
+                    balance_stmt.execute(params![payout.address, amount])?;
+                    if !payout.kind.from_fee() {
+                        total_paid += payout.amount;
+                    }
+                }
+                let mut stats_stmt = transaction.prepare_cached(ADD_STAT)?;
+                stats_stmt.execute(params!["total_paid", total_paid as i64])?;
+                stats_stmt.execute(params!["total_fee", fee as i64])?;
+            }
+            transaction.execute(
+                "UPDATE solution SET paid = 1, state = 'mature' WHERE id = ?1",
+                params![solution_id],
+            )?;
+            transaction.commit()?;
+            Ok(())
+        })
+    }
+
+    fn close_solution(&self, solution_id: i32) -> BoxFuture<'_, Result<()>> {
+        self.run(move |conn| {
+            let closed = conn.execute(
+                "UPDATE solution SET paid = 1, state = 'mature' WHERE id = ?1 AND state = 'confirmed'",
+                params![solution_id],
+            )?;
+            if closed == 0 {
+                return Err(anyhow!("Solution is not confirmed"));
+            }
+            Ok(())
+        })
+    }
+
+    fn credit_balances<'a>(&'a self, credits: &'a HashMap<String, u64>) -> BoxFuture<'a, Result<()>> {
+        let credits = credits.clone();
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+            {
+                let mut stmt = transaction.prepare_cached(CREDIT_BALANCE)?;
+                for (address, amount) in &credits {
+                    stmt.execute(params![address, *amount as i64])?;
+                }
+            }
+            transaction.execute(ADD_STAT, params!["total_paid", credits.values().sum::<u64>() as i64])?;
+            transaction.commit()?;
+            Ok(())
+        })
+    }
+
+    fn get_payable_balances(&self, minimum: u64) -> BoxFuture<'_, Result<Vec<PayableBalance>>> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT address, unpaid, COALESCE(payout_address, address) FROM balance \
+                 WHERE unpaid > 0 AND unpaid >= MAX(COALESCE(minimum_payout, ?1), ?1) ORDER BY unpaid DESC",
+            )?;
+            let rows = stmt.query_map(params![minimum as i64], |row| {
+                let unpaid: i64 = row.get(1)?;
+                Ok(PayableBalance {
+                    address: row.get(0)?,
+                    unpaid: unpaid as u64,
+                    recipient: row.get(2)?,
+                })
+            })?;
+            Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+        })
+    }
+
+    fn start_transfer<'a>(
+        &'a self,
+        address: &'a str,
+        recipient: &'a str,
+        amount: u64,
+        transaction_id: &'a str,
+    ) -> BoxFuture<'a, Result<i32>> {
+        let address = address.to_string();
+        let recipient = recipient.to_string();
+        let transaction_id = transaction_id.to_string();
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+            let moved = transaction.execute(
+                "UPDATE balance SET unpaid = unpaid - ?2, pending = pending + ?2 WHERE address = ?1 AND unpaid >= ?2",
+                params![address, amount as i64],
+            )?;
+            if moved == 0 {
+                return Err(anyhow!("Balance of {} is lower than {}", address, amount));
+            }
+            transaction.execute(
+                "INSERT INTO payout (address, amount, transaction_id, kind, status, recipient) \
+                 VALUES (?1, ?2, ?3, 'transfer', 'pending', ?4)",
+                params![address, amount as i64, transaction_id, recipient],
+            )?;
+            let id = transaction.last_insert_rowid();
+            transaction.commit()?;
+            Ok(id as i32)
+        })
+    }
+
+    fn get_pending_transfers(&self) -> BoxFuture<'_, Result<Vec<PendingTransfer>>> {
+        self.run(|conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT id, address, amount, transaction_id, timestamp FROM payout \
+                 WHERE kind = 'transfer' AND status = 'pending' ORDER BY id",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                let amount: i64 = row.get(2)?;
+                let timestamp: Option<i64> = row.get(4)?;
+                Ok(PendingTransfer {
+                    id: row.get(0)?,
+                    address: row.get(1)?,
+                    amount: amount as u64,
+                    transaction_id: row.get(3)?,
+                    timestamp: timestamp.unwrap_or_default() as u64,
+                })
+            })?;
+            Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+        })
+    }
+
+    fn get_balance<'a>(&'a self, address: &'a str) -> BoxFuture<'a, Result<Option<AddressBalance>>> {
+        let address = address.to_string();
+        self.run(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT unpaid, pending, paid FROM balance WHERE address = ?1",
+                    params![address],
+                    |row| {
+                        let (unpaid, pending, paid): (i64, i64, i64) = (row.get(0)?, row.get(1)?, row.get(2)?);
+                        Ok(AddressBalance {
+                            unpaid: unpaid as u64,
+                            pending: pending as u64,
+                            paid: paid as u64,
+                        })
+                    },
+                )
+                .optional()?)
+        })
+    }
+
+    fn get_address_payouts<'a>(
+        &'a self,
+        address: &'a str,
+        before: i32,
+        limit: u32,
+    ) -> BoxFuture<'a, Result<Vec<PayoutRecord>>> {
+        let address = address.to_string();
+        self.run(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT payout.id, solution.solution_id, amount, kind, status, transaction_id, recipient, origin, \
+                 payout.timestamp FROM payout LEFT JOIN solution ON solution.id = payout.solution_id \
+                 WHERE address = ?1 AND payout.id < ?2 ORDER BY payout.id DESC LIMIT ?3",
+            )?;
+            let rows = stmt.query_map(params![address, before, limit], |row| {
+                let amount: i64 = row.get(2)?;
+                let timestamp: Option<i64> = row.get(8)?;
+                Ok(PayoutRecord {
+                    id: row.get(0)?,
+                    solution_id: row.get(1)?,
+                    amount: amount as u64,
+                    kind: row.get(3)?,
+                    status: row.get(4)?,
+                    transaction_id: row.get(5)?,
+                    recipient: row.get(6)?,
+                    origin: row.get(7)?,
+                    timestamp: timestamp.unwrap_or_default() as u64,
+                })
+            })?;
+            Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+        })
+    }
+
+    fn get_address_shares<'a>(
+        &'a self,
+        address: &'a str,
+        before: i32,
+        limit: u32,
+    ) -> BoxFuture<'a, Result<Vec<SolutionShare>>> {
+        let address = address.to_string();
+        self.run(move |conn| {
+            let solutions = {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT id, solution_id, state, height, reward, timestamp FROM solution \
+                     WHERE id < ?1 ORDER BY id DESC LIMIT ?2",
+                )?;
+                read_solutions(&mut stmt, params![before, limit])?
+            };
+            // The shares of the chain add up to the window, addresses that left it add up to 0
+            let mut stmt = conn.prepare_cached(
+                "WITH RECURSIVE chain (id, base_solution_id) AS ( \
+                     SELECT id, base_solution_id FROM solution WHERE id = ?1 \
+                     UNION ALL \
+                     SELECT solution.id, solution.base_solution_id FROM solution \
+                     JOIN chain ON solution.id = chain.base_solution_id \
+                 ) \
+                 SELECT COALESCE(SUM(share) FILTER (WHERE address = ?2), 0), COALESCE(SUM(share), 0) FROM share \
+                 WHERE solution_id IN (SELECT id FROM chain)",
+            )?;
+            solutions
+                .into_iter()
+                .map(|solution| {
+                    let (share, total): (i64, i64) =
+                        stmt.query_row(params![solution.id, address], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                    Ok(SolutionShare {
+                        solution,
+                        share: share.max(0) as u64,
+                        total: total.max(0) as u64,
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn get_stats(&self) -> BoxFuture<'_, Result<HashMap<String, u64>>> {
+        self.run(|conn| {
+            let mut stmt = conn.prepare_cached("SELECT key, value FROM stats")?;
+            let rows = stmt.query_map([], |row| {
+                let value: Option<i64> = row.get(1)?;
+                Ok((row.get(0)?, value.unwrap_or_default() as u64))
+            })?;
+            Ok(rows.collect::<rusqlite::Result<HashMap<_, _>>>()?)
+        })
+    }
+
+    fn finish_transfer(&self, id: i32, accepted: bool) -> BoxFuture<'_, Result<()>> {
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+            let (address, amount): (String, i64) = transaction
+                .query_row(
+                    "SELECT address, amount FROM payout WHERE id = ?1 AND status = 'pending'",
+                    params![id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?
+                .ok_or_else(|| anyhow!("Transfer is not pending"))?;
+            transaction.execute(
+                "UPDATE payout SET status = ?2 WHERE id = ?1",
+                params![id, if accepted { "done" } else { "failed" }],
+            )?;
+            let stmt = if accepted {
+                "UPDATE balance SET pending = pending - ?2, paid = paid + ?2 WHERE address = ?1"
+            } else {
+                "UPDATE balance SET pending = pending - ?2, unpaid = unpaid + ?2 WHERE address = ?1"
+            };
+            transaction.execute(stmt, params![address, amount])?;
+            transaction.commit()?;
+            Ok(())
+        })
+    }
+
+    fn get_prover_settings<'a>(&'a self, address: &'a str) -> BoxFuture<'a, Result<Option<ProverSettings>>> {
+        let address = address.to_string();
+        self.run(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT minimum_payout, payout_address, donation_bps, referrer, settings_timestamp FROM balance \
+                     WHERE address = ?1 AND settings_timestamp IS NOT NULL",
+                    params![address],
+                    |row| {
+                        let minimum_payout: Option<i64> = row.get(0)?;
+                        let donation_bps: i64 = row.get(2)?;
+                        let timestamp: i64 = row.get(4)?;
+                        Ok(ProverSettings {
+                            minimum_payout: minimum_payout.map(|minimum| minimum as u64),
+                            payout_address: row.get(1)?,
+                            donation_bps: donation_bps as u64,
+                            referrer: row.get(3)?,
+                            timestamp: timestamp as u64,
+                        })
+                    },
+                )
+                .optional()?)
+        })
+    }
+
+    fn set_prover_settings<'a>(
+        &'a self,
+        address: &'a str,
+        settings: &'a ProverSettings,
+    ) -> BoxFuture<'a, Result<bool>> {
+        let address = address.to_string();
+        let settings = settings.clone();
+        self.run(move |conn| {
+            let changed = conn.execute(
+                "INSERT INTO balance \
+                 (address, minimum_payout, payout_address, settings_timestamp, donation_bps, referrer) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6) ON CONFLICT (address) DO UPDATE SET \
+                 minimum_payout = ?2, payout_address = ?3, settings_timestamp = ?4, donation_bps = ?5, \
+                 referrer = COALESCE(referrer, ?6) \
+                 WHERE settings_timestamp IS NULL OR settings_timestamp < ?4",
+                params![
+                    address,
+                    settings.minimum_payout.map(|minimum| minimum as i64),
+                    settings.payout_address,
+                    settings.timestamp as i64,
+                    settings.donation_bps as i64,
+                    settings.referrer
+                ],
+            )?;
+            Ok(changed > 0)
+        })
+    }
+
+    fn get_prover_options<'a>(
+        &'a self,
+        addresses: &'a [String],
+    ) -> BoxFuture<'a, Result<HashMap<String, ProverOptions>>> {
+        let addresses = addresses.to_vec();
+        self.run(move |conn| {
+            let mut stmt = conn.prepare_cached("SELECT donation_bps, referrer FROM balance WHERE address = ?1")?;
+            let mut options = HashMap::new();
+            for address in addresses {
+                let row = stmt
+                    .query_row(params![address], |row| {
+                        let donation_bps: i64 = row.get(0)?;
+                        Ok(ProverOptions {
+                            donation_bps: donation_bps as u64,
+                            referrer: row.get(1)?,
+                        })
+                    })
+                    .optional()?;
+                if let Some(row) = row {
+                    options.insert(address, row);
+                }
+            }
+            Ok(options)
+        })
+    }
+}
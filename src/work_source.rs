@@ -0,0 +1,305 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use snarkvm::{
+    ledger::puzzle::{Solution, SolutionID},
+    prelude::{Block, Field, FromBytes, Network, Transaction},
+};
+use tokio::{
+    sync::{mpsc::Sender, Notify, RwLock},
+    task,
+    time::timeout,
+};
+use tracing::{error, info};
+
+use crate::ServerMessage;
+
+/// Something that provides epochs and proof targets to the pool server, and accepts the solutions
+/// that meet the network proof target.
+pub trait WorkSource<N: Network>: Send + Sync {
+    /// Starts feeding `NewEpochHash` messages to the pool server.
+    fn start(&self, server_sender: Sender<ServerMessage<N>>);
+
+    /// Hands a solution over to the upstream.
+    fn submit_solution(&self, solution: Solution<N>) -> BoxFuture<'_, Result<()>>;
+
+    /// Broadcasts a transaction to the network.
+    fn broadcast_transaction(&self, transaction: Transaction<N>) -> BoxFuture<'_, Result<()>>;
+
+    /// Asks for the current puzzle now rather than at the next poll.
+    fn request_puzzle(&self) -> BoxFuture<'_, Result<()>>;
+
+    /// Whether the upstream can take solutions right now.
+    fn connected(&self) -> bool {
+        true
+    }
+
+    /// Solutions and transactions held back until the upstream is connected.
+    fn pending_solutions(&self) -> BoxFuture<'_, usize> {
+        Box::pin(async { 0 })
+    }
+}
+
+/// Offline upstream for testing.
+///
+/// Epoch 0 uses the hash of the genesis block (the one passed with `--genesis-block`, or the network
+/// genesis block), and every following epoch hash is derived from it, so runs are reproducible.
+/// Proof targets are taken from the script in order, wrapping around when it runs out.
+/// A new epoch starts when the epoch interval elapses, a solution is submitted or a puzzle is
+/// requested.
+pub struct MockSource<N: Network> {
+    genesis_hash: <N as Network>::BlockHash,
+    proof_targets: Vec<u64>,
+    epoch_interval: Duration,
+    solutions: Arc<RwLock<Vec<SolutionID<N>>>>,
+    next_epoch: Arc<Notify>,
+}
+
+impl<N: Network> MockSource<N> {
+    pub fn new(genesis_path: Option<String>, proof_targets: Vec<u64>, epoch_interval: Duration) -> Result<Self> {
+        if proof_targets.is_empty() {
+            return Err(anyhow!("Mock upstream needs at least one proof target"));
+        }
+        let genesis_hash = match &genesis_path {
+            Some(path) => {
+                let bytes = std::fs::read(path).map_err(|e| anyhow!("Unable to read genesis block {}: {}", path, e))?;
+                Block::<N>::from_bytes_le(&bytes)
+                    .map_err(|e| anyhow!("Invalid genesis block {}: {}", path, e))?
+                    .hash()
+            }
+            None => Block::<N>::from_bytes_le(N::genesis_bytes())?.hash(),
+        };
+        Ok(Self {
+            genesis_hash,
+            proof_targets,
+            epoch_interval,
+            solutions: Default::default(),
+            next_epoch: Default::default(),
+        })
+    }
+
+    /// Solutions received so far, in submission order.
+    #[cfg(test)]
+    pub async fn solutions(&self) -> Vec<SolutionID<N>> {
+        self.solutions.read().await.clone()
+    }
+
+    fn epoch_hash(genesis_hash: <N as Network>::BlockHash, epoch_number: u32) -> Result<<N as Network>::BlockHash> {
+        if epoch_number == 0 {
+            return Ok(genesis_hash);
+        }
+        let hash = N::hash_psd2(&[*genesis_hash, Field::<N>::from_u32(epoch_number)])
+            .map_err(|e| anyhow!("Failed to derive epoch hash: {}", e))?;
+        Ok(hash.into())
+    }
+}
+
+impl<N: Network> WorkSource<N> for MockSource<N> {
+    fn start(&self, server_sender: Sender<ServerMessage<N>>) {
+        let genesis_hash = self.genesis_hash;
+        let proof_targets = self.proof_targets.clone();
+        let epoch_interval = self.epoch_interval;
+        let next_epoch = self.next_epoch.clone();
+        task::spawn(async move {
+            let mut epoch_number = 0u32;
+            loop {
+                let epoch_hash = match Self::epoch_hash(genesis_hash, epoch_number) {
+                    Ok(epoch_hash) => epoch_hash,
+                    Err(e) => {
+                        error!("{}", e);
+                        return;
+                    }
+                };
+                let proof_target = proof_targets[epoch_number as usize % proof_targets.len()];
+                let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+                info!("Mock upstream: epoch {} with proof target {}", epoch_number, proof_target);
+                if let Err(e) = server_sender
+                    .send(ServerMessage::NewEpochHash(epoch_hash, epoch_number, proof_target, timestamp))
+                    .await
+                {
+                    error!("Error sending new epoch hash to pool server: {}", e);
+                    return;
+                }
+                let _ = timeout(epoch_interval, next_epoch.notified()).await;
+                epoch_number += 1;
+            }
+        });
+    }
+
+    fn submit_solution(&self, solution: Solution<N>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            info!("Mock upstream: accepted solution {}", solution.id());
+            self.solutions.write().await.push(solution.id());
+            self.next_epoch.notify_one();
+            Ok(())
+        })
+    }
+
+    fn broadcast_transaction(&self, transaction: Transaction<N>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move { Err(anyhow!("Mock upstream can not broadcast transaction {}", transaction.id())) })
+    }
+
+    fn request_puzzle(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            info!("Mock upstream: puzzle requested, moving to the next epoch");
+            self.next_epoch.notify_one();
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener as StdTcpListener;
+
+    use aleo_stratum::{codec::StratumCodec, message::StratumMessage};
+    use futures_util::SinkExt;
+    use json_rpc_types::Id;
+    use snarkvm::console::{
+        account::{Address, PrivateKey},
+        network::MainnetV0,
+    };
+    use tokio::{net::TcpStream, time::sleep};
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::Framed;
+
+    use super::*;
+    use crate::{
+        accounting::Accounting,
+        events::Events,
+        payout_model::{FeeSettings, PayoutModelKind, PayoutSettings},
+        server::Server,
+        store::{self, StoreKind},
+    };
+
+    type N = MainnetV0;
+
+    static STEP_TIMEOUT: Duration = Duration::from_secs(120);
+
+    fn free_port() -> u16 {
+        StdTcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    async fn next_message(framed: &mut Framed<TcpStream, StratumCodec>) -> StratumMessage {
+        timeout(STEP_TIMEOUT, framed.next())
+            .await
+            .expect("Timed out waiting for the pool")
+            .expect("Pool closed the connection")
+            .expect("Invalid message from the pool")
+    }
+
+    #[test]
+    fn empty_proof_targets_are_refused() {
+        assert!(MockSource::<N>::new(None, vec![], Duration::from_secs(60)).is_err());
+    }
+
+    /// A prover connects, gets a job and submits a share meeting the network proof target, which
+    /// reaches the mock upstream and is recorded as a solution.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn offline_round() {
+        let data_dir = std::env::temp_dir().join(format!("aleo-pool-mock-round-{}", std::process::id()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let rng = &mut rand::thread_rng();
+        let pool_address = Address::try_from(PrivateKey::<N>::new(rng).unwrap()).unwrap();
+        let prover_address = Address::try_from(PrivateKey::<N>::new(rng).unwrap()).unwrap();
+
+        let store = store::open(StoreKind::Memory, &data_dir);
+        #[cfg(feature = "storage")]
+        let storage = Arc::new(crate::state_storage::Storage::load(&data_dir));
+        let payout_settings = PayoutSettings {
+            kind: PayoutModelKind::Pplns,
+            expected_reward: None,
+            n_multiplier: 5,
+            pplnt_window: Duration::from_secs(60 * 60),
+            fees: FeeSettings::default(),
+            maturity_blocks: 10,
+        };
+        let accounting = Accounting::init(
+            None,
+            data_dir.clone(),
+            payout_settings,
+            store.clone(),
+            #[cfg(feature = "storage")]
+            storage.clone(),
+        );
+
+        // Every share meets a proof target of 1
+        let mock = Arc::new(MockSource::<N>::new(None, vec![1], Duration::from_secs(60 * 60)).unwrap());
+        let port = free_port();
+        let server = Server::init(
+            port,
+            pool_address,
+            mock.clone(),
+            accounting.sender(),
+            Events::new(),
+            #[cfg(feature = "storage")]
+            storage,
+        )
+        .await;
+        mock.start(server.sender());
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut framed = Framed::new(stream, StratumCodec::default());
+        framed
+            .send(StratumMessage::Subscribe(
+                Id::Num(1),
+                "test".to_string(),
+                "AleoStratum/3.0.0".to_string(),
+                None,
+            ))
+            .await
+            .unwrap();
+        assert!(matches!(next_message(&mut framed).await, StratumMessage::Response(Id::Num(1), Some(_), None)));
+        framed
+            .send(StratumMessage::Authorize(
+                Id::Num(2),
+                prover_address.to_string(),
+                String::new(),
+            ))
+            .await
+            .unwrap();
+        assert!(matches!(next_message(&mut framed).await, StratumMessage::Response(Id::Num(2), Some(_), None)));
+
+        let job_id = loop {
+            if let StratumMessage::Notify(job_id, ..) = next_message(&mut framed).await {
+                break job_id;
+            }
+        };
+        framed
+            .send(StratumMessage::Submit(
+                Id::Num(3),
+                "worker".to_string(),
+                job_id,
+                "1".to_string(),
+            ))
+            .await
+            .unwrap();
+        let accepted = loop {
+            if let StratumMessage::Response(Id::Num(3), result, error) = next_message(&mut framed).await {
+                break result.is_some() && error.is_none();
+            }
+        };
+        assert!(accepted, "share was rejected");
+
+        let recorded = timeout(STEP_TIMEOUT, async {
+            loop {
+                let solutions = store.get_recent_solutions(i32::MAX, 10).await.unwrap();
+                if !solutions.is_empty() {
+                    return solutions;
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .expect("Solution was not recorded");
+        let submitted = mock.solutions().await;
+        assert_eq!(submitted.len(), 1);
+        assert_eq!(recorded[0].solution_id, submitted[0].to_string());
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+}